@@ -0,0 +1,248 @@
+// 循环密集的程序，用于比较不同的指令分派方式，以及栈式和寄存器两个后端：
+// cargo bench --bench dispatch
+// 加上--features dispatch-table时比较原来查HashMap分派和match分派，
+// 加上--features jit时还比较全部解释执行和把热点函数编译为机器码
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use svm::assembler::assemble;
+use svm::compiler::compile;
+use svm::program::Program;
+#[cfg(feature = "dispatch-table")]
+use svm::value::Value;
+use svm::vm::{Backend, Vm};
+use svm::vmio::BufferIo;
+
+// 递归计算fib(n)，n*(n-1)为0时即n<2
+const FIB: &str = "
+    push 20
+    call fib, 1
+    jmp end
+fib:
+    loadlocal 0
+    loadlocal 0
+    push 1
+    sub
+    mul
+    push rec
+    push base
+    if
+    jmp
+rec:
+    loadlocal 0
+    push 1
+    sub
+    call fib, 1
+    loadlocal 0
+    push 2
+    sub
+    call fib, 1
+    add
+    return
+base:
+    loadlocal 0
+    return
+end:
+    push 0
+    exit
+";
+
+// 埃氏筛法统计小于N的素数个数，局部变量0..N作为标记数组
+#[cfg(feature = "dispatch-table")]
+fn sieve(n: usize) -> String {
+    let limit = (1..).take_while(|i| i * i < n).last().unwrap();
+    let (i, r, j, k, c) = (n, n + 1, n + 2, n + 3, n + 4);
+    let mut source = "push 0\n".repeat(n + 5);
+    source += &format!("
+        push 2
+        storelocal {i}
+        push {outer}
+        storelocal {r}
+    outer:
+        loadlocal {i}
+        loadlocal
+        push skip
+        push mark
+        if
+        jmp
+    mark:
+        loadlocal {i}
+        loadlocal {i}
+        mul
+        storelocal {j}
+        push {last}
+        loadlocal {j}
+        sub
+        loadlocal {i}
+        div
+        push 1
+        add
+        storelocal {k}
+    inner:
+        push 1
+        loadlocal {j}
+        storelocal
+        loadlocal {j}
+        loadlocal {i}
+        add
+        storelocal {j}
+        loadlocal {k}
+        push 1
+        sub
+        storelocal {k}
+        loadlocal {k}
+        push inner
+        push skip
+        if
+        jmp
+    skip:
+        loadlocal {i}
+        push 1
+        add
+        storelocal {i}
+        loadlocal {r}
+        push 1
+        sub
+        storelocal {r}
+        loadlocal {r}
+        push outer
+        push count
+        if
+        jmp
+    count:
+        push 2
+        storelocal {i}
+        push {candidates}
+        storelocal {r}
+    cloop:
+        loadlocal {i}
+        loadlocal
+        push 0
+        push 1
+        if
+        loadlocal {c}
+        add
+        storelocal {c}
+        loadlocal {i}
+        push 1
+        add
+        storelocal {i}
+        loadlocal {r}
+        push 1
+        sub
+        storelocal {r}
+        loadlocal {r}
+        push cloop
+        push done
+        if
+        jmp
+    done:
+        loadlocal {c}
+    ",
+        i = i, r = r, j = j, k = k, c = c,
+        outer = limit - 1, last = n - 1, candidates = n - 2);
+    source
+}
+
+// 用数组做标记的埃氏筛法脚本
+const SIEVE_SCRIPT: &str = "
+    let n = 10000;
+    let marks = array(n);
+    let i = 2;
+    while i * i < n {
+        if !marks[i] {
+            let j = i * i;
+            while j < n { marks[j] = true; j = j + i; }
+        }
+        i = i + 1;
+    }
+    let count = 0;
+    i = 2;
+    while i < n {
+        if !marks[i] { count = count + 1; }
+        i = i + 1;
+    }
+    print(count);
+";
+
+// 递归的整数函数，jit可以编译
+#[cfg(feature = "jit")]
+const FIB_SCRIPT: &str = "
+    fn fib(n) {
+        if n < 2 { return n; }
+        return fib(n - 1) + fib(n - 2);
+    }
+    print(fib(25));
+";
+
+#[cfg(feature = "dispatch-table")]
+fn vm_with(source: &str) -> Vm {
+    let mut vm = Vm::new();
+    vm.import_program(&assemble(source).unwrap());
+    vm
+}
+
+fn bench(c: &mut Criterion) {
+    #[cfg(feature = "dispatch-table")]
+    {
+        dispatch(c, "fib 20", FIB, &Value::Int(6765));
+        dispatch(c, "sieve 10000", &sieve(10000), &Value::Int(1229));
+    }
+
+    backends(c, "fib 20", &assemble(FIB).unwrap(), "");
+    backends(c, "sieve script 10000", &compile(SIEVE_SCRIPT).unwrap(), "1229\n");
+
+    #[cfg(feature = "jit")]
+    {
+        jit(c, "fib 20", &assemble(FIB).unwrap(), "");
+        jit(c, "fib script 25", &compile(FIB_SCRIPT).unwrap(), "75025\n");
+    }
+}
+
+// 同一程序分别用原来的dispatch_table和match分派执行，先检查结果一致
+#[cfg(feature = "dispatch-table")]
+fn dispatch(c: &mut Criterion, name: &str, source: &str, expected: &Value) {
+    let mut vm = vm_with(source);
+    #[cfg(feature = "jit")]
+    vm.set_jit_threshold(None);
+    vm.run_dispatch_table().unwrap();
+    assert_eq!(vm.stack().last(), Some(expected));
+    vm.run().unwrap();
+    assert_eq!(vm.stack().last(), Some(expected));
+    c.bench_function(&format!("{} (dispatch_table)", name), |b| b.iter(|| vm.run_dispatch_table().unwrap()));
+    c.bench_function(&format!("{} (match)", name), |b| b.iter(|| vm.run().unwrap()));
+}
+
+// 同一程序全部解释执行和使用jit，Vm在多次运行之间保留编译好的机器码
+#[cfg(feature = "jit")]
+fn jit(c: &mut Criterion, name: &str, program: &Program, expected: &str) {
+    for (threshold, label) in [(None, "interpreted"), (Some(svm::jit::DEFAULT_THRESHOLD), "jit")] {
+        let mut vm = Vm::new();
+        vm.import_program(program);
+        vm.set_jit_threshold(threshold);
+        let io = BufferIo::new("");
+        vm.set_io(io.clone());
+        vm.run().unwrap();
+        assert_eq!(io.take_output(), expected);
+        c.bench_function(&format!("{} ({})", name, label), |b| b.iter(|| vm.run().unwrap()));
+    }
+}
+
+// 同一程序分别在两个后端上运行，先检查输出一致并打印执行的指令条数
+fn backends(c: &mut Criterion, name: &str, program: &Program, expected: &str) {
+    for (backend, label) in [(Backend::Stack, "stack"), (Backend::Register, "register")] {
+        let mut vm = Vm::new();
+        vm.import_program(program);
+        vm.set_backend(backend);
+        #[cfg(feature = "jit")]
+        vm.set_jit_threshold(None);
+        let io = BufferIo::new("");
+        vm.set_io(io.clone());
+        vm.run().unwrap();
+        assert_eq!(io.take_output(), expected);
+        println!("{} ({}): {} instructions", name, label, vm.fuel_used());
+        c.bench_function(&format!("{} ({})", name, label), |b| b.iter(|| vm.run().unwrap()));
+    }
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
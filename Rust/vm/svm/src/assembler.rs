@@ -0,0 +1,361 @@
+// 汇编器，将可读的汇编文本翻译为Vm::import_program能执行的程序。
+//
+// 语法示例：
+// ```
+// loop:           ; 标号
+//     push 1      ; 压入整数
+//     push 2.5    ; 浮点数、字符串、true/false/nil放入常量池，用pushconst压入
+//     push "hi"
+//     print
+//     jmp loop    ; 等价于 push loop; jmp
+// ```
+// `call f, 2`等价于`push 2; push f; call`，`loadlocal 0`等价于`push 0; loadlocal`，
+// `callnative sqrt, 1`等价于`push 1; callnative "sqrt"`。
+// 条件跳转的目标是立即数：`jmpifzero done`和`jmpifzero 12`为绝对地址，`jmpifzero -7`、`jmpifzero +3`
+// 这样带符号的整数为相对该指令地址的偏移。
+// 注释以`;`或`#`开头，直到行尾。
+
+use std::collections::HashMap;
+use std::fmt;
+use crate::instruction::{Instruction, OpCode, Operand, OperandKind, Target};
+use crate::program::Program;
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    InvalidOperand(String),
+    MissingOperand,
+    UnexpectedOperand,
+    UnterminatedString,
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(s) => write!(f, "unknown mnemonic `{}`", s),
+            AsmErrorKind::InvalidOperand(s) => write!(f, "invalid operand `{}`", s),
+            AsmErrorKind::MissingOperand => write!(f, "missing operand"),
+            AsmErrorKind::UnexpectedOperand => write!(f, "unexpected operand"),
+            AsmErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AsmErrorKind::UndefinedLabel(s) => write!(f, "undefined label `{}`", s),
+            AsmErrorKind::DuplicateLabel(s) => write!(f, "duplicate label `{}`", s),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// 带位置信息的单词，行号与列号都从1开始
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error(&self, kind: AsmErrorKind) -> AsmError {
+        AsmError { line: self.line, column: self.column, kind }
+    }
+}
+
+enum AsmOperand<'a> {
+    None,
+    Int(i64),
+    Label(Token<'a>),
+    Const(Value),
+    Target(Target),
+}
+
+// 第一遍扫描得到的指令，标号地址在布局完成后回填
+struct Item<'a> {
+    opcode: OpCode,
+    operand: AsmOperand<'a>,
+    // 所在的源码行，写入调试信息
+    line: usize,
+}
+
+impl<'a> Item<'a> {
+    fn op(opcode: OpCode) -> Self {
+        Item { opcode, operand: AsmOperand::None, line: 0 }
+    }
+
+    // 整数与标号用push的立即数，其余常量用pushconst
+    fn push(operand: AsmOperand<'a>) -> Self {
+        match operand {
+            AsmOperand::Const(_) => Item { opcode: OpCode::PushConst, operand, line: 0 },
+            _ => Item { opcode: OpCode::Push, operand, line: 0 },
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut items = Vec::new();
+    // 标号名 -> 标号后第一条指令在items中的位置
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let mut tokens = tokenize(line, i + 1)?;
+
+        // 行首的标号，形如`name:`
+        while let Some(first) = tokens.first() {
+            if let Some(name) = first.text.strip_suffix(':') {
+                if !is_identifier(name) {
+                    return Err(first.error(AsmErrorKind::InvalidOperand(first.text.to_string())));
+                }
+                if labels.insert(name, items.len()).is_some() {
+                    return Err(first.error(AsmErrorKind::DuplicateLabel(name.to_string())));
+                }
+                tokens.remove(0);
+            } else {
+                break;
+            }
+        }
+
+        let Some((mnemonic, operands)) = tokens.split_first() else {
+            continue;
+        };
+        items.extend(parse_instruction(mnemonic, operands)?.into_iter().map(|item| Item { line: i + 1, ..item }));
+    }
+
+    // 先分配常量下标，剩下的标号操作数需要等布局完成后回填
+    let mut program = Program::default();
+    let mut operands = Vec::with_capacity(items.len());
+    for item in &items {
+        operands.push(match &item.operand {
+            AsmOperand::None => Ok(Operand::None),
+            AsmOperand::Int(v) => Ok(Operand::Int(*v)),
+            AsmOperand::Const(value) => Ok(Operand::Const(program.add_constant(value.clone()))),
+            AsmOperand::Target(target) => Ok(Operand::Target(*target)),
+            AsmOperand::Label(token) => match labels.get(token.text) {
+                Some(&index) => Err(index),
+                None => return Err(token.error(AsmErrorKind::UndefinedLabel(token.text.to_string()))),
+            },
+        });
+    }
+    let resolve = |opcode: OpCode, operand: &Result<Operand, usize>, addrs: &[usize]| match operand {
+        Ok(operand) => *operand,
+        Err(index) if opcode.operand_kind() == OperandKind::Target => Operand::Target(Target::Absolute(addrs[*index] as i64)),
+        Err(index) => Operand::Int(addrs[*index] as i64),
+    };
+
+    // 标号地址的编码长度取决于地址本身，反复布局直到各指令地址不再变化
+    let mut addrs = vec![0usize; items.len() + 1];
+    loop {
+        let mut next = Vec::with_capacity(items.len() + 1);
+        let mut addr = 0;
+        for (item, operand) in items.iter().zip(&operands) {
+            next.push(addr);
+            addr += Instruction::new(item.opcode, resolve(item.opcode, operand, &addrs)).len;
+        }
+        next.push(addr);
+        if next == addrs {
+            break;
+        }
+        addrs = next;
+    }
+
+    for (i, (item, operand)) in items.iter().zip(&operands).enumerate() {
+        program.debug.add_line(addrs[i], item.line);
+        Instruction::new(item.opcode, resolve(item.opcode, operand, &addrs)).encode(&mut program.codes);
+    }
+    let mut labels: Vec<(String, usize)> = labels.into_iter().map(|(name, i)| (name.to_string(), addrs[i])).collect();
+    labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    program.debug.labels = labels;
+    Ok(program)
+}
+
+fn parse_instruction<'a>(mnemonic: &Token<'a>, operands: &[Token<'a>]) -> Result<Vec<Item<'a>>, AsmError> {
+    let op = OpCode::from_mnemonic(mnemonic.text)
+        .ok_or_else(|| mnemonic.error(AsmErrorKind::UnknownMnemonic(mnemonic.text.to_string())))?;
+    match (op, operands) {
+        (OpCode::Push, []) | (OpCode::PushConst, []) | (OpCode::CallNative, []) | (OpCode::JmpIfZero, []) | (OpCode::JmpIfNonZero, []) => {
+            Err(mnemonic.error(AsmErrorKind::MissingOperand))
+        }
+        (OpCode::JmpIfZero, [target]) | (OpCode::JmpIfNonZero, [target]) => {
+            Ok(vec![Item { opcode: op, operand: parse_target(target)?, line: 0 }])
+        }
+        (OpCode::Push, [operand]) => Ok(vec![Item::push(parse_operand(operand)?)]),
+        // pushconst总是放入常量池
+        (OpCode::PushConst, [operand]) => {
+            let value = match parse_operand(operand)? {
+                AsmOperand::Int(v) => Value::Int(v),
+                AsmOperand::Const(value) => value,
+                _ => return Err(operand.error(AsmErrorKind::InvalidOperand(operand.text.to_string()))),
+            };
+            Ok(vec![Item::push(AsmOperand::Const(value))])
+        }
+        (_, []) => Ok(vec![Item::op(op)]),
+        // `jmp label`、`loadlocal 0`等是先压入操作数再执行指令的简写
+        (OpCode::Jmp, [operand]) | (OpCode::LoadLocal, [operand]) | (OpCode::StoreLocal, [operand]) => {
+            Ok(vec![Item::push(parse_int_operand(operand)?), Item::op(op)])
+        }
+        // `call label, argc`，省略参数个数时为0
+        (OpCode::Call, [target]) => {
+            Ok(vec![Item::push(AsmOperand::Int(0)), Item::push(parse_int_operand(target)?), Item::op(op)])
+        }
+        (OpCode::Call, [target, argc]) => {
+            Ok(vec![Item::push(parse_int_operand(argc)?), Item::push(parse_int_operand(target)?), Item::op(op)])
+        }
+        // `callnative name, argc`，函数名可以是标识符或字符串，省略参数个数时为0
+        (OpCode::CallNative, [name]) => Ok(vec![Item::push(AsmOperand::Int(0)), native(name)?]),
+        (OpCode::CallNative, [name, argc]) => Ok(vec![Item::push(parse_int_operand(argc)?), native(name)?]),
+        (OpCode::Call, [_, _, extra, ..]) | (OpCode::CallNative, [_, _, extra, ..]) | (_, [_, extra, ..]) | (_, [extra]) => {
+            Err(extra.error(AsmErrorKind::UnexpectedOperand))
+        }
+    }
+}
+
+fn native<'a>(name: &Token<'a>) -> Result<Item<'a>, AsmError> {
+    let name = match parse_operand(name)? {
+        AsmOperand::Label(token) => token.text.to_string(),
+        AsmOperand::Const(Value::Str(s)) => s,
+        _ => return Err(name.error(AsmErrorKind::InvalidOperand(name.text.to_string()))),
+    };
+    Ok(Item { opcode: OpCode::CallNative, operand: AsmOperand::Const(Value::Str(name)), line: 0 })
+}
+
+// 带符号的整数为相对偏移，其余整数和标号为绝对地址
+fn parse_target<'a>(token: &Token<'a>) -> Result<AsmOperand<'a>, AsmError> {
+    match parse_int_operand(token)? {
+        AsmOperand::Int(v) if token.text.starts_with(['+', '-']) => Ok(AsmOperand::Target(Target::Relative(v))),
+        AsmOperand::Int(v) => Ok(AsmOperand::Target(Target::Absolute(v))),
+        operand => Ok(operand),
+    }
+}
+
+// 简写指令的操作数只能是整数或标号
+fn parse_int_operand<'a>(token: &Token<'a>) -> Result<AsmOperand<'a>, AsmError> {
+    match parse_operand(token)? {
+        AsmOperand::Const(_) => Err(token.error(AsmErrorKind::InvalidOperand(token.text.to_string()))),
+        operand => Ok(operand),
+    }
+}
+
+fn parse_operand<'a>(token: &Token<'a>) -> Result<AsmOperand<'a>, AsmError> {
+    let invalid = || token.error(AsmErrorKind::InvalidOperand(token.text.to_string()));
+    match token.text {
+        "true" => return Ok(AsmOperand::Const(Value::Bool(true))),
+        "false" => return Ok(AsmOperand::Const(Value::Bool(false))),
+        "nil" => return Ok(AsmOperand::Const(Value::Nil)),
+        _ => {}
+    }
+    if token.text.starts_with('"') {
+        return Ok(AsmOperand::Const(Value::Str(unescape(token.text).ok_or_else(invalid)?)));
+    }
+    if is_identifier(token.text) {
+        return Ok(AsmOperand::Label(*token));
+    }
+    if let Ok(v) = token.text.parse::<i64>() {
+        return Ok(AsmOperand::Int(v));
+    }
+    token.text.parse::<f64>().map(|f| AsmOperand::Const(Value::Float(f))).map_err(|_| invalid())
+}
+
+// 去掉字符串两边的引号并处理转义字符
+pub fn unescape(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+// 将字符串转换为汇编中的字符串字面量
+pub fn escape(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn tokenize(line: &str, line_no: usize) -> Result<Vec<Token<'_>>, AsmError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().enumerate().peekable();
+    while let Some(&(column, (offset, c))) = chars.peek() {
+        let column = column + 1;
+        if c == ';' || c == '#' {
+            break;
+        }
+        if c.is_whitespace() || c == ',' {
+            chars.next();
+            continue;
+        }
+
+        let mut end = line.len();
+        if c == '"' {
+            // 字符串字面量，其中可以有空格、逗号和注释符号
+            chars.next();
+            let mut escaped = false;
+            let mut closed = false;
+            for (_, (i, c)) in chars.by_ref() {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    end = i + 1;
+                    closed = true;
+                    break;
+                }
+            }
+            if !closed {
+                return Err(AsmError { line: line_no, column, kind: AsmErrorKind::UnterminatedString });
+            }
+        } else {
+            while let Some(&(_, (i, c))) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ';' || c == '#' {
+                    end = i;
+                    break;
+                }
+                chars.next();
+            }
+        }
+        tokens.push(Token { text: &line[offset..end], line: line_no, column });
+    }
+    Ok(tokens)
+}
@@ -0,0 +1,311 @@
+// 字节码文件格式（.svmb），所有定长整数都是小端序：
+//
+// ```
+// magic         4字节  b"SVMB"
+// version       u16    当前为1
+// section_count u16
+// section*      kind(u8) + length(u32) + 内容
+// checksum      u32    之前所有字节的CRC-32
+// ```
+//
+// 段的种类见SectionKind，每种段最多出现一次，内容必须正好用完段的长度；不认识的段直接跳过，以便以后扩展。
+// 段内的变长整数都使用LEB128编码。
+
+use std::fmt;
+use std::path::Path;
+use crate::leb128;
+use crate::program::{DebugInfo, Program};
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"SVMB";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code = 1,
+    Constants = 2,
+    Debug = 3,
+}
+
+// 常量池中每个常量前的类型标记
+const TAG_NIL: u8 = 0;
+const TAG_BOOL: u8 = 1;
+const TAG_INT: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+const TAG_STR: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    Io(String),
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch { expected: u32, found: u32 },
+    Truncated,
+    Malformed(&'static str),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(message) => write!(f, "io error: {}", message),
+            FormatError::BadMagic => write!(f, "not a svm bytecode file"),
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported bytecode version {}", v),
+            FormatError::ChecksumMismatch { expected, found } => {
+                write!(f, "checksum mismatch: expected {:08x}, found {:08x}", expected, found)
+            }
+            FormatError::Truncated => write!(f, "unexpected end of file"),
+            FormatError::Malformed(what) => write!(f, "malformed {}", what),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<std::io::Error> for FormatError {
+    fn from(e: std::io::Error) -> Self {
+        FormatError::Io(e.to_string())
+    }
+}
+
+pub fn is_bytecode_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn serialize(program: &Program) -> Vec<u8> {
+    let mut constants = Vec::new();
+    leb128::write_unsigned(&mut constants, program.constants.len() as u64);
+    for value in &program.constants {
+        write_value(&mut constants, value);
+    }
+
+    let mut debug = Vec::new();
+    write_debug(&mut debug, &program.debug);
+
+    let sections = [
+        (SectionKind::Code, &program.codes),
+        (SectionKind::Constants, &constants),
+        (SectionKind::Debug, &debug),
+    ];
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    for (kind, data) in sections.iter() {
+        out.push(*kind as u8);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Program, FormatError> {
+    if !is_bytecode_file(bytes) {
+        return Err(FormatError::BadMagic);
+    }
+    if bytes.len() < MAGIC.len() + 8 {
+        return Err(FormatError::Truncated);
+    }
+    let (body, tail) = bytes.split_at(bytes.len() - 4);
+    let expected = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]);
+    let found = crc32(body);
+    if expected != found {
+        return Err(FormatError::ChecksumMismatch { expected, found });
+    }
+
+    let mut reader = Reader { bytes: body, pos: MAGIC.len() };
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    let count = reader.u16()?;
+
+    let mut program = Program::default();
+    // 已经读到的段，同一种段只能出现一次
+    let mut seen = Vec::new();
+    for _ in 0..count {
+        let kind = reader.u8()?;
+        let len = reader.u32()? as usize;
+        let data = reader.take(len)?;
+        let mut section = Reader { bytes: data, pos: 0 };
+        let known = [SectionKind::Code, SectionKind::Constants, SectionKind::Debug];
+        let Some(&kind) = known.iter().find(|&&k| k as u8 == kind) else {
+            continue;
+        };
+        if seen.contains(&kind) {
+            return Err(FormatError::Malformed("duplicate section"));
+        }
+        seen.push(kind);
+        match kind {
+            SectionKind::Code => {
+                program.codes = data.to_vec();
+                section.pos = data.len();
+            }
+            SectionKind::Constants => {
+                let n = section.uleb()?;
+                for _ in 0..n {
+                    program.constants.push(section.value()?);
+                }
+            }
+            SectionKind::Debug => program.debug = section.debug()?,
+        }
+        if section.pos != data.len() {
+            return Err(FormatError::Malformed("section with trailing data"));
+        }
+    }
+    if reader.pos != body.len() {
+        return Err(FormatError::Malformed("trailing data"));
+    }
+    if !seen.contains(&SectionKind::Code) {
+        return Err(FormatError::Malformed("file without code section"));
+    }
+    Ok(program)
+}
+
+pub fn save(program: &Program, path: impl AsRef<Path>) -> Result<(), FormatError> {
+    std::fs::write(path, serialize(program))?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Program, FormatError> {
+    deserialize(&std::fs::read(path)?)
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    leb128::write_unsigned(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => out.push(TAG_NIL),
+        Value::Bool(b) => {
+            out.push(TAG_BOOL);
+            out.push(*b as u8);
+        }
+        Value::Int(i) => {
+            out.push(TAG_INT);
+            leb128::write_signed(out, *i);
+        }
+        Value::Float(f) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&f.to_le_bytes());
+        }
+        Value::Str(s) => {
+            out.push(TAG_STR);
+            write_str(out, s);
+        }
+        // 堆对象的句柄只在运行时存在，常量池中不会出现
+        Value::Obj(_) => unreachable!("heap object in constant pool"),
+    }
+}
+
+fn write_debug(out: &mut Vec<u8>, debug: &DebugInfo) {
+    write_str(out, debug.source.as_deref().unwrap_or(""));
+    leb128::write_unsigned(out, debug.lines.len() as u64);
+    for &(addr, line) in &debug.lines {
+        leb128::write_unsigned(out, addr as u64);
+        leb128::write_unsigned(out, line as u64);
+    }
+    leb128::write_unsigned(out, debug.labels.len() as u64);
+    for (name, addr) in &debug.labels {
+        write_str(out, name);
+        leb128::write_unsigned(out, *addr as u64);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        let end = self.pos.checked_add(n).ok_or(FormatError::Truncated)?;
+        let data = self.bytes.get(self.pos..end).ok_or(FormatError::Truncated)?;
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn uleb(&mut self) -> Result<u64, FormatError> {
+        let (v, len) = leb128::read_unsigned(self.bytes, self.pos).ok_or(FormatError::Truncated)?;
+        self.pos += len;
+        Ok(v)
+    }
+
+    fn sleb(&mut self) -> Result<i64, FormatError> {
+        let (v, len) = leb128::read_signed(self.bytes, self.pos).ok_or(FormatError::Truncated)?;
+        self.pos += len;
+        Ok(v)
+    }
+
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.uleb()? as usize;
+        let data = self.take(len)?;
+        String::from_utf8(data.to_vec()).map_err(|_| FormatError::Malformed("string"))
+    }
+
+    fn value(&mut self) -> Result<Value, FormatError> {
+        Ok(match self.u8()? {
+            TAG_NIL => Value::Nil,
+            TAG_BOOL => Value::Bool(self.u8()? != 0),
+            TAG_INT => Value::Int(self.sleb()?),
+            TAG_FLOAT => {
+                let b = self.take(8)?;
+                let mut bits = [0u8; 8];
+                bits.copy_from_slice(b);
+                Value::Float(f64::from_le_bytes(bits))
+            }
+            TAG_STR => Value::Str(self.string()?),
+            _ => return Err(FormatError::Malformed("constant")),
+        })
+    }
+
+    fn debug(&mut self) -> Result<DebugInfo, FormatError> {
+        let source = self.string()?;
+        let mut debug = DebugInfo {
+            source: if source.is_empty() { None } else { Some(source) },
+            ..DebugInfo::default()
+        };
+        for _ in 0..self.uleb()? {
+            let addr = self.uleb()? as usize;
+            let line = self.uleb()? as usize;
+            debug.lines.push((addr, line));
+        }
+        for _ in 0..self.uleb()? {
+            let name = self.string()?;
+            let addr = self.uleb()? as usize;
+            debug.labels.push((name, addr));
+        }
+        Ok(debug)
+    }
+}
+
+// CRC-32（IEEE 802.3，与zlib相同）
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
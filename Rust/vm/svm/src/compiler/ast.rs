@@ -0,0 +1,84 @@
+use super::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // 短路求值，结果为bool
+    And,
+    Or,
+}
+
+// 源码中的名字及其位置，作用域分析按位置记录每个名字解析到的局部变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Number(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    Nil,
+    Var(Ident),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    // 函数名和参数，函数可以是脚本中定义的、内置的或宿主注册的
+    Call(Ident, Vec<Expr>),
+    // 数组下标
+    Index(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Print(Expr),
+    Let(Ident, Expr),
+    Assign(Ident, Expr),
+    // 数组、下标、值
+    SetIndex(Expr, Expr, Expr),
+    // 条件、then分支、else分支，没有else时为空
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    // 只为副作用求值的表达式，结果丢弃
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Ident,
+    pub params: Vec<Ident>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+// 函数只能定义在最外层，其余的语句按顺序组成主程序
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub main: Vec<Stmt>,
+}
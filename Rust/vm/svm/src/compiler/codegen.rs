@@ -0,0 +1,310 @@
+// 代码生成：主程序在前，各函数依次在后，每个函数开头为参数以外的局部变量压入nil。
+//
+// 跳转目标先用标号表示，和汇编器一样在布局完成后回填。if和while用条件跳转跳过不执行的分支：
+// ```
+// <条件>; jmpifzero else; <then>; push end; jmp; else: <else>; end:
+// ```
+// &&和||短路求值，结果总是bool。
+
+use std::collections::HashMap;
+use super::ast::*;
+use super::resolver::{self, Resolution};
+use super::CompileError;
+use crate::instruction::*;
+use crate::program;
+use crate::value::Value;
+
+pub fn generate(program: &Program, resolution: &Resolution) -> Result<program::Program, CompileError> {
+    let mut generator = Generator {
+        resolution,
+        items: Vec::new(),
+        labels: Vec::new(),
+        functions: HashMap::new(),
+        constants: program::Program::default(),
+        function: None,
+        line: 0,
+    };
+    for function in &program.functions {
+        let label = generator.label();
+        generator.functions.insert(&function.name.name, label);
+    }
+
+    // 主程序开头为局部变量压入nil的指令算在第一条语句上
+    generator.line = program.main.first().map_or(1, |stmt| stmt.span.line);
+    generator.body(None, 0, &program.main);
+    // 主程序结束时不能继续执行后面的函数；跳转目标也不能是字节码的末尾，
+    // 主程序以if或while结束时同样要加上exit
+    if !program.functions.is_empty() || generator.labels.contains(&Some(generator.items.len())) {
+        generator.push(Value::Int(0));
+        generator.emit(OpCode::Exit);
+    }
+    for function in &program.functions {
+        generator.line = function.span.line;
+        generator.place(generator.functions[function.name.name.as_str()]);
+        generator.body(Some(&function.name.name), function.params.len(), &function.body);
+        // 没有return时返回nil
+        generator.push(Value::Nil);
+        generator.emit(OpCode::Return);
+    }
+    Ok(generator.finish(program))
+}
+
+enum Arg {
+    None,
+    Int(i64),
+    Const(usize),
+    // 标号的地址，布局后确定，Label作为push的立即数，Target作为条件跳转的绝对目标
+    Label(usize),
+    Target(usize),
+}
+
+struct Item {
+    opcode: OpCode,
+    arg: Arg,
+    line: usize,
+}
+
+struct Generator<'a> {
+    resolution: &'a Resolution,
+    items: Vec<Item>,
+    // 标号 -> 其后第一条指令在items中的位置
+    labels: Vec<Option<usize>>,
+    // 函数名 -> 入口的标号
+    functions: HashMap<&'a str, usize>,
+    // 只用到其中的常量池
+    constants: program::Program,
+    // 正在生成的函数，主程序为None
+    function: Option<&'a str>,
+    // 当前语句所在的行，写入调试信息
+    line: usize,
+}
+
+impl<'a> Generator<'a> {
+    fn emit_arg(&mut self, opcode: OpCode, arg: Arg) {
+        self.items.push(Item { opcode, arg, line: self.line });
+    }
+
+    fn emit(&mut self, opcode: OpCode) {
+        self.emit_arg(opcode, Arg::None);
+    }
+
+    // 整数用push的立即数，其余常量用pushconst
+    fn push(&mut self, value: Value) {
+        match value {
+            Value::Int(v) => self.emit_arg(OpCode::Push, Arg::Int(v)),
+            value => {
+                let index = self.constants.add_constant(value);
+                self.emit_arg(OpCode::PushConst, Arg::Const(index));
+            }
+        }
+    }
+
+    fn push_label(&mut self, label: usize) {
+        self.emit_arg(OpCode::Push, Arg::Label(label));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.items.len());
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.push_label(label);
+        self.emit(OpCode::Jmp);
+    }
+
+    // 弹出栈顶的条件，为假时跳到label
+    fn jump_if_false(&mut self, label: usize) {
+        self.emit_arg(OpCode::JmpIfZero, Arg::Target(label));
+    }
+
+    fn store(&mut self, slot: usize) {
+        self.push(Value::Int(slot as i64));
+        self.emit(OpCode::StoreLocal);
+    }
+
+    fn body(&mut self, function: Option<&'a str>, params: usize, stmts: &'a [Stmt]) {
+        self.function = function;
+        for _ in params..self.resolution.locals(function) {
+            self.push(Value::Nil);
+        }
+        self.block(stmts);
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        self.line = stmt.span.line;
+        match &stmt.kind {
+            StmtKind::Print(expr) => {
+                self.expr(expr);
+                self.emit(OpCode::Print);
+            }
+            StmtKind::Let(name, value) | StmtKind::Assign(name, value) => {
+                self.expr(value);
+                self.store(self.resolution.slot(name));
+            }
+            StmtKind::SetIndex(array, index, value) => {
+                self.expr(array);
+                self.expr(index);
+                self.expr(value);
+                self.emit(OpCode::ArraySet);
+            }
+            StmtKind::If(condition, then, otherwise) => {
+                let (else_label, end) = (self.label(), self.label());
+                self.expr(condition);
+                self.jump_if_false(else_label);
+                self.block(then);
+                self.jump(end);
+                self.place(else_label);
+                self.block(otherwise);
+                self.place(end);
+            }
+            StmtKind::While(condition, body) => {
+                let (top, end) = (self.label(), self.label());
+                self.place(top);
+                self.expr(condition);
+                self.jump_if_false(end);
+                self.block(body);
+                self.line = stmt.span.line;
+                self.jump(top);
+                self.place(end);
+            }
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value),
+                    None => self.push(Value::Nil),
+                }
+                self.emit(OpCode::Return);
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                let scratch = self.resolution.scratch(self.function).unwrap();
+                self.store(scratch);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Number(n) => self.push(Value::Int(*n)),
+            ExprKind::Float(x) => self.push(Value::Float(*x)),
+            ExprKind::Str(s) => self.push(Value::Str(s.clone())),
+            ExprKind::Bool(b) => self.push(Value::Bool(*b)),
+            ExprKind::Nil => self.push(Value::Nil),
+            ExprKind::Var(name) => {
+                self.push(Value::Int(self.resolution.slot(name) as i64));
+                self.emit(OpCode::LoadLocal);
+            }
+            ExprKind::Neg(operand) => {
+                self.expr(operand);
+                self.emit(OpCode::Neg);
+            }
+            ExprKind::Not(operand) => {
+                self.expr(operand);
+                self.emit(OpCode::Not);
+            }
+            // a && b：a为假时结果为false，不再求b；||与之对称
+            ExprKind::Binary(op @ (BinOp::And | BinOp::Or), lhs, rhs) => {
+                let (short, end) = (self.label(), self.label());
+                let (jump, value) = match op {
+                    BinOp::And => (OpCode::JmpIfZero, false),
+                    _ => (OpCode::JmpIfNonZero, true),
+                };
+                self.expr(lhs);
+                self.emit_arg(jump, Arg::Target(short));
+                self.expr(rhs);
+                self.emit_arg(jump, Arg::Target(short));
+                self.push(Value::Bool(!value));
+                self.jump(end);
+                self.place(short);
+                self.push(Value::Bool(value));
+                self.place(end);
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+                self.emit(match op {
+                    BinOp::Add => OpCode::Add,
+                    BinOp::Sub => OpCode::Sub,
+                    BinOp::Mul => OpCode::Mul,
+                    BinOp::Div => OpCode::Div,
+                    BinOp::Eq => OpCode::Eq,
+                    BinOp::Ne => OpCode::Ne,
+                    BinOp::Lt => OpCode::Lt,
+                    BinOp::Le => OpCode::Le,
+                    BinOp::Gt => OpCode::Gt,
+                    BinOp::Ge => OpCode::Ge,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                });
+            }
+            ExprKind::Index(array, index) => {
+                self.expr(array);
+                self.expr(index);
+                self.emit(OpCode::ArrayGet);
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                if let Some(&label) = self.functions.get(name.name.as_str()) {
+                    self.push(Value::Int(args.len() as i64));
+                    self.push_label(label);
+                    self.emit(OpCode::Call);
+                } else if let Some((opcode, _)) = resolver::builtin(&name.name) {
+                    self.emit(opcode);
+                } else {
+                    self.push(Value::Int(args.len() as i64));
+                    let index = self.constants.add_constant(Value::Str(name.name.clone()));
+                    self.emit_arg(OpCode::CallNative, Arg::Const(index));
+                }
+            }
+        }
+    }
+
+    // 布局并编码，标号地址的编码长度取决于地址本身，反复布局直到各指令地址不再变化
+    fn finish(self, program: &Program) -> program::Program {
+        let labels: Vec<usize> = self.labels.iter().map(|label| label.expect("label not placed")).collect();
+        let operand = |arg: &Arg, addrs: &[usize]| match arg {
+            Arg::None => Operand::None,
+            Arg::Int(v) => Operand::Int(*v),
+            Arg::Const(index) => Operand::Const(*index),
+            Arg::Label(label) => Operand::Int(addrs[labels[*label]] as i64),
+            Arg::Target(label) => Operand::Target(Target::Absolute(addrs[labels[*label]] as i64)),
+        };
+        let mut addrs = vec![0usize; self.items.len() + 1];
+        loop {
+            let mut next = Vec::with_capacity(self.items.len() + 1);
+            let mut addr = 0;
+            for item in &self.items {
+                next.push(addr);
+                addr += Instruction::new(item.opcode, operand(&item.arg, &addrs)).len;
+            }
+            next.push(addr);
+            if next == addrs {
+                break;
+            }
+            addrs = next;
+        }
+
+        // 函数名作为标号写入调试信息，调试器和性能分析用它显示函数名
+        let function_labels = program.functions.iter()
+            .map(|function| (function.name.name.clone(), addrs[labels[self.functions[function.name.name.as_str()]]]))
+            .collect();
+        let mut out = self.constants;
+        for (item, &addr) in self.items.iter().zip(&addrs) {
+            out.debug.add_line(addr, item.line);
+            Instruction::new(item.opcode, operand(&item.arg, &addrs)).encode(&mut out.codes);
+        }
+        out.debug.labels = function_labels;
+        out
+    }
+}
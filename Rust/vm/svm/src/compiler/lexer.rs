@@ -0,0 +1,250 @@
+use std::fmt;
+use super::{CompileError, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // 整数字面量不带符号，可以是2^63，由语法分析检查范围
+    Number(u64),
+    Float(f64),
+    Str(String),
+    Ident(String),
+    Print,
+    Let,
+    If,
+    Else,
+    While,
+    Fn,
+    Return,
+    True,
+    False,
+    Nil,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Assign,
+    EqEq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    AndAnd,
+    OrOr,
+    Bang,
+    Semicolon,
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Number(n) => write!(f, "`{}`", n),
+            TokenKind::Float(x) => write!(f, "`{:?}`", x),
+            TokenKind::Str(s) => write!(f, "`{}`", crate::assembler::escape(s)),
+            TokenKind::Ident(s) => write!(f, "`{}`", s),
+            TokenKind::Print => write!(f, "`print`"),
+            TokenKind::Let => write!(f, "`let`"),
+            TokenKind::If => write!(f, "`if`"),
+            TokenKind::Else => write!(f, "`else`"),
+            TokenKind::While => write!(f, "`while`"),
+            TokenKind::Fn => write!(f, "`fn`"),
+            TokenKind::Return => write!(f, "`return`"),
+            TokenKind::True => write!(f, "`true`"),
+            TokenKind::False => write!(f, "`false`"),
+            TokenKind::Nil => write!(f, "`nil`"),
+            TokenKind::Plus => write!(f, "`+`"),
+            TokenKind::Minus => write!(f, "`-`"),
+            TokenKind::Star => write!(f, "`*`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::LParen => write!(f, "`(`"),
+            TokenKind::RParen => write!(f, "`)`"),
+            TokenKind::LBrace => write!(f, "`{{`"),
+            TokenKind::RBrace => write!(f, "`}}`"),
+            TokenKind::LBracket => write!(f, "`[`"),
+            TokenKind::RBracket => write!(f, "`]`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Assign => write!(f, "`=`"),
+            TokenKind::EqEq => write!(f, "`==`"),
+            TokenKind::NotEq => write!(f, "`!=`"),
+            TokenKind::Less => write!(f, "`<`"),
+            TokenKind::LessEq => write!(f, "`<=`"),
+            TokenKind::Greater => write!(f, "`>`"),
+            TokenKind::GreaterEq => write!(f, "`>=`"),
+            TokenKind::AndAnd => write!(f, "`&&`"),
+            TokenKind::OrOr => write!(f, "`||`"),
+            TokenKind::Bang => write!(f, "`!`"),
+            TokenKind::Semicolon => write!(f, "`;`"),
+            TokenKind::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+// 两个字符的运算符，要在单个字符之前匹配
+const TWO_CHAR_TOKENS: [(&str, TokenKind); 6] = [
+    ("==", TokenKind::EqEq),
+    ("!=", TokenKind::NotEq),
+    ("<=", TokenKind::LessEq),
+    (">=", TokenKind::GreaterEq),
+    ("&&", TokenKind::AndAnd),
+    ("||", TokenKind::OrOr),
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut line = 1;
+    let mut line_start = 0;
+
+    while let Some(&(start, c)) = chars.peek() {
+        let column = source[line_start..start].chars().count() + 1;
+        let span_to = |end: usize| Span { start, end, line, column };
+
+        if c == '\n' {
+            chars.next();
+            line += 1;
+            line_start = start + 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        // `//`开始的行注释
+        if c == '/' && source[start..].starts_with("//") {
+            while let Some(&(_, c)) = chars.peek() {
+                if c == '\n' {
+                    break;
+                }
+                chars.next();
+            }
+            continue;
+        }
+
+        let kind = if c.is_ascii_digit() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            // 小数点后还有数字时是浮点数
+            let mut rest = source[end..].chars();
+            if rest.next() == Some('.') && rest.next().is_some_and(|c| c.is_ascii_digit()) {
+                chars.next();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let value = source[start..end].parse().unwrap();
+                tokens.push(Token { kind: TokenKind::Float(value), span: span_to(end) });
+                continue;
+            }
+            let text = &source[start..end];
+            let value: u64 = text.parse()
+                .map_err(|_| CompileError::new(span_to(end), format!("number `{}` is too large", text)))?;
+            tokens.push(Token { kind: TokenKind::Number(value), span: span_to(end) });
+            continue;
+        } else if c == '"' {
+            // 字符串字面量，转义字符与汇编中相同，不能跨行
+            chars.next();
+            let mut end = None;
+            let mut escaped = false;
+            while let Some(&(i, c)) = chars.peek() {
+                if c == '\n' {
+                    break;
+                }
+                chars.next();
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    end = Some(i + 1);
+                    break;
+                }
+            }
+            let end = end.ok_or_else(|| CompileError::new(span_to(start + 1), "unterminated string"))?;
+            let value = crate::assembler::unescape(&source[start..end])
+                .ok_or_else(|| CompileError::new(span_to(end), "invalid escape in string"))?;
+            tokens.push(Token { kind: TokenKind::Str(value), span: span_to(end) });
+            continue;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let kind = match &source[start..end] {
+                "print" => TokenKind::Print,
+                "let" => TokenKind::Let,
+                "if" => TokenKind::If,
+                "else" => TokenKind::Else,
+                "while" => TokenKind::While,
+                "fn" => TokenKind::Fn,
+                "return" => TokenKind::Return,
+                "true" => TokenKind::True,
+                "false" => TokenKind::False,
+                "nil" => TokenKind::Nil,
+                ident => TokenKind::Ident(ident.to_string()),
+            };
+            tokens.push(Token { kind, span: span_to(end) });
+            continue;
+        } else if let Some((text, kind)) = TWO_CHAR_TOKENS.iter().find(|(text, _)| source[start..].starts_with(*text)) {
+            chars.next();
+            chars.next();
+            tokens.push(Token { kind: kind.clone(), span: span_to(start + text.len()) });
+            continue;
+        } else {
+            match c {
+                '+' => TokenKind::Plus,
+                '-' => TokenKind::Minus,
+                '*' => TokenKind::Star,
+                '/' => TokenKind::Slash,
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                '{' => TokenKind::LBrace,
+                '}' => TokenKind::RBrace,
+                '[' => TokenKind::LBracket,
+                ']' => TokenKind::RBracket,
+                ',' => TokenKind::Comma,
+                '=' => TokenKind::Assign,
+                '<' => TokenKind::Less,
+                '>' => TokenKind::Greater,
+                '!' => TokenKind::Bang,
+                ';' | '；' => TokenKind::Semicolon,
+                _ => {
+                    return Err(CompileError::new(span_to(start + c.len_utf8()), format!("unexpected character `{}`", c)));
+                }
+            }
+        };
+        chars.next();
+        tokens.push(Token { kind, span: span_to(start + c.len_utf8()) });
+    }
+
+    let column = source[line_start..].chars().count() + 1;
+    let end = source.len();
+    tokens.push(Token { kind: TokenKind::Eof, span: Span { start: end, end, line, column } });
+    Ok(tokens)
+}
@@ -0,0 +1,67 @@
+// 编译前端：将.svs脚本翻译为虚拟机字节码。
+//
+// 源码 --lexer--> Token --parser--> 语法树 --resolver--> 变量槽位 --codegen--> 字节码
+//
+// 脚本示例：
+// ```
+// fn fact(n) {
+//     if n { return n * fact(n - 1); }   // 条件按值的真假判断，nil、false、0和0.0为假
+//     return 1;
+// }
+// let i = 5;
+// while i { print(fact(i)); i = i - 1; }
+// ```
+// 内置函数readline、array、len、concat、substring直接对应指令，
+// 其他未定义的函数在运行时按名字调用宿主注册的函数，如sqrt(2.0)。
+
+pub mod ast;
+pub mod codegen;
+pub mod lexer;
+pub mod parser;
+pub mod resolver;
+
+use std::fmt;
+use crate::program::Program;
+
+// 源码中的一段区间，start/end为字节偏移，line/column为起始位置（从1开始）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // 从self开始到other结束的区间
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        CompileError { span, message: message.into() }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+pub fn compile(source: &str) -> Result<Program, CompileError> {
+    let tokens = lexer::tokenize(source)?;
+    let program = parser::parse(&tokens)?;
+    let resolution = resolver::resolve(&program)?;
+    codegen::generate(&program, &resolution)
+}
@@ -0,0 +1,318 @@
+// 递归下降语法分析器，文法如下：
+//
+// program    := (function | statement)*
+// function   := "fn" IDENT "(" (IDENT ("," IDENT)*)? ")" block
+// block      := "{" statement* "}"
+// statement  := "print" "(" expr ")" ";"?
+//             | "let" IDENT "=" expr ";"?
+//             | "if" expr block ("else" (block | if-statement))?
+//             | "while" expr block
+//             | "return" expr? ";"?
+//             | expr ("=" expr)? ";"?          赋值的左边只能是变量或数组元素
+// expr       := and ("||" and)*
+// and        := equality ("&&" equality)*
+// equality   := comparison (("==" | "!=") comparison)*
+// comparison := sum (("<" | "<=" | ">" | ">=") sum)*
+// sum        := term (("+" | "-") term)*
+// term       := unary (("*" | "/") unary)*
+// unary      := ("-" | "!") unary | postfix
+// postfix    := primary ("[" expr "]")*
+// primary    := NUMBER | FLOAT | STRING | "true" | "false" | "nil"
+//             | IDENT ("(" (expr ("," expr)*)? ")")? | "(" expr ")"
+
+use std::convert::TryFrom;
+use super::ast::*;
+use super::lexer::{Token, TokenKind};
+use super::{CompileError, Span};
+
+// 括号、一元运算、块和else if的最大嵌套层数，更深时报错，而不是耗尽栈
+pub const MAX_DEPTH: usize = 64;
+
+pub fn parse(tokens: &[Token]) -> Result<Program, CompileError> {
+    let mut parser = Parser { tokens, pos: 0, depth: 0 };
+    let mut program = Program::default();
+    while parser.peek().kind != TokenKind::Eof {
+        if parser.peek().kind == TokenKind::Fn {
+            program.functions.push(parser.function()?);
+        } else {
+            program.main.push(parser.statement()?);
+        }
+    }
+    Ok(program)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    // 当前的嵌套层数
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        let token = &self.tokens[self.pos];
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<&'a Token, CompileError> {
+        let token = self.peek();
+        if token.kind == kind {
+            Ok(self.advance())
+        } else {
+            Err(unexpected(token, what))
+        }
+    }
+
+    // 多嵌套一层调用parse
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, CompileError>) -> Result<T, CompileError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(CompileError::new(self.peek().span, "too deeply nested"));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn ident(&mut self, what: &str) -> Result<Ident, CompileError> {
+        let token = self.advance();
+        match &token.kind {
+            TokenKind::Ident(name) => Ok(Ident { name: name.clone(), span: token.span }),
+            _ => Err(unexpected(token, what)),
+        }
+    }
+
+    // 语句末尾可选的分号，返回语句结束的位置
+    fn end_of_statement(&mut self, end: Span) -> Span {
+        if self.peek().kind == TokenKind::Semicolon {
+            return self.advance().span;
+        }
+        end
+    }
+
+    fn function(&mut self) -> Result<Function, CompileError> {
+        let start = self.expect(TokenKind::Fn, "`fn`")?.span;
+        let name = self.ident("function name")?;
+        self.expect(TokenKind::LParen, "`(`")?;
+        let mut params = Vec::new();
+        if self.peek().kind != TokenKind::RParen {
+            params.push(self.ident("parameter name")?);
+            while self.peek().kind == TokenKind::Comma {
+                self.advance();
+                params.push(self.ident("parameter name")?);
+            }
+        }
+        self.expect(TokenKind::RParen, "`)`")?;
+        let (body, end) = self.block()?;
+        Ok(Function { name, params, body, span: start.to(end) })
+    }
+
+    // 返回块中的语句和右花括号的位置
+    fn block(&mut self) -> Result<(Vec<Stmt>, Span), CompileError> {
+        self.expect(TokenKind::LBrace, "`{`")?;
+        let mut stmts = Vec::new();
+        while !matches!(self.peek().kind, TokenKind::RBrace | TokenKind::Eof) {
+            if self.peek().kind == TokenKind::Fn {
+                return Err(CompileError::new(self.peek().span, "functions can only be defined at the top level"));
+            }
+            stmts.push(self.nested(Self::statement)?);
+        }
+        let end = self.expect(TokenKind::RBrace, "`}`")?.span;
+        Ok((stmts, end))
+    }
+
+    fn statement(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.peek().span;
+        match self.peek().kind {
+            TokenKind::Print => {
+                self.advance();
+                self.expect(TokenKind::LParen, "`(`")?;
+                let expr = self.expr()?;
+                let end = self.expect(TokenKind::RParen, "`)`")?.span;
+                let end = self.end_of_statement(end);
+                Ok(Stmt { kind: StmtKind::Print(expr), span: start.to(end) })
+            }
+            TokenKind::Let => {
+                self.advance();
+                let name = self.ident("variable name")?;
+                self.expect(TokenKind::Assign, "`=`")?;
+                let value = self.expr()?;
+                let end = self.end_of_statement(value.span);
+                Ok(Stmt { kind: StmtKind::Let(name, value), span: start.to(end) })
+            }
+            TokenKind::If => self.if_statement(),
+            TokenKind::While => {
+                self.advance();
+                let condition = self.expr()?;
+                let (body, end) = self.block()?;
+                Ok(Stmt { kind: StmtKind::While(condition, body), span: start.to(end) })
+            }
+            TokenKind::Return => {
+                self.advance();
+                let value = match self.peek().kind {
+                    TokenKind::Semicolon | TokenKind::RBrace | TokenKind::Eof => None,
+                    _ => Some(self.expr()?),
+                };
+                let end = self.end_of_statement(value.as_ref().map_or(start, |v| v.span));
+                Ok(Stmt { kind: StmtKind::Return(value), span: start.to(end) })
+            }
+            _ => {
+                let expr = self.expr()?;
+                if self.peek().kind != TokenKind::Assign {
+                    let end = self.end_of_statement(expr.span);
+                    return Ok(Stmt { kind: StmtKind::Expr(expr), span: start.to(end) });
+                }
+                self.advance();
+                let value = self.expr()?;
+                let end = self.end_of_statement(value.span);
+                let kind = match expr.kind {
+                    ExprKind::Var(name) => StmtKind::Assign(name, value),
+                    ExprKind::Index(array, index) => StmtKind::SetIndex(*array, *index, value),
+                    _ => return Err(CompileError::new(expr.span, "invalid assignment target")),
+                };
+                Ok(Stmt { kind, span: start.to(end) })
+            }
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, CompileError> {
+        let start = self.expect(TokenKind::If, "`if`")?.span;
+        let condition = self.expr()?;
+        let (then, mut end) = self.block()?;
+        let mut otherwise = Vec::new();
+        if self.peek().kind == TokenKind::Else {
+            self.advance();
+            if self.peek().kind == TokenKind::If {
+                let stmt = self.nested(Self::if_statement)?;
+                end = stmt.span;
+                otherwise.push(stmt);
+            } else {
+                let (stmts, block_end) = self.block()?;
+                otherwise = stmts;
+                end = block_end;
+            }
+        }
+        Ok(Stmt { kind: StmtKind::If(condition, then, otherwise), span: start.to(end) })
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.nested(|parser| parser.binary(0))
+    }
+
+    // 按优先级从低到高解析左结合的二元运算，level超出PRECEDENCE时解析一元运算
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        let Some(ops) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&(_, op)) = ops.iter().find(|(kind, _)| *kind == self.peek().kind) {
+            self.advance();
+            let rhs = self.binary(level + 1)?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let negate = match self.peek().kind {
+            TokenKind::Minus => true,
+            TokenKind::Bang => false,
+            _ => return self.postfix(),
+        };
+        let start = self.advance().span;
+        // 2^63只能直接写在负号后面，即i64::MIN
+        if let (true, TokenKind::Number(n)) = (negate, &self.peek().kind) {
+            if *n == 1 << 63 {
+                let span = start.to(self.advance().span);
+                return Ok(Expr { kind: ExprKind::Number(i64::MIN), span });
+            }
+        }
+        let operand = self.nested(Self::unary)?;
+        let span = start.to(operand.span);
+        // 负数字面量直接折叠为常量，-i64::MIN留到运行时报溢出
+        let kind = match operand.kind {
+            ExprKind::Number(n) if negate && n != i64::MIN => ExprKind::Number(-n),
+            ExprKind::Float(x) if negate => ExprKind::Float(-x),
+            _ if negate => ExprKind::Neg(Box::new(operand)),
+            _ => ExprKind::Not(Box::new(operand)),
+        };
+        Ok(Expr { kind, span })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.primary()?;
+        while self.peek().kind == TokenKind::LBracket {
+            self.advance();
+            let index = self.expr()?;
+            let end = self.expect(TokenKind::RBracket, "`]`")?.span;
+            let span = expr.span.to(end);
+            expr = Expr { kind: ExprKind::Index(Box::new(expr), Box::new(index)), span };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let token = self.advance();
+        let kind = match &token.kind {
+            TokenKind::Number(n) => match i64::try_from(*n) {
+                Ok(n) => ExprKind::Number(n),
+                Err(_) => return Err(CompileError::new(token.span, format!("number `{}` is too large", n))),
+            },
+            TokenKind::Float(x) => ExprKind::Float(*x),
+            TokenKind::Str(s) => ExprKind::Str(s.clone()),
+            TokenKind::True => ExprKind::Bool(true),
+            TokenKind::False => ExprKind::Bool(false),
+            TokenKind::Nil => ExprKind::Nil,
+            TokenKind::Ident(name) => {
+                let ident = Ident { name: name.clone(), span: token.span };
+                if self.peek().kind != TokenKind::LParen {
+                    return Ok(Expr { kind: ExprKind::Var(ident), span: token.span });
+                }
+                self.advance();
+                let mut args = Vec::new();
+                if self.peek().kind != TokenKind::RParen {
+                    args.push(self.expr()?);
+                    while self.peek().kind == TokenKind::Comma {
+                        self.advance();
+                        args.push(self.expr()?);
+                    }
+                }
+                let end = self.expect(TokenKind::RParen, "`)`")?.span;
+                return Ok(Expr { kind: ExprKind::Call(ident, args), span: token.span.to(end) });
+            }
+            TokenKind::LParen => {
+                let expr = self.expr()?;
+                let end = self.expect(TokenKind::RParen, "`)`")?.span;
+                return Ok(Expr { span: token.span.to(end), ..expr });
+            }
+            _ => return Err(unexpected(token, "expression")),
+        };
+        Ok(Expr { kind, span: token.span })
+    }
+}
+
+// 各级二元运算符，优先级从低到高
+const PRECEDENCE: [&[(TokenKind, BinOp)]; 6] = [
+    &[(TokenKind::OrOr, BinOp::Or)],
+    &[(TokenKind::AndAnd, BinOp::And)],
+    &[(TokenKind::EqEq, BinOp::Eq), (TokenKind::NotEq, BinOp::Ne)],
+    &[(TokenKind::Less, BinOp::Lt), (TokenKind::LessEq, BinOp::Le), (TokenKind::Greater, BinOp::Gt), (TokenKind::GreaterEq, BinOp::Ge)],
+    &[(TokenKind::Plus, BinOp::Add), (TokenKind::Minus, BinOp::Sub)],
+    &[(TokenKind::Star, BinOp::Mul), (TokenKind::Slash, BinOp::Div)],
+];
+
+fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span: Span = lhs.span.to(rhs.span);
+    Expr { kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span }
+}
+
+fn unexpected(token: &Token, expected: &str) -> CompileError {
+    CompileError::new(token.span, format!("expected {}, found {}", expected, token.kind))
+}
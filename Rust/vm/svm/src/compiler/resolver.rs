@@ -0,0 +1,198 @@
+// 作用域分析：把每个变量名解析为所在函数的局部变量槽位，并检查函数调用。
+//
+// 参数依次占用槽位0..n，之后每个let声明占用一个新的槽位，块结束后其中声明的名字不再可见，
+// 但槽位不会复用。函数只能访问自己的参数和局部变量，不能访问主程序中的变量。
+
+use std::collections::HashMap;
+use super::ast::*;
+use super::CompileError;
+use crate::instruction::OpCode;
+
+// 直接对应一条指令的内置函数及其参数个数
+pub fn builtin(name: &str) -> Option<(OpCode, usize)> {
+    match name {
+        "readline" => Some((OpCode::ReadLine, 0)),
+        "array" => Some((OpCode::NewArray, 1)),
+        "len" => Some((OpCode::ArrayLen, 1)),
+        "concat" => Some((OpCode::Concat, 2)),
+        "substring" => Some((OpCode::Substring, 3)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Resolution {
+    // 变量名出现的位置（Ident的span.start）-> 槽位
+    slots: HashMap<usize, usize>,
+    // 函数名 -> 局部变量个数（包括参数），主程序为None
+    locals: HashMap<Option<String>, usize>,
+    // 函数名 -> 丢弃表达式语句结果用的槽位，没有表达式语句的函数没有
+    scratch: HashMap<Option<String>, usize>,
+}
+
+impl Resolution {
+    pub fn slot(&self, ident: &Ident) -> usize {
+        self.slots[&ident.span.start]
+    }
+
+    pub fn locals(&self, function: Option<&str>) -> usize {
+        self.locals[&function.map(str::to_string)]
+    }
+
+    pub fn scratch(&self, function: Option<&str>) -> Option<usize> {
+        self.scratch.get(&function.map(str::to_string)).copied()
+    }
+}
+
+pub fn resolve(program: &Program) -> Result<Resolution, CompileError> {
+    let mut resolver = Resolver { functions: HashMap::new(), resolution: Resolution::default(), scopes: Vec::new(), next_slot: 0, scratch: None, in_function: false };
+    for function in &program.functions {
+        let name = &function.name;
+        if builtin(&name.name).is_some() {
+            return Err(CompileError::new(name.span, format!("`{}` is a builtin function", name.name)));
+        }
+        if resolver.functions.insert(&name.name, function.params.len()).is_some() {
+            return Err(CompileError::new(name.span, format!("function `{}` is already defined", name.name)));
+        }
+    }
+
+    resolver.body(None, &[], &program.main)?;
+    for function in &program.functions {
+        resolver.body(Some(&function.name.name), &function.params, &function.body)?;
+    }
+    Ok(resolver.resolution)
+}
+
+struct Resolver<'a> {
+    // 函数名 -> 参数个数
+    functions: HashMap<&'a str, usize>,
+    resolution: Resolution,
+    // 由外到内的块作用域，名字 -> 槽位
+    scopes: Vec<HashMap<&'a str, usize>>,
+    next_slot: usize,
+    scratch: Option<usize>,
+    in_function: bool,
+}
+
+impl<'a> Resolver<'a> {
+    fn body(&mut self, function: Option<&str>, params: &'a [Ident], stmts: &'a [Stmt]) -> Result<(), CompileError> {
+        self.scopes = vec![HashMap::new()];
+        self.next_slot = 0;
+        self.scratch = None;
+        self.in_function = function.is_some();
+        for param in params {
+            if self.scopes[0].contains_key(param.name.as_str()) {
+                return Err(CompileError::new(param.span, format!("duplicate parameter `{}`", param.name)));
+            }
+            self.declare(param);
+        }
+        self.block(stmts)?;
+        let function = function.map(str::to_string);
+        if let Some(slot) = self.scratch {
+            self.resolution.scratch.insert(function.clone(), slot);
+        }
+        self.resolution.locals.insert(function, self.next_slot);
+        Ok(())
+    }
+
+    fn declare(&mut self, ident: &'a Ident) {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.scopes.last_mut().unwrap().insert(&ident.name, slot);
+        self.resolution.slots.insert(ident.span.start, slot);
+    }
+
+    fn lookup(&mut self, ident: &Ident) -> Result<(), CompileError> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(ident.name.as_str())) {
+            Some(&slot) => {
+                self.resolution.slots.insert(ident.span.start, slot);
+                Ok(())
+            }
+            None if self.functions.contains_key(ident.name.as_str()) => {
+                Err(CompileError::new(ident.span, format!("`{}` is a function, not a variable", ident.name)))
+            }
+            None => Err(CompileError::new(ident.span, format!("undefined variable `{}`", ident.name))),
+        }
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.stmt(stmt)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> Result<(), CompileError> {
+        match &stmt.kind {
+            StmtKind::Print(expr) => self.expr(expr),
+            // 先解析初始值，`let x = x + 1`中右边的x是外层的变量
+            StmtKind::Let(name, value) => {
+                self.expr(value)?;
+                self.declare(name);
+                Ok(())
+            }
+            StmtKind::Assign(name, value) => {
+                self.expr(value)?;
+                self.lookup(name)
+            }
+            StmtKind::SetIndex(array, index, value) => {
+                self.expr(array)?;
+                self.expr(index)?;
+                self.expr(value)
+            }
+            StmtKind::If(condition, then, otherwise) => {
+                self.expr(condition)?;
+                self.block(then)?;
+                self.block(otherwise)
+            }
+            StmtKind::While(condition, body) => {
+                self.expr(condition)?;
+                self.block(body)
+            }
+            StmtKind::Return(value) => {
+                if !self.in_function {
+                    return Err(CompileError::new(stmt.span, "return outside of a function"));
+                }
+                match value {
+                    Some(value) => self.expr(value),
+                    None => Ok(()),
+                }
+            }
+            StmtKind::Expr(expr) => {
+                if self.scratch.is_none() {
+                    self.scratch = Some(self.next_slot);
+                    self.next_slot += 1;
+                }
+                self.expr(expr)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Result<(), CompileError> {
+        match &expr.kind {
+            ExprKind::Number(_) | ExprKind::Float(_) | ExprKind::Str(_) | ExprKind::Bool(_) | ExprKind::Nil => Ok(()),
+            ExprKind::Var(name) => self.lookup(name),
+            ExprKind::Neg(operand) | ExprKind::Not(operand) => self.expr(operand),
+            ExprKind::Binary(_, lhs, rhs) | ExprKind::Index(lhs, rhs) => {
+                self.expr(lhs)?;
+                self.expr(rhs)
+            }
+            ExprKind::Call(name, args) => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                // 既不是脚本中的函数也不是内置函数时，运行时按名字调用宿主函数
+                let arity = self.functions.get(name.name.as_str()).copied().or_else(|| builtin(&name.name).map(|(_, n)| n));
+                match arity {
+                    Some(arity) if arity != args.len() => Err(CompileError::new(
+                        expr.span,
+                        format!("function `{}` takes {} arguments, found {}", name.name, arity, args.len()),
+                    )),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+// Debug Adapter Protocol服务，`svm dap`通过标准输入输出与VS Code等编辑器通信。
+//
+// 每条消息为`Content-Length: N\r\n\r\n`加上N字节的JSON。支持的请求有initialize、launch、
+// setBreakpoints、configurationDone、threads、stackTrace、scopes、variables、next、stepIn、
+// continue和disconnect。断点和单步都以源码行为单位，通过调试信息在地址和行号之间转换，
+// 一行的代码分成几段时，断点在每一段的第一个地址上都会停下。
+// 虚拟机只有一个线程，id为1；栈帧id从0开始，0为最内层；变量引用为栈帧id加1。
+// 程序的Print输出通过output事件发送，ReadLine没有输入可读。
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use serde_json::{json, Value as Json};
+use crate::natives;
+use crate::program::Program;
+use crate::value::Value;
+use crate::vm::{Control, Vm};
+use crate::vmio::BufferIo;
+
+const THREAD_ID: i64 = 1;
+// 一条消息最多的字节数，超出时认为输入有误，不再按Content-Length分配内存
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+// 停下后恢复执行的方式
+#[derive(Debug, Clone, Copy)]
+enum Resume {
+    Continue,
+    // 单步进入，行号变化时停下
+    StepIn { line: Option<usize> },
+    // 单步跳过，回到不深于depth的帧且行号变化时停下
+    Next { depth: usize, line: Option<usize> },
+}
+
+struct Server<R, W> {
+    input: R,
+    output: W,
+    // 发出消息的序号
+    seq: i64,
+    program: Option<Program>,
+    stop_on_entry: bool,
+    // 设置了断点的源码行
+    breakpoints: BTreeSet<usize>,
+    resume: Resume,
+    // 是否已经执行过第一条指令
+    started: bool,
+    disconnected: bool,
+    // 收集Print的输出，停下或结束时作为output事件发送
+    printed: BufferIo,
+}
+
+// 处理一个调试会话，直到客户端断开连接或输入结束
+pub fn serve(input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        input,
+        output,
+        seq: 0,
+        program: None,
+        stop_on_entry: false,
+        breakpoints: BTreeSet::new(),
+        resume: Resume::Continue,
+        started: false,
+        disconnected: false,
+        printed: BufferIo::default(),
+    };
+    let program = match server.configure()? {
+        Some(program) => program,
+        None => return Ok(()),
+    };
+
+    let mut vm = Vm::new();
+    vm.import_program(&program);
+    natives::register_builtins(&mut vm);
+    vm.set_io(server.printed.clone());
+    let mut failure = None;
+    let result = vm.run_with(|vm, _| match server.before(vm) {
+        Ok(control) => control,
+        Err(e) => {
+            failure = Some(e);
+            Control::Stop
+        }
+    });
+    if let Some(e) = failure {
+        return Err(e);
+    }
+    server.flush_printed()?;
+    if server.disconnected {
+        return Ok(());
+    }
+
+    let code = match result {
+        Ok(()) => vm.exit_code().unwrap_or(0),
+        Err(e) => {
+            let line = vm.debug_info().line_of(e.pc()).map(|l| format!(" (line {})", l)).unwrap_or_default();
+            server.event("output", json!({ "category": "stderr", "output": format!("runtime error: {}{}\n", e, line) }))?;
+            1
+        }
+    };
+    server.event("exited", json!({ "exitCode": code }))?;
+    server.event("terminated", json!({}))?;
+
+    // 程序已经结束，等待客户端断开
+    while let Some(request) = server.read()? {
+        if command(&request) == "disconnect" {
+            return server.respond(&request, json!({}));
+        }
+        server.common(&request, None)?;
+    }
+    Ok(())
+}
+
+fn command(request: &Json) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    // 处理启动前的请求，launch和configurationDone都完成后返回要运行的程序
+    fn configure(&mut self) -> io::Result<Option<Program>> {
+        let mut configured = false;
+        loop {
+            if configured && self.program.is_some() {
+                return Ok(self.program.clone());
+            }
+            let request = match self.read()? {
+                Some(request) => request,
+                None => return Ok(None),
+            };
+            match command(&request) {
+                "initialize" => {
+                    self.respond(&request, json!({ "supportsConfigurationDoneRequest": true }))?;
+                    self.event("initialized", json!({}))?;
+                }
+                "launch" => {
+                    let arguments = &request["arguments"];
+                    let file = arguments["program"].as_str().unwrap_or_default();
+                    match Program::load(file) {
+                        Ok(program) => {
+                            self.program = Some(program);
+                            self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+                            self.respond(&request, json!({}))?;
+                        }
+                        Err(message) => self.respond_error(&request, &message)?,
+                    }
+                }
+                "configurationDone" => {
+                    configured = true;
+                    self.respond(&request, json!({}))?;
+                }
+                "disconnect" => {
+                    self.respond(&request, json!({}))?;
+                    return Ok(None);
+                }
+                _ => self.common(&request, None)?,
+            }
+        }
+    }
+
+    // 每条指令执行前调用，需要停下时发送stopped事件并处理请求，直到恢复执行
+    fn before(&mut self, vm: &Vm) -> io::Result<Control> {
+        let pc = vm.pc();
+        let line = vm.debug_info().line_of(pc);
+        let entry = !self.started;
+        self.started = true;
+        let left = |from: Option<usize>| line.is_none() || line != from;
+        let reason = if entry && self.stop_on_entry {
+            "entry"
+        } else if self.is_breakpoint(vm, pc) {
+            "breakpoint"
+        } else {
+            match self.resume {
+                Resume::StepIn { line: from } if left(from) => "step",
+                Resume::Next { depth, line: from } if vm.frames().len() <= depth && left(from) => "step",
+                _ => return Ok(Control::Continue),
+            }
+        };
+
+        self.flush_printed()?;
+        self.event("stopped", json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }))?;
+        loop {
+            let request = match self.read()? {
+                Some(request) => request,
+                None => {
+                    self.disconnected = true;
+                    return Ok(Control::Stop);
+                }
+            };
+            let (resume, body) = match command(&request) {
+                "continue" => (Resume::Continue, json!({ "allThreadsContinued": true })),
+                "next" => (Resume::Next { depth: vm.frames().len(), line }, json!({})),
+                "stepIn" => (Resume::StepIn { line }, json!({})),
+                "disconnect" => {
+                    self.respond(&request, json!({}))?;
+                    self.disconnected = true;
+                    return Ok(Control::Stop);
+                }
+                _ => {
+                    self.common(&request, Some(vm))?;
+                    continue;
+                }
+            };
+            self.respond(&request, body)?;
+            self.resume = resume;
+            return Ok(Control::Continue);
+        }
+    }
+
+    // 断点设在某一行时，停在该行每一段代码的第一个地址上
+    fn is_breakpoint(&self, vm: &Vm, pc: usize) -> bool {
+        vm.debug_info().line_starting_at(pc).is_some_and(|line| self.breakpoints.contains(&line))
+    }
+
+    // 任何时候都可以处理的请求，查看栈帧和变量需要程序处于停止状态
+    fn common(&mut self, request: &Json, vm: Option<&Vm>) -> io::Result<()> {
+        match (command(request), vm) {
+            ("setBreakpoints", _) => {
+                let arguments = &request["arguments"];
+                let lines: Vec<usize> = match arguments["breakpoints"].as_array() {
+                    Some(breakpoints) => breakpoints.iter().filter_map(|b| b["line"].as_u64()).map(|l| l as usize).collect(),
+                    None => Vec::new(),
+                };
+                self.breakpoints = lines.iter().copied().collect();
+                let program = self.program.as_ref();
+                let breakpoints: Vec<Json> = lines.iter().map(|&line| {
+                    let verified = program.is_some_and(|p| p.debug.addr_of_line(line).is_some());
+                    json!({ "verified": verified, "line": line })
+                }).collect();
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            }
+            ("threads", _) => self.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            ("stackTrace", Some(vm)) => {
+                let frames = stack_frames(vm);
+                self.respond(request, json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            ("scopes", Some(_)) => {
+                let frame = request["arguments"]["frameId"].as_u64().unwrap_or(0);
+                let scopes = [json!({ "name": "Locals", "variablesReference": frame + 1, "expensive": false })];
+                self.respond(request, json!({ "scopes": scopes }))
+            }
+            ("variables", Some(vm)) => {
+                let reference = request["arguments"]["variablesReference"].as_u64().unwrap_or(0) as usize;
+                match reference.checked_sub(1).and_then(|frame| locals(vm, frame)) {
+                    Some(values) => {
+                        let variables: Vec<Json> = values.iter().enumerate().map(|(index, value)| json!({
+                            "name": index.to_string(),
+                            "value": vm.heap().repr(value),
+                            "type": vm.heap().type_name(value),
+                            "variablesReference": 0,
+                        })).collect();
+                        self.respond(request, json!({ "variables": variables }))
+                    }
+                    None => self.respond_error(request, "invalid variables reference"),
+                }
+            }
+            ("stackTrace", None) | ("scopes", None) | ("variables", None) => self.respond_error(request, "program is not stopped"),
+            (other, _) => {
+                let message = format!("unsupported request `{}`", other);
+                self.respond_error(request, &message)
+            }
+        }
+    }
+
+    // 读取一条消息，输入结束时返回None
+    fn read(&mut self) -> io::Result<Option<Json>> {
+        let mut length = None;
+        let mut headers = false;
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                // 消息之间多余的空行直接跳过
+                if headers {
+                    break;
+                }
+                continue;
+            }
+            headers = true;
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+            }
+        }
+        let length = length.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+        if length > MAX_MESSAGE_LEN {
+            return Err(invalid_data(format!("message of {} bytes exceeds the limit of {} bytes", length, MAX_MESSAGE_LEN)));
+        }
+        let mut body = vec![0; length];
+        self.input.read_exact(&mut body)?;
+        serde_json::from_slice(&body).map(Some).map_err(invalid_data)
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn respond_error(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    // 把程序已经打印的内容作为output事件发出
+    fn flush_printed(&mut self) -> io::Result<()> {
+        let printed = self.printed.take_output();
+        if printed.is_empty() {
+            return Ok(());
+        }
+        self.event("output", json!({ "category": "stdout", "output": printed }))
+    }
+}
+
+// 从最内层开始的栈帧，外层帧的位置为其中的call指令，即返回地址的前一个字节
+fn stack_frames(vm: &Vm) -> Vec<Json> {
+    let debug = vm.debug_info();
+    let source = debug.source.as_ref().map(|path| {
+        let name = std::path::Path::new(path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+        json!({ "name": name, "path": path })
+    });
+    let pcs = std::iter::once(vm.pc()).chain(vm.frames().iter().rev().map(|frame| frame.ret - 1));
+    let names = vm.frames().iter().rev().map(|frame| function_name(vm, frame.entry)).chain(std::iter::once("main".to_string()));
+    pcs.zip(names).enumerate().map(|(id, (pc, name))| {
+        let line = debug.line_of(pc);
+        let mut frame = json!({
+            "id": id,
+            "name": name,
+            "line": line.unwrap_or(0),
+            "column": if line.is_some() { 1 } else { 0 },
+            "instructionPointerReference": format!("{:04}", pc),
+        });
+        if let Some(source) = &source {
+            frame["source"] = source.clone();
+        }
+        frame
+    }).collect()
+}
+
+// 函数名取入口地址上的标号
+fn function_name(vm: &Vm, entry: usize) -> String {
+    match vm.debug_info().labels.iter().find(|(_, addr)| *addr == entry) {
+        Some((name, _)) => name.clone(),
+        None => format!("{:04}", entry),
+    }
+}
+
+// 第frame个栈帧（0为最内层）的参数和局部变量
+fn locals(vm: &Vm, frame: usize) -> Option<&[Value]> {
+    let frames = vm.frames();
+    if frame > frames.len() {
+        return None;
+    }
+    // 由外到内各帧的起始位置，main为0
+    let bases: Vec<usize> = std::iter::once(0).chain(frames.iter().map(|f| f.base)).collect();
+    let index = frames.len() - frame;
+    let end = bases.get(index + 1).copied().unwrap_or(vm.stack().len());
+    Some(&vm.stack()[bases[index]..end])
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,165 @@
+// 交互式调试器，`svm debug prog`使用。
+//
+// 基于Vm::run_with的钩子，每条指令执行前检查断点、单步和监视的栈槽，需要停下时读取命令：
+// ```
+// break <地址|标号>     设置断点，简写b
+// delete <地址|标号>    删除断点，简写d
+// step                  执行一条指令，简写s
+// continue              继续执行到下一个断点，简写c
+// stack                 打印整个栈
+// locals                打印当前帧的局部变量
+// pc                    打印当前地址和指令
+// watch <槽位>          监视栈上的槽位，值变化时停下，简写w
+// quit                  结束程序，简写q
+// ```
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use crate::disassembler;
+use crate::error::VmError;
+use crate::instruction::Instruction;
+use crate::vm::{Control, Vm};
+
+const HELP: &str = "\
+break <addr|label>    set a breakpoint (b)
+delete <addr|label>   remove a breakpoint (d)
+step                  execute one instruction (s)
+continue              run until the next breakpoint (c)
+stack                 print the stack
+locals                print the locals of the current frame
+pc                    print the current address and instruction
+watch <slot>          stop when a stack slot changes (w)
+quit                  stop the program (q)";
+
+pub struct Debugger<R, W> {
+    input: R,
+    output: W,
+    breakpoints: BTreeSet<usize>,
+    // 监视的栈槽及其上次显示的值，槽位不存在时为None。
+    // 记录显示的文本而不是Value，数组内容的变化也能发现，旧的对象被回收后也能显示
+    watches: Vec<(usize, Option<String>)>,
+    // 单步执行，下一条指令前停下
+    stepping: bool,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    // 启动后停在第一条指令前
+    pub fn new(input: R, output: W) -> Self {
+        Debugger { input, output, breakpoints: BTreeSet::new(), watches: Vec::new(), stepping: true }
+    }
+
+    // 在调试器的控制下运行vm中已加载的程序，quit时返回Ok
+    pub fn run(&mut self, vm: &mut Vm) -> Result<(), VmError> {
+        let result = vm.run_with(|vm, instruction| {
+            // 输出失败时无法再和用户交互，直接停止
+            self.before(vm, instruction).unwrap_or(Control::Stop)
+        });
+        let _ = match &result {
+            Ok(()) => match vm.exit_code() {
+                Some(code) => writeln!(self.output, "program exited with code {}", code),
+                None if vm.pc() < vm.program().codes.len() => writeln!(self.output, "program stopped"),
+                None => writeln!(self.output, "program finished"),
+            },
+            Err(e) => writeln!(self.output, "runtime error: {}", e),
+        };
+        result
+    }
+
+    fn before(&mut self, vm: &Vm, instruction: &Instruction) -> io::Result<Control> {
+        let mut stop = self.stepping;
+        if self.breakpoints.contains(&vm.pc()) {
+            writeln!(self.output, "breakpoint at {:04}", vm.pc())?;
+            stop = true;
+        }
+        for (slot, last) in &mut self.watches {
+            let current = vm.stack().get(*slot).map(|value| vm.heap().repr(value));
+            if current != *last {
+                writeln!(self.output, "slot {}: {} -> {}", slot, show(last.as_deref()), show(current.as_deref()))?;
+                *last = current;
+                stop = true;
+            }
+        }
+        if !stop {
+            return Ok(Control::Continue);
+        }
+        self.stepping = false;
+        self.print_pc(vm, instruction)?;
+        self.prompt(vm, instruction)
+    }
+
+    // 读取并执行命令，直到step、continue或quit
+    fn prompt(&mut self, vm: &Vm, instruction: &Instruction) -> io::Result<Control> {
+        loop {
+            write!(self.output, "(svm) ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(Control::Stop);
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["step"] | ["s"] => {
+                    self.stepping = true;
+                    return Ok(Control::Continue);
+                }
+                ["continue"] | ["c"] => return Ok(Control::Continue),
+                ["quit"] | ["q"] => return Ok(Control::Stop),
+                ["break", location] | ["b", location] => match resolve(vm, location) {
+                    Some(addr) => {
+                        self.breakpoints.insert(addr);
+                        writeln!(self.output, "breakpoint set at {:04}", addr)?;
+                    }
+                    None => writeln!(self.output, "unknown address or label `{}`", location)?,
+                },
+                ["delete", location] | ["d", location] => match resolve(vm, location) {
+                    Some(addr) if self.breakpoints.remove(&addr) => {
+                        writeln!(self.output, "breakpoint at {:04} deleted", addr)?;
+                    }
+                    _ => writeln!(self.output, "no breakpoint at `{}`", location)?,
+                },
+                ["watch", slot] | ["w", slot] => match slot.parse::<usize>() {
+                    Ok(slot) => {
+                        let value = vm.stack().get(slot).map(|value| vm.heap().repr(value));
+                        writeln!(self.output, "watching slot {} = {}", slot, show(value.as_deref()))?;
+                        self.watches.retain(|(s, _)| *s != slot);
+                        self.watches.push((slot, value));
+                    }
+                    Err(_) => writeln!(self.output, "invalid stack slot `{}`", slot)?,
+                },
+                ["stack"] => {
+                    for (slot, value) in vm.stack().iter().enumerate() {
+                        writeln!(self.output, "{:4}: {}", slot, vm.heap().repr(value))?;
+                    }
+                }
+                ["locals"] => {
+                    let base = vm.frames().last().map_or(0, |frame| frame.base);
+                    for (index, value) in vm.stack()[base..].iter().enumerate() {
+                        writeln!(self.output, "{:4}: {}", index, vm.heap().repr(value))?;
+                    }
+                }
+                ["pc"] => self.print_pc(vm, instruction)?,
+                ["help"] | ["h"] => writeln!(self.output, "{}", HELP)?,
+                _ => writeln!(self.output, "unknown command `{}`, try `help`", line.trim())?,
+            }
+        }
+    }
+
+    // 当前地址、指令和对应的源码行
+    fn print_pc(&mut self, vm: &Vm, instruction: &Instruction) -> io::Result<()> {
+        let text = disassembler::instruction_text(instruction, &vm.program().constants);
+        match vm.debug_info().line_of(vm.pc()) {
+            Some(line) => writeln!(self.output, "{:04}  {:<24} ; line {}", vm.pc(), text, line),
+            None => writeln!(self.output, "{:04}  {}", vm.pc(), text),
+        }
+    }
+}
+
+// 断点位置可以是地址或标号
+fn resolve(vm: &Vm, location: &str) -> Option<usize> {
+    location.parse().ok().or_else(|| vm.debug_info().label_addr(location))
+}
+
+fn show(value: Option<&str>) -> String {
+    value.unwrap_or("<empty>").to_string()
+}
@@ -0,0 +1,162 @@
+// 反汇编器，将字节码还原为每行一条指令的汇编文本。
+//
+// 输出形如：
+// ```
+// L0:
+// 0000  push 1
+// 0002  print
+// 0003  jmp L0
+// ```
+// 紧跟在jmp/call前面的push被视为跳转目标，与操作码合并显示为`jmp 标号`、`call 标号, 参数个数`，
+// callnative与前面压入的参数个数合并显示为`callnative "name", 参数个数`。
+// 条件跳转的绝对目标显示为标号，相对目标显示为`+偏移`并在注释中给出目标。
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use crate::assembler::escape;
+use crate::instruction::*;
+use crate::program::Program;
+use crate::value::Value;
+
+pub fn disassemble(program: &Program) -> String {
+    let decoded = decode_all(&program.codes);
+    let boundaries: BTreeSet<usize> = decoded.iter().map(|(addr, _)| *addr).collect();
+    let targets = jump_targets(&decoded, &boundaries);
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < decoded.len() {
+        let addr = decoded[i].0;
+        if targets.contains(&addr) {
+            writeln!(out, "{}:", label(addr)).unwrap();
+        }
+        let (text, count) = format_at(program, &decoded, i, &targets);
+        writeln!(out, "{:04}  {}", addr, text).unwrap();
+        i += count;
+    }
+    out
+}
+
+// 按顺序解码全部指令，遇到非法字节时跳过一个字节继续
+fn decode_all(codes: &[u8]) -> Vec<(usize, Result<Instruction, DecodeError>)> {
+    let mut decoded = Vec::new();
+    let mut addr = 0;
+    while addr < codes.len() {
+        let result = Instruction::decode(codes, addr);
+        let len = match &result {
+            Ok(instruction) => instruction.len,
+            Err(_) => 1,
+        };
+        decoded.push((addr, result));
+        addr += len;
+    }
+    decoded
+}
+
+// 收集所有落在指令边界上的跳转目标
+fn jump_targets(decoded: &[(usize, Result<Instruction, DecodeError>)], boundaries: &BTreeSet<usize>) -> BTreeSet<usize> {
+    let pushed = decoded.windows(2).filter_map(|w| jump_pair(&w[0].1, &w[1].1).map(|(target, _)| target as i64));
+    let immediate = decoded.iter().filter_map(|(addr, result)| match result {
+        Ok(Instruction { operand: Operand::Target(target), .. }) => Some(target.resolve(*addr)),
+        _ => None,
+    });
+    pushed.chain(immediate)
+        .filter(|&target| target >= 0 && boundaries.contains(&(target as usize)))
+        .map(|target| target as usize)
+        .collect()
+}
+
+// `push 整数; 操作码`这样的两条指令
+fn operand_pair(first: &Result<Instruction, DecodeError>, second: &Result<Instruction, DecodeError>) -> Option<(i64, OpCode)> {
+    match (first, second) {
+        (Ok(Instruction { operand: Operand::Int(v), opcode: OpCode::Push, .. }), Ok(next)) => Some((*v, next.opcode)),
+        _ => None,
+    }
+}
+
+// 如果是`push 目标地址; jmp/call`，返回目标地址与操作码
+fn jump_pair(first: &Result<Instruction, DecodeError>, second: &Result<Instruction, DecodeError>) -> Option<(usize, OpCode)> {
+    match operand_pair(first, second) {
+        Some((target, op @ OpCode::Jmp)) | Some((target, op @ OpCode::Call)) if target >= 0 => Some((target as usize, op)),
+        _ => None,
+    }
+}
+
+// 格式化decoded[i]开始的指令，返回文本和合并的指令条数
+fn format_at(
+    program: &Program,
+    decoded: &[(usize, Result<Instruction, DecodeError>)],
+    i: usize,
+    targets: &BTreeSet<usize>,
+) -> (String, usize) {
+    let target_text = |target: usize| if targets.contains(&target) { label(target) } else { target.to_string() };
+    // 被合并的后续指令本身不能是跳转目标
+    let fusable = |count: usize| i + count <= decoded.len() && (1..count).all(|k| !targets.contains(&decoded[i + k].0));
+    let at = |k: usize| &decoded[i + k].1;
+
+    if fusable(3) {
+        if let (Some((argc, OpCode::Push)), Some((target, OpCode::Call))) = (operand_pair(at(0), at(1)), jump_pair(at(1), at(2))) {
+            return (format!("call {}, {}", target_text(target), argc), 3);
+        }
+    }
+    if fusable(2) {
+        match operand_pair(at(0), at(1)) {
+            Some((target, OpCode::Jmp)) if target >= 0 => return (format!("jmp {}", target_text(target as usize)), 2),
+            Some((index, op @ OpCode::LoadLocal)) | Some((index, op @ OpCode::StoreLocal)) => {
+                return (format!("{} {}", op.mnemonic(), index), 2);
+            }
+            Some((argc, OpCode::CallNative)) => {
+                if let Ok(instruction) = at(1) {
+                    return (format!("{}, {}", instruction_text(instruction, &program.constants), argc), 2);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let text = match at(0) {
+        Err(DecodeError::InvalidOpcode(byte)) => format!(".byte {}    ; invalid opcode", byte),
+        Err(DecodeError::TruncatedOperand(op)) => format!(".byte {}    ; truncated {}", *op as u8, op.mnemonic()),
+        Ok(instruction) => match (instruction.operand, decoded.get(i + 1).and_then(|next| jump_pair(at(0), &next.1))) {
+            // 作为跳转目标压栈的地址也显示为标号
+            (Operand::Int(_), Some((target, _))) => format!("push {}", target_text(target)),
+            (Operand::Target(Target::Absolute(addr)), _) if addr >= 0 => {
+                format!("{} {}", instruction.opcode.mnemonic(), target_text(addr as usize))
+            }
+            (Operand::Target(target @ Target::Relative(_)), _) => match target.resolve(decoded[i].0) {
+                addr if addr >= 0 && targets.contains(&(addr as usize)) => {
+                    format!("{}    ; {}", instruction_text(instruction, &program.constants), label(addr as usize))
+                }
+                _ => instruction_text(instruction, &program.constants),
+            },
+            _ => instruction_text(instruction, &program.constants),
+        },
+    };
+    (text, 1)
+}
+
+// 单条指令的汇编文本，不做跳转目标的合并
+pub fn instruction_text(instruction: &Instruction, constants: &[Value]) -> String {
+    match instruction.operand {
+        Operand::Int(v) => format!("{} {}", instruction.opcode.mnemonic(), v),
+        Operand::Const(index) => match constants.get(index) {
+            Some(value) => format!("{} {}", instruction.opcode.mnemonic(), literal(value)),
+            None => format!("{} #{}    ; invalid constant", instruction.opcode.mnemonic(), index),
+        },
+        Operand::Target(Target::Absolute(addr)) => format!("{} {}", instruction.opcode.mnemonic(), addr),
+        Operand::Target(Target::Relative(offset)) => format!("{} {:+}", instruction.opcode.mnemonic(), offset),
+        Operand::None => instruction.opcode.mnemonic().to_string(),
+    }
+}
+
+// 常量在汇编中的写法
+pub(crate) fn literal(value: &Value) -> String {
+    match value {
+        Value::Str(s) => escape(s),
+        other => other.to_string(),
+    }
+}
+
+fn label(addr: usize) -> String {
+    format!("L{}", addr)
+}
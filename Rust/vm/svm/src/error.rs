@@ -0,0 +1,111 @@
+use std::fmt;
+use crate::instruction::OpCode;
+use crate::limits::Limit;
+use crate::verifier::VerifyError;
+
+// 虚拟机执行过程中的错误，pc为出错指令所在的地址
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    // 执行前的静态校验没有通过
+    Verify(VerifyError),
+    StackUnderflow { pc: usize, opcode: OpCode },
+    InvalidOpcode { pc: usize, byte: u8 },
+    TruncatedOperand { pc: usize, opcode: OpCode },
+    DivisionByZero { pc: usize, opcode: OpCode },
+    ArithmeticOverflow { pc: usize, opcode: OpCode },
+    TypeMismatch { pc: usize, opcode: OpCode, expected: &'static str, found: &'static str },
+    JumpOutOfRange { pc: usize, opcode: OpCode, target: i64 },
+    CallStackUnderflow { pc: usize, opcode: OpCode },
+    // 超出Limits中的某项资源限制
+    LimitExceeded { pc: usize, opcode: OpCode, limit: Limit },
+    LocalOutOfRange { pc: usize, opcode: OpCode, index: i64 },
+    ConstantOutOfRange { pc: usize, opcode: OpCode, index: usize },
+    Io { pc: usize, opcode: OpCode, message: String },
+    UnknownNative { pc: usize, opcode: OpCode, name: String },
+    // 宿主函数返回的错误
+    Native { pc: usize, opcode: OpCode, name: String, message: String },
+    // 数组下标或子串范围越界，len为数组或字符串的长度
+    IndexOutOfRange { pc: usize, opcode: OpCode, index: i64, len: usize },
+    // 数组或子串的长度为负数
+    InvalidLength { pc: usize, opcode: OpCode, length: i64 },
+}
+
+impl VmError {
+    pub fn pc(&self) -> usize {
+        match self {
+            VmError::Verify(e) => e.pc,
+            VmError::StackUnderflow { pc, .. }
+            | VmError::InvalidOpcode { pc, .. }
+            | VmError::TruncatedOperand { pc, .. }
+            | VmError::DivisionByZero { pc, .. }
+            | VmError::ArithmeticOverflow { pc, .. }
+            | VmError::JumpOutOfRange { pc, .. }
+            | VmError::CallStackUnderflow { pc, .. }
+            | VmError::LimitExceeded { pc, .. }
+            | VmError::LocalOutOfRange { pc, .. }
+            | VmError::ConstantOutOfRange { pc, .. }
+            | VmError::TypeMismatch { pc, .. }
+            | VmError::Io { pc, .. }
+            | VmError::UnknownNative { pc, .. }
+            | VmError::Native { pc, .. }
+            | VmError::IndexOutOfRange { pc, .. }
+            | VmError::InvalidLength { pc, .. } => *pc,
+        }
+    }
+
+    // 出错的操作码，非法字节无法解析为操作码时返回None
+    pub fn opcode(&self) -> Option<OpCode> {
+        match self {
+            VmError::Verify(e) => e.opcode,
+            VmError::InvalidOpcode { .. } => None,
+            VmError::StackUnderflow { opcode, .. }
+            | VmError::TruncatedOperand { opcode, .. }
+            | VmError::DivisionByZero { opcode, .. }
+            | VmError::ArithmeticOverflow { opcode, .. }
+            | VmError::JumpOutOfRange { opcode, .. }
+            | VmError::CallStackUnderflow { opcode, .. }
+            | VmError::LimitExceeded { opcode, .. }
+            | VmError::LocalOutOfRange { opcode, .. }
+            | VmError::ConstantOutOfRange { opcode, .. }
+            | VmError::TypeMismatch { opcode, .. }
+            | VmError::Io { opcode, .. }
+            | VmError::UnknownNative { opcode, .. }
+            | VmError::Native { opcode, .. }
+            | VmError::IndexOutOfRange { opcode, .. }
+            | VmError::InvalidLength { opcode, .. } => Some(*opcode),
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::Verify(e) => return write!(f, "verification failed: {}", e),
+            VmError::InvalidOpcode { pc, byte } => {
+                return write!(f, "invalid opcode {} at {:04}", byte, pc);
+            }
+            VmError::StackUnderflow { .. } => write!(f, "stack underflow")?,
+            VmError::TruncatedOperand { .. } => write!(f, "truncated operand")?,
+            VmError::DivisionByZero { .. } => write!(f, "division by zero")?,
+            VmError::ArithmeticOverflow { .. } => write!(f, "arithmetic overflow")?,
+            VmError::JumpOutOfRange { target, .. } => write!(f, "jump target {} out of range", target)?,
+            VmError::CallStackUnderflow { .. } => write!(f, "return without call")?,
+            VmError::LimitExceeded { limit, .. } => write!(f, "{}", limit)?,
+            VmError::LocalOutOfRange { index, .. } => write!(f, "local {} out of range", index)?,
+            VmError::ConstantOutOfRange { index, .. } => write!(f, "constant {} out of range", index)?,
+            VmError::TypeMismatch { expected, found, .. } => write!(f, "type mismatch: expected {}, found {}", expected, found)?,
+            VmError::Io { message, .. } => write!(f, "io error: {}", message)?,
+            VmError::UnknownNative { name, .. } => write!(f, "unknown native function `{}`", name)?,
+            VmError::Native { name, message, .. } => write!(f, "native function `{}` failed: {}", name, message)?,
+            VmError::IndexOutOfRange { index, len, .. } => write!(f, "index {} out of range for length {}", index, len)?,
+            VmError::InvalidLength { length, .. } => write!(f, "invalid length {}", length)?,
+        }
+        // 除InvalidOpcode外都有合法的操作码
+        if let Some(opcode) = self.opcode() {
+            write!(f, " at {:04} ({})", self.pc(), opcode.mnemonic())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VmError {}
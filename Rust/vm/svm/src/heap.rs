@@ -0,0 +1,225 @@
+// 虚拟机管理的堆，存放运行时创建的字符串和数组，用标记-清除算法回收。
+//
+// 栈上的Value::Obj是对象在堆中的句柄，常量池中的字符串在PushConst时才分配到堆上。
+// 分配时若已分配的字节数超过阈值，先以操作数栈为根做一次回收（各调用帧的参数和局部变量
+// 都在栈上）；压力模式下每次分配都回收，用于在测试中暴露遗漏的根。
+
+use std::mem;
+use crate::value::Value;
+
+// 第一次回收的阈值，之后为回收后存活字节数的两倍
+const INITIAL_THRESHOLD: usize = 1 << 20;
+
+// 堆中对象的句柄
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(usize);
+
+impl ObjRef {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Str(String),
+    // 数组创建后长度不变，元素可以是任意值，包括其他数组
+    Array(Vec<Value>),
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Str(_) => "string",
+            Object::Array(_) => "array",
+        }
+    }
+
+    // 计入堆大小的字节数，只算内容
+    pub fn size(&self) -> usize {
+        match self {
+            Object::Str(s) => s.len(),
+            Object::Array(items) => items.len() * mem::size_of::<Value>(),
+        }
+    }
+}
+
+// 垃圾回收的统计，每次run重新开始
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    // 回收的次数
+    pub collections: u64,
+    // 分配和回收的对象个数
+    pub allocated: u64,
+    pub freed: u64,
+    // 当前堆中的对象个数和字节数，包括尚未回收的垃圾
+    pub objects: usize,
+    pub bytes: usize,
+    // 字节数的峰值
+    pub peak_bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct Heap {
+    // 下标为ObjRef，回收后的位置为None，留给之后的分配复用
+    objects: Vec<Option<Object>>,
+    free: Vec<usize>,
+    threshold: usize,
+    stress: bool,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap { objects: Vec::new(), free: Vec::new(), threshold: INITIAL_THRESHOLD, stress: false, stats: GcStats::default() }
+    }
+
+    // 压力模式：每次分配前都做一次完整的回收
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    // 释放所有对象并清空统计
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.free.clear();
+        self.threshold = INITIAL_THRESHOLD;
+        self.stats = GcStats::default();
+    }
+
+    // 分配一个对象，roots之外不能有仍在使用的句柄
+    pub fn alloc(&mut self, object: Object, roots: &[Value]) -> ObjRef {
+        if self.stress || self.stats.bytes + object.size() > self.threshold {
+            self.collect(roots);
+        }
+        self.stats.allocated += 1;
+        self.stats.objects += 1;
+        self.stats.bytes += object.size();
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.bytes);
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(object);
+                ObjRef(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                ObjRef(self.objects.len() - 1)
+            }
+        }
+    }
+
+    // 从roots出发标记所有可达对象，释放其余的对象
+    pub fn collect(&mut self, roots: &[Value]) {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<ObjRef> = roots.iter().filter_map(Value::as_obj).collect();
+        while let Some(r) = pending.pop() {
+            if mem::replace(&mut marked[r.0], true) {
+                continue;
+            }
+            if let Some(Object::Array(items)) = &self.objects[r.0] {
+                pending.extend(items.iter().filter_map(Value::as_obj).filter(|item| !marked[item.0]));
+            }
+        }
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            if marked[index] {
+                continue;
+            }
+            if let Some(object) = slot.take() {
+                self.stats.freed += 1;
+                self.stats.objects -= 1;
+                self.stats.bytes -= object.size();
+                self.free.push(index);
+            }
+        }
+        self.stats.collections += 1;
+        self.threshold = INITIAL_THRESHOLD.max(self.stats.bytes * 2);
+    }
+
+    pub fn get(&self, r: ObjRef) -> &Object {
+        self.objects[r.0].as_ref().expect("dangling object reference")
+    }
+
+    pub fn get_mut(&mut self, r: ObjRef) -> &mut Object {
+        self.objects[r.0].as_mut().expect("dangling object reference")
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    // 当前堆中的字节数
+    pub fn bytes(&self) -> usize {
+        self.stats.bytes
+    }
+
+    // 字符串的内容，常量池中的Value::Str和堆上的字符串都可以
+    pub fn str<'a>(&'a self, value: &'a Value) -> Option<&'a str> {
+        match value {
+            Value::Str(s) => Some(s),
+            Value::Obj(r) => match self.get(*r) {
+                Object::Str(s) => Some(s),
+                Object::Array(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Obj(r) => self.get(*r).type_name(),
+            value => value.type_name(),
+        }
+    }
+
+    // 交给宿主的值：堆上的字符串复制为Value::Str，数组仍是句柄
+    pub fn export(&self, value: &Value) -> Value {
+        match self.str(value) {
+            Some(s) => Value::Str(s.to_string()),
+            None => value.clone(),
+        }
+    }
+
+    // Print输出的形式，字符串不加引号
+    pub fn display(&self, value: &Value) -> String {
+        match self.str(value) {
+            Some(s) => s.to_string(),
+            None => self.repr(value),
+        }
+    }
+
+    // 调试器和跟踪中的形式，字符串加上引号，数组递归展开，环用[...]表示
+    pub fn repr(&self, value: &Value) -> String {
+        let mut out = String::new();
+        self.write_repr(value, &mut Vec::new(), &mut out);
+        out
+    }
+
+    fn write_repr(&self, value: &Value, visiting: &mut Vec<ObjRef>, out: &mut String) {
+        match value {
+            Value::Str(s) => out.push_str(&crate::assembler::escape(s)),
+            Value::Obj(r) => match self.get(*r) {
+                Object::Str(s) => out.push_str(&crate::assembler::escape(s)),
+                Object::Array(_) if visiting.contains(r) => out.push_str("[...]"),
+                Object::Array(items) => {
+                    visiting.push(*r);
+                    out.push('[');
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        self.write_repr(item, visiting, out);
+                    }
+                    out.push(']');
+                    visiting.pop();
+                }
+            },
+            value => out.push_str(&value.to_string()),
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
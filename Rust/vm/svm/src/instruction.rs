@@ -1,6 +1,10 @@
-pub const IR_OFFSET: u8 = 16;
+use std::convert::TryFrom;
+use crate::leb128;
 
-#[derive(Eq, PartialEq, Hash, Debug)]
+// 字节码由一条条指令组成，每条指令是一个字节的操作码，后面跟着该操作码的立即数：
+// Push后面是LEB128编码的有符号整数，PushConst和CallNative后面是LEB128编码的常量池下标，
+// JmpIfZero和JmpIfNonZero后面是跳转目标（见Target），其余操作码没有立即数，操作数都从栈上取。
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
 pub enum OpCode {
     Add = 0,
     Sub = 1,
@@ -13,29 +17,244 @@ pub enum OpCode {
     Return,
     Call,
     Exit,
+    LoadLocal,
+    StoreLocal,
+    Push,
+    PushConst,
+    // 调用宿主注册的函数，立即数为函数名在常量池中的下标
+    CallNative,
+    // 堆上的数组和字符串
+    NewArray,
+    ArrayGet,
+    ArraySet,
+    ArrayLen,
+    Concat,
+    Substring,
+    // 比较栈顶的两个值，压入bool
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // 按真假做逻辑运算，压入bool
+    And,
+    Or,
+    Not,
+    // 弹出条件，为假（nil、false、0、0.0）或为真时跳到立即数给出的目标
+    JmpIfZero,
+    JmpIfNonZero,
+    // 弹出int或float，压入它的相反数
+    Neg,
 }
 
+pub const OPCODES: [OpCode; 34] = [
+    OpCode::Add,
+    OpCode::Sub,
+    OpCode::Mul,
+    OpCode::Div,
+    OpCode::Print,
+    OpCode::Jmp,
+    OpCode::If,
+    OpCode::ReadLine,
+    OpCode::Return,
+    OpCode::Call,
+    OpCode::Exit,
+    OpCode::LoadLocal,
+    OpCode::StoreLocal,
+    OpCode::Push,
+    OpCode::PushConst,
+    OpCode::CallNative,
+    OpCode::NewArray,
+    OpCode::ArrayGet,
+    OpCode::ArraySet,
+    OpCode::ArrayLen,
+    OpCode::Concat,
+    OpCode::Substring,
+    OpCode::Eq,
+    OpCode::Ne,
+    OpCode::Lt,
+    OpCode::Le,
+    OpCode::Gt,
+    OpCode::Ge,
+    OpCode::And,
+    OpCode::Or,
+    OpCode::Not,
+    OpCode::JmpIfZero,
+    OpCode::JmpIfNonZero,
+    OpCode::Neg,
+];
+
 pub fn is_opcode(opcode: u8) -> bool {
-    let min = OpCode::Add as u8;
-    let max = OpCode::Exit as u8;
-    opcode >= min && opcode <= max
-}
-
-impl From<u8> for OpCode {
-    fn from(v: u8) -> Self {
-        match v {
-            0 => OpCode::Add,
-            1 => OpCode::Sub,
-            2 => OpCode::Mul,
-            3 => OpCode::Div,
-            4 => OpCode::Print,
-            5 => OpCode::Jmp,
-            6 => OpCode::If,
-            7 => OpCode::ReadLine,
-            8 => OpCode::Return,
-            9 => OpCode::Call,
-            10 => OpCode::Exit,
-            _ => panic!("invalid opcode."),
+    OpCode::try_from(opcode).is_ok()
+}
+
+// 操作码后面跟的立即数类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    None,
+    Int,
+    Const,
+    Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Int(i64),
+    Const(usize),
+    Target(Target),
+}
+
+// 条件跳转的目标，可以是绝对地址，也可以是相对跳转指令本身地址的偏移。
+// 编码为一个有符号LEB128整数，最低位为1表示相对偏移，其余位为地址或偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Absolute(i64),
+    Relative(i64),
+}
+
+impl Target {
+    // 位于pc处的跳转指令的目标地址
+    pub fn resolve(self, pc: usize) -> i64 {
+        match self {
+            Target::Absolute(addr) => addr,
+            Target::Relative(offset) => (pc as i64).wrapping_add(offset),
         }
     }
-}
\ No newline at end of file
+
+    fn raw(self) -> i64 {
+        match self {
+            Target::Absolute(addr) => addr.wrapping_shl(1),
+            Target::Relative(offset) => offset.wrapping_shl(1) | 1,
+        }
+    }
+
+    fn from_raw(raw: i64) -> Target {
+        if raw & 1 == 0 {
+            Target::Absolute(raw >> 1)
+        } else {
+            Target::Relative(raw >> 1)
+        }
+    }
+}
+
+impl OpCode {
+    // 汇编语言中使用的助记符
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::Add => "add",
+            OpCode::Sub => "sub",
+            OpCode::Mul => "mul",
+            OpCode::Div => "div",
+            OpCode::Print => "print",
+            OpCode::Jmp => "jmp",
+            OpCode::If => "if",
+            OpCode::ReadLine => "readline",
+            OpCode::Return => "return",
+            OpCode::Call => "call",
+            OpCode::Exit => "exit",
+            OpCode::LoadLocal => "loadlocal",
+            OpCode::StoreLocal => "storelocal",
+            OpCode::Push => "push",
+            OpCode::PushConst => "pushconst",
+            OpCode::CallNative => "callnative",
+            OpCode::NewArray => "newarray",
+            OpCode::ArrayGet => "arrayget",
+            OpCode::ArraySet => "arrayset",
+            OpCode::ArrayLen => "arraylen",
+            OpCode::Concat => "concat",
+            OpCode::Substring => "substring",
+            OpCode::Eq => "eq",
+            OpCode::Ne => "ne",
+            OpCode::Lt => "lt",
+            OpCode::Le => "le",
+            OpCode::Gt => "gt",
+            OpCode::Ge => "ge",
+            OpCode::And => "and",
+            OpCode::Or => "or",
+            OpCode::Not => "not",
+            OpCode::JmpIfZero => "jmpifzero",
+            OpCode::JmpIfNonZero => "jmpifnonzero",
+            OpCode::Neg => "neg",
+        }
+    }
+
+    pub fn from_mnemonic(s: &str) -> Option<OpCode> {
+        OPCODES.iter().find(|op| op.mnemonic().eq_ignore_ascii_case(s)).copied()
+    }
+
+    pub fn operand_kind(&self) -> OperandKind {
+        match self {
+            OpCode::Push => OperandKind::Int,
+            OpCode::PushConst | OpCode::CallNative => OperandKind::Const,
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => OperandKind::Target,
+            _ => OperandKind::None,
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(v: u8) -> Result<Self, u8> {
+        OPCODES.get(v as usize).copied().ok_or(v)
+    }
+}
+
+// 解码后的一条指令，len为其在字节码中占用的字节数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: OpCode,
+    pub operand: Operand,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidOpcode(u8),
+    TruncatedOperand(OpCode),
+}
+
+impl Instruction {
+    pub fn new(opcode: OpCode, operand: Operand) -> Self {
+        let len = 1 + match operand {
+            Operand::None => 0,
+            Operand::Int(v) => leb128::signed_len(v),
+            Operand::Const(i) => leb128::unsigned_len(i as u64),
+            Operand::Target(target) => leb128::signed_len(target.raw()),
+        };
+        Instruction { opcode, operand, len }
+    }
+
+    // 解码codes[pc..]处的一条指令
+    pub fn decode(codes: &[u8], pc: usize) -> Result<Instruction, DecodeError> {
+        let opcode = OpCode::try_from(codes[pc]).map_err(DecodeError::InvalidOpcode)?;
+        let (operand, len) = match opcode.operand_kind() {
+            OperandKind::None => (Operand::None, 0),
+            OperandKind::Int => {
+                let (v, len) = leb128::read_signed(codes, pc + 1).ok_or(DecodeError::TruncatedOperand(opcode))?;
+                (Operand::Int(v), len)
+            }
+            OperandKind::Const => {
+                let (v, len) = leb128::read_unsigned(codes, pc + 1).ok_or(DecodeError::TruncatedOperand(opcode))?;
+                (Operand::Const(v as usize), len)
+            }
+            OperandKind::Target => {
+                let (v, len) = leb128::read_signed(codes, pc + 1).ok_or(DecodeError::TruncatedOperand(opcode))?;
+                (Operand::Target(Target::from_raw(v)), len)
+            }
+        };
+        Ok(Instruction { opcode, operand, len: 1 + len })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode as u8);
+        match self.operand {
+            Operand::None => {}
+            Operand::Int(v) => leb128::write_signed(out, v),
+            Operand::Const(i) => leb128::write_unsigned(out, i as u64),
+            Operand::Target(target) => leb128::write_signed(out, target.raw()),
+        }
+    }
+}
@@ -0,0 +1,686 @@
+// 用cranelift把只用到整数的函数编译为机器码，需要打开jit特性：cargo build --features jit。
+//
+// 栈式后端执行call时统计每个函数被调用的次数，达到阈值后尝试编译该函数及其直接或间接调用的函数。
+// 能编译的函数只用到push、int、bool和nil常量、整数的算术和比较、逻辑运算、if、跳转、常量下标的loadlocal/storelocal、
+// 目标唯一的call和return，参数和返回值都是int；含有其他指令的函数一直由解释器执行。
+//
+// 栈上相对当前帧的第d个位置对应cranelift的变量d，bool在机器码中为0或1。溢出、除零、调用过深等
+// 解释器会报错的情况下，机器码不报错而是放弃本次调用，由解释器从头重新执行这次调用。
+// 能编译的函数没有副作用，重新执行的结果和错误信息与一开始就解释执行相同。
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::{self, condcodes::IntCC, types::I64, AbiParam, Block, InstBuilder, MemFlags};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module, ModuleError};
+use crate::instruction::*;
+use crate::program::Program;
+use crate::value::Value;
+use crate::verifier::{self, Analysis};
+
+// 默认的编译阈值：函数被调用这么多次后编译
+pub const DEFAULT_THRESHOLD: u32 = 100;
+// 机器码中最多嵌套调用的层数，更深的调用交给解释器，避免耗尽线程的栈
+pub const MAX_NATIVE_DEPTH: usize = 1024;
+// 同一函数的机器码放弃这么多次后不再使用
+const MAX_BAILOUTS: u32 = 8;
+
+// 解释器进入机器码的入口：(放弃标志的地址, 还允许嵌套调用的层数, 参数数组) -> 返回值
+type Entry = unsafe extern "C" fn(*mut i64, i64, *const i64) -> i64;
+
+enum State {
+    Native { entry: Entry, bailouts: u32 },
+    // 不能编译，或者机器码放弃的次数太多
+    Interpreted,
+}
+
+pub struct Jit {
+    threshold: Option<u32>,
+    // 第一次编译时创建
+    module: Option<JITModule>,
+    analysis: Option<Analysis>,
+    // 函数入口地址 -> 尚未决定是否编译时被调用的次数
+    calls: HashMap<usize, u32>,
+    functions: HashMap<usize, State>,
+    // 校验或编译出错后不再编译
+    disabled: bool,
+}
+
+impl Jit {
+    // threshold为None时不编译
+    pub fn new(threshold: Option<u32>) -> Self {
+        Jit {
+            threshold,
+            module: None,
+            analysis: None,
+            calls: HashMap::new(),
+            functions: HashMap::new(),
+            disabled: false,
+        }
+    }
+
+    pub fn threshold(&self) -> Option<u32> {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: Option<u32>) {
+        self.threshold = threshold;
+    }
+
+    // 换了程序，丢弃已经编译的机器码和调用计数
+    pub fn clear(&mut self) {
+        *self = Jit::new(self.threshold);
+    }
+
+    // 已经编译为机器码的函数的入口地址，按地址排列
+    pub fn functions(&self) -> Vec<usize> {
+        let mut entries: Vec<usize> = self.functions.iter()
+            .filter(|(_, state)| matches!(state, State::Native { .. }))
+            .map(|(&entry, _)| entry)
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    // 以args调用entry处的函数，depth为还允许嵌套调用的层数。
+    // 返回None时函数没有执行（未编译、不能编译或机器码放弃），由解释器执行这次调用
+    pub fn call(&mut self, program: &Program, entry: usize, args: &[i64], depth: usize) -> Option<i64> {
+        let threshold = self.threshold?;
+        if !self.functions.contains_key(&entry) {
+            if self.disabled {
+                return None;
+            }
+            let calls = self.calls.entry(entry).or_insert(0);
+            *calls += 1;
+            if *calls < threshold {
+                return None;
+            }
+            self.calls.remove(&entry);
+            if !self.compile(program, entry) {
+                self.functions.insert(entry, State::Interpreted);
+                return None;
+            }
+        }
+        let state = self.functions.get_mut(&entry)?;
+        let (entry, bailouts) = match state {
+            State::Native { entry, bailouts } => (*entry, bailouts),
+            State::Interpreted => return None,
+        };
+        let mut bailed = 0;
+        // 机器码只读写参数数组和放弃标志，参数个数与函数一致
+        let result = unsafe { entry(&mut bailed, depth as i64, args.as_ptr()) };
+        if bailed == 0 {
+            return Some(result);
+        }
+        *bailouts += 1;
+        if *bailouts >= MAX_BAILOUTS {
+            *state = State::Interpreted;
+        }
+        None
+    }
+
+    // 编译entry及其调用的函数，其中有不能编译的函数时返回false
+    fn compile(&mut self, program: &Program, entry: usize) -> bool {
+        if self.analysis.is_none() {
+            match verifier::verify(program) {
+                Ok(analysis) => self.analysis = Some(analysis),
+                Err(_) => {
+                    self.disabled = true;
+                    return false;
+                }
+            }
+        }
+        let analysis = self.analysis.as_ref().unwrap();
+        let mut bodies = BTreeMap::new();
+        let mut pending = vec![entry];
+        while let Some(function) = pending.pop() {
+            if bodies.contains_key(&function) {
+                continue;
+            }
+            match self.functions.get(&function) {
+                Some(State::Native { .. }) => continue,
+                Some(State::Interpreted) => return false,
+                None => {}
+            }
+            let body = match scan(program, analysis, function) {
+                Some(body) => body,
+                None => return false,
+            };
+            pending.extend(body.calls.values().map(|&(callee, _)| callee));
+            bodies.insert(function, body);
+        }
+
+        if self.module.is_none() {
+            let builder = JITBuilder::with_flags(&[("opt_level", "speed")], default_libcall_names());
+            match builder {
+                Ok(builder) => self.module = Some(JITModule::new(builder)),
+                Err(e) => return self.fail(e),
+            }
+        }
+        match define(self.module.as_mut().unwrap(), &bodies) {
+            Ok(entries) => {
+                for (function, entry) in entries {
+                    log::debug!("jit: compiled function at {:04}", function);
+                    self.functions.insert(function, State::Native { entry, bailouts: 0 });
+                }
+                true
+            }
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn fail(&mut self, error: impl std::fmt::Display) -> bool {
+        log::warn!("jit: {}, falling back to the interpreter", error);
+        self.disabled = true;
+        false
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // 机器码的入口随self一起丢弃，不会再被调用
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+// 栈位置上的值在机器码中都是i64，类型只在编译时区分。nil只用于局部变量赋值前的初值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Int,
+    Bool,
+    Nil,
+}
+
+// 栈位置的类型，以及编译时已知的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    kind: Kind,
+    constant: Option<i64>,
+}
+
+impl Slot {
+    fn int(constant: Option<i64>) -> Self {
+        Slot { kind: Kind::Int, constant }
+    }
+
+    fn bool() -> Self {
+        Slot { kind: Kind::Bool, constant: None }
+    }
+
+    // 两条路径汇合，类型不同时不能编译
+    fn join(self, other: Slot) -> Option<Slot> {
+        if self.kind != other.kind {
+            return None;
+        }
+        Some(Slot { kind: self.kind, constant: if self.constant == other.constant { self.constant } else { None } })
+    }
+}
+
+// 能编译的函数
+struct Body {
+    entry: usize,
+    argc: usize,
+    // 函数中的指令，以及每条指令执行前的栈，按地址排列
+    instructions: BTreeMap<usize, Instruction>,
+    stacks: BTreeMap<usize, Vec<Slot>>,
+    // push和pushconst指令的地址 -> 压入的值，bool为0或1，nil为0
+    pushes: BTreeMap<usize, i64>,
+    // 基本块的起点
+    leaders: BTreeSet<usize>,
+    // 跳转指令的地址 -> 目标地址
+    jumps: BTreeMap<usize, Vec<usize>>,
+    // call指令的地址 -> (被调用函数, 参数个数)
+    calls: BTreeMap<usize, (usize, usize)>,
+}
+
+impl Body {
+    // 栈上最多同时有几个值
+    fn slots(&self) -> usize {
+        self.stacks.values().map(|stack| stack.len() + 1).max().unwrap_or(0).max(self.argc)
+    }
+}
+
+fn pop(stack: &mut Vec<Slot>, kind: Kind) -> Option<Slot> {
+    stack.pop().filter(|slot| slot.kind == kind)
+}
+
+// 从入口开始找出函数的全部指令，推导每个栈位置的类型。遇到不支持的指令或类型时返回None
+fn scan(program: &Program, analysis: &Analysis, entry: usize) -> Option<Body> {
+    let argc = *analysis.functions.get(&entry)?;
+    let mut body = Body {
+        entry,
+        argc,
+        instructions: BTreeMap::new(),
+        stacks: BTreeMap::new(),
+        pushes: BTreeMap::new(),
+        leaders: BTreeSet::new(),
+        jumps: BTreeMap::new(),
+        calls: BTreeMap::new(),
+    };
+    body.stacks.insert(entry, vec![Slot::int(None); argc]);
+    body.leaders.insert(entry);
+    let mut worklist = vec![entry];
+    while let Some(pc) = worklist.pop() {
+        let instruction = *analysis.instructions.get(&pc)?;
+        body.instructions.insert(pc, instruction);
+        let mut stack = body.stacks[&pc].clone();
+        let next = pc + instruction.len;
+        let mut successors = vec![next];
+        match instruction.opcode {
+            OpCode::Push | OpCode::PushConst => {
+                let slot = match (instruction.operand, &program.constants[..]) {
+                    (Operand::Int(v), _) => Slot::int(Some(v)),
+                    (Operand::Const(index), constants) => match constants.get(index)? {
+                        Value::Int(v) => Slot::int(Some(*v)),
+                        Value::Bool(b) => Slot { kind: Kind::Bool, constant: Some(*b as i64) },
+                        Value::Nil => Slot { kind: Kind::Nil, constant: Some(0) },
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                body.pushes.insert(pc, slot.constant.unwrap());
+                stack.push(slot);
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => {
+                pop(&mut stack, Kind::Int)?;
+                pop(&mut stack, Kind::Int)?;
+                stack.push(Slot::int(None));
+            }
+            OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge => {
+                pop(&mut stack, Kind::Int)?;
+                pop(&mut stack, Kind::Int)?;
+                stack.push(Slot::bool());
+            }
+            // 类型不同的值总是不相等，这里不处理
+            OpCode::Eq | OpCode::Ne => {
+                let second = stack.pop()?;
+                pop(&mut stack, second.kind)?;
+                stack.push(Slot::bool());
+            }
+            OpCode::And | OpCode::Or => {
+                stack.pop()?;
+                stack.pop()?;
+                stack.push(Slot::bool());
+            }
+            OpCode::Not => {
+                stack.pop()?;
+                stack.push(Slot::bool());
+            }
+            OpCode::Neg => {
+                pop(&mut stack, Kind::Int)?;
+                stack.push(Slot::int(None));
+            }
+            OpCode::If => {
+                let f = stack.pop()?;
+                let t = stack.pop()?;
+                stack.pop()?;
+                stack.push(t.join(f)?);
+            }
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                stack.pop()?;
+                let targets: Vec<usize> = analysis.targets.get(&pc)?.iter().copied().collect();
+                successors.extend(&targets);
+                body.leaders.extend(&successors);
+                body.jumps.insert(pc, targets);
+            }
+            // 目标可以有多个（来自if），按栈顶的值选择
+            OpCode::Jmp => {
+                stack.pop()?;
+                successors = analysis.targets.get(&pc)?.iter().copied().collect();
+                body.leaders.extend(&successors);
+                body.jumps.insert(pc, successors.clone());
+            }
+            OpCode::Call => {
+                stack.pop()?;
+                stack.pop()?;
+                let targets = analysis.targets.get(&pc)?;
+                let count = *analysis.arg_counts.get(&pc)?;
+                if targets.len() != 1 {
+                    return None;
+                }
+                for _ in 0..count {
+                    pop(&mut stack, Kind::Int)?;
+                }
+                stack.push(Slot::int(None));
+                body.calls.insert(pc, (*targets.iter().next().unwrap(), count));
+            }
+            OpCode::Return => {
+                pop(&mut stack, Kind::Int)?;
+                successors.clear();
+            }
+            OpCode::LoadLocal => {
+                let index = usize::try_from(stack.pop()?.constant?).ok()?;
+                let slot = *stack.get(index)?;
+                stack.push(slot);
+            }
+            OpCode::StoreLocal => {
+                let index = usize::try_from(stack.pop()?.constant?).ok()?;
+                let value = stack.pop()?;
+                *stack.get_mut(index)? = value;
+            }
+            _ => return None,
+        }
+        for successor in successors {
+            // 执行到字节码末尾时程序结束，不是函数
+            if successor >= program.codes.len() {
+                return None;
+            }
+            match body.stacks.get_mut(&successor) {
+                Some(old) => {
+                    if old.len() != stack.len() {
+                        return None;
+                    }
+                    let joined = old.iter().zip(&stack).map(|(a, b)| a.join(*b)).collect::<Option<Vec<_>>>()?;
+                    if joined != *old {
+                        *old = joined;
+                        worklist.push(successor);
+                    }
+                }
+                None => {
+                    body.stacks.insert(successor, stack.clone());
+                    worklist.push(successor);
+                }
+            }
+        }
+    }
+    Some(body)
+}
+
+// 函数的签名：(放弃标志的地址, 还允许嵌套调用的层数, 参数...) -> 返回值
+fn declare(module: &mut JITModule, function: usize, argc: usize) -> Result<FuncId, Box<ModuleError>> {
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(module.target_config().pointer_type()));
+    signature.params.extend(std::iter::repeat_n(AbiParam::new(I64), argc + 1));
+    signature.returns.push(AbiParam::new(I64));
+    Ok(module.declare_function(&format!("svm_{}", function), Linkage::Local, &signature)?)
+}
+
+// 定义bodies中的函数，以及解释器进入每个函数的入口
+fn define(module: &mut JITModule, bodies: &BTreeMap<usize, Body>) -> Result<Vec<(usize, Entry)>, Box<ModuleError>> {
+    let mut context = module.make_context();
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut entries = Vec::new();
+    for (&function, body) in bodies {
+        let id = declare(module, function, body.argc)?;
+        let mut callees = HashMap::new();
+        for &(callee, argc) in body.calls.values() {
+            callees.insert(callee, declare(module, callee, argc)?);
+        }
+        context.func.signature = module.declarations().get_function_decl(id).signature.clone();
+        let mut refs = HashMap::new();
+        for (callee, callee_id) in callees {
+            refs.insert(callee, module.declare_func_in_func(callee_id, &mut context.func));
+        }
+        translate(&mut context, &mut builder_context, body, &refs);
+        module.define_function(id, &mut context)?;
+        module.clear_context(&mut context);
+
+        let entry_id = define_entry(module, &mut context, &mut builder_context, function, id, body.argc)?;
+        entries.push((function, entry_id));
+    }
+    module.finalize_definitions()?;
+    Ok(entries.into_iter().map(|(function, id)| {
+        let code = module.get_finalized_function(id);
+        // 入口按Entry的签名生成
+        (function, unsafe { std::mem::transmute::<*const u8, Entry>(code) })
+    }).collect())
+}
+
+// 解释器的入口：从参数数组中取出参数，再调用函数
+fn define_entry(
+    module: &mut JITModule,
+    context: &mut Context,
+    builder_context: &mut FunctionBuilderContext,
+    function: usize,
+    id: FuncId,
+    argc: usize,
+) -> Result<FuncId, Box<ModuleError>> {
+    let pointer = module.target_config().pointer_type();
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer));
+    signature.params.push(AbiParam::new(I64));
+    signature.params.push(AbiParam::new(pointer));
+    signature.returns.push(AbiParam::new(I64));
+    let entry_id = module.declare_function(&format!("svm_enter_{}", function), Linkage::Local, &signature)?;
+    context.func.signature = signature;
+    let callee = module.declare_func_in_func(id, &mut context.func);
+
+    let mut builder = FunctionBuilder::new(&mut context.func, builder_context);
+    let block = builder.create_block();
+    builder.append_block_params_for_function_params(block);
+    builder.switch_to_block(block);
+    let params = builder.block_params(block).to_vec();
+    let mut args = vec![params[0], params[1]];
+    for i in 0..argc {
+        args.push(builder.ins().load(I64, MemFlags::trusted(), params[2], (i * 8) as i32));
+    }
+    let call = builder.ins().call(callee, &args);
+    let result = builder.inst_results(call)[0];
+    builder.ins().return_(&[result]);
+    builder.seal_all_blocks();
+    builder.finalize();
+    module.define_function(entry_id, context)?;
+    module.clear_context(context);
+    Ok(entry_id)
+}
+
+// 生成一个函数的cranelift IR
+fn translate(context: &mut Context, builder_context: &mut FunctionBuilderContext, body: &Body, refs: &HashMap<usize, ir::FuncRef>) {
+    let mut builder = FunctionBuilder::new(&mut context.func, builder_context);
+    for slot in 0..body.slots() {
+        builder.declare_var(Variable::new(slot), I64);
+    }
+    let start = builder.create_block();
+    builder.append_block_params_for_function_params(start);
+    let blocks: BTreeMap<usize, Block> = body.leaders.iter().map(|&leader| (leader, builder.create_block())).collect();
+    let bail = builder.create_block();
+
+    builder.switch_to_block(start);
+    let params = builder.block_params(start).to_vec();
+    let (flag, depth) = (params[0], params[1]);
+    for (slot, &arg) in params[2..].iter().enumerate() {
+        builder.def_var(Variable::new(slot), arg);
+    }
+    builder.ins().jump(blocks[&body.entry], &[]);
+
+    let mut translator = Translator { builder, flag, depth, bail };
+    let mut open = false;
+    for (&pc, stack) in &body.stacks {
+        if let Some(&block) = blocks.get(&pc) {
+            if open {
+                translator.builder.ins().jump(block, &[]);
+            }
+            translator.builder.switch_to_block(block);
+        }
+        open = translator.instruction(body, pc, stack.len(), &blocks, refs);
+    }
+
+    // 放弃本次调用：设置标志后返回，调用者检查标志后继续放弃
+    let mut builder = translator.builder;
+    builder.switch_to_block(bail);
+    let one = builder.ins().iconst(I64, 1);
+    builder.ins().store(MemFlags::trusted(), one, flag, 0);
+    let zero = builder.ins().iconst(I64, 0);
+    builder.ins().return_(&[zero]);
+    builder.seal_all_blocks();
+    builder.finalize();
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    flag: ir::Value,
+    depth: ir::Value,
+    bail: Block,
+}
+
+impl<'a> Translator<'a> {
+    fn get(&mut self, slot: usize) -> ir::Value {
+        self.builder.use_var(Variable::new(slot))
+    }
+
+    fn set(&mut self, slot: usize, value: ir::Value) {
+        self.builder.def_var(Variable::new(slot), value);
+    }
+
+    // condition不为0时放弃
+    fn bail_if(&mut self, condition: ir::Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, self.bail, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn truthy(&mut self, value: ir::Value) -> ir::Value {
+        self.builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
+    }
+
+    // 比较结果扩展为i64的0或1
+    fn compare(&mut self, cc: IntCC, a: ir::Value, b: ir::Value) -> ir::Value {
+        let result = self.builder.ins().icmp(cc, a, b);
+        self.builder.ins().uextend(I64, result)
+    }
+
+    // 翻译pc处执行前栈深度为depth的指令，返回之后是否还能顺序执行下一条指令
+    fn instruction(
+        &mut self,
+        body: &Body,
+        pc: usize,
+        depth: usize,
+        blocks: &BTreeMap<usize, Block>,
+        refs: &HashMap<usize, ir::FuncRef>,
+    ) -> bool {
+        let stack = &body.stacks[&pc];
+        let instruction = body.instructions[&pc];
+        match instruction.opcode {
+            OpCode::Push | OpCode::PushConst => {
+                let value = self.builder.ins().iconst(I64, body.pushes[&pc]);
+                self.set(depth, value);
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le
+            | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or => {
+                let (a, b) = (self.get(depth - 2), self.get(depth - 1));
+                let result = self.binary(instruction.opcode, a, b);
+                self.set(depth - 2, result);
+            }
+            OpCode::Not => {
+                let a = self.get(depth - 1);
+                let result = self.builder.ins().icmp_imm(IntCC::Equal, a, 0);
+                let result = self.builder.ins().uextend(I64, result);
+                self.set(depth - 1, result);
+            }
+            // 0 - a，a为i64::MIN时溢出
+            OpCode::Neg => {
+                let a = self.get(depth - 1);
+                let zero = self.builder.ins().iconst(I64, 0);
+                let result = self.binary(OpCode::Sub, zero, a);
+                self.set(depth - 1, result);
+            }
+            OpCode::If => {
+                let (condition, t, f) = (self.get(depth - 3), self.get(depth - 2), self.get(depth - 1));
+                let result = self.builder.ins().select(condition, t, f);
+                self.set(depth - 3, result);
+            }
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                let condition = self.get(depth - 1);
+                let target = blocks[&body.jumps[&pc][0]];
+                let next = blocks[&(pc + instruction.len)];
+                let (then, otherwise) = if instruction.opcode == OpCode::JmpIfNonZero { (target, next) } else { (next, target) };
+                if then == otherwise {
+                    self.builder.ins().jump(then, &[]);
+                } else {
+                    self.builder.ins().brif(condition, then, &[], otherwise, &[]);
+                }
+                return false;
+            }
+            OpCode::Jmp => {
+                let targets = &body.jumps[&pc];
+                if let [target] = targets[..] {
+                    self.builder.ins().jump(blocks[&target], &[]);
+                    return false;
+                }
+                // 地址一定是可能的目标之一，都不相等时放弃
+                let addr = self.get(depth - 1);
+                for &target in targets {
+                    let equal = self.builder.ins().icmp_imm(IntCC::Equal, addr, target as i64);
+                    let next = self.builder.create_block();
+                    self.builder.ins().brif(equal, blocks[&target], &[], next, &[]);
+                    self.builder.switch_to_block(next);
+                }
+                self.builder.ins().jump(self.bail, &[]);
+                return false;
+            }
+            OpCode::Call => {
+                let (callee, argc) = body.calls[&pc];
+                let first = depth - 2 - argc;
+                let exhausted = self.builder.ins().icmp_imm(IntCC::Equal, self.depth, 0);
+                self.bail_if(exhausted);
+                let mut args = vec![self.flag, self.builder.ins().iadd_imm(self.depth, -1)];
+                for slot in first..first + argc {
+                    args.push(self.get(slot));
+                }
+                let call = self.builder.ins().call(refs[&callee], &args);
+                let result = self.builder.inst_results(call)[0];
+                let bailed = self.builder.ins().load(I64, MemFlags::trusted(), self.flag, 0);
+                self.bail_if(bailed);
+                self.set(first, result);
+            }
+            OpCode::Return => {
+                let value = self.get(depth - 1);
+                self.builder.ins().return_(&[value]);
+                return false;
+            }
+            OpCode::LoadLocal => {
+                let index = stack[depth - 1].constant.unwrap() as usize;
+                let value = self.get(index);
+                self.set(depth - 1, value);
+            }
+            OpCode::StoreLocal => {
+                let index = stack[depth - 1].constant.unwrap() as usize;
+                let value = self.get(depth - 2);
+                self.set(index, value);
+            }
+            _ => unreachable!("scan accepts only these opcodes"),
+        }
+        true
+    }
+
+    // 与解释器的int运算相同，解释器会报错时放弃
+    fn binary(&mut self, opcode: OpCode, a: ir::Value, b: ir::Value) -> ir::Value {
+        match opcode {
+            OpCode::Add | OpCode::Sub | OpCode::Mul => {
+                let (result, overflow) = match opcode {
+                    OpCode::Add => self.builder.ins().sadd_overflow(a, b),
+                    OpCode::Sub => self.builder.ins().ssub_overflow(a, b),
+                    _ => self.builder.ins().smul_overflow(a, b),
+                };
+                self.bail_if(overflow);
+                result
+            }
+            OpCode::Div => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.bail_if(zero);
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, a, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let overflow = self.builder.ins().band(min, minus_one);
+                self.bail_if(overflow);
+                self.builder.ins().sdiv(a, b)
+            }
+            OpCode::Eq => self.compare(IntCC::Equal, a, b),
+            OpCode::Ne => self.compare(IntCC::NotEqual, a, b),
+            OpCode::Lt => self.compare(IntCC::SignedLessThan, a, b),
+            OpCode::Le => self.compare(IntCC::SignedLessThanOrEqual, a, b),
+            OpCode::Gt => self.compare(IntCC::SignedGreaterThan, a, b),
+            OpCode::Ge => self.compare(IntCC::SignedGreaterThanOrEqual, a, b),
+            OpCode::And | OpCode::Or => {
+                let (a, b) = (self.truthy(a), self.truthy(b));
+                let result = if opcode == OpCode::And { self.builder.ins().band(a, b) } else { self.builder.ins().bor(a, b) };
+                self.builder.ins().uextend(I64, result)
+            }
+            _ => unreachable!("not a binary opcode"),
+        }
+    }
+}
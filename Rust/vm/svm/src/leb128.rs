@@ -0,0 +1,77 @@
+// LEB128变长整数编码，每个字节低7位存数据，最高位表示后面是否还有字节
+
+pub fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // 剩余部分全是符号位，且当前字节的符号位与之一致时结束
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// 从bytes[pos..]读取，返回值和占用的字节数，数据不完整或超出64位时返回None
+pub fn read_unsigned(bytes: &[u8], pos: usize) -> Option<(u64, usize)> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    for (i, &byte) in bytes.get(pos..)?.iter().enumerate() {
+        if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+            return None;
+        }
+        result |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some((result, i + 1));
+        }
+    }
+    None
+}
+
+pub fn read_signed(bytes: &[u8], pos: usize) -> Option<(i64, usize)> {
+    let mut result = 0i64;
+    let mut shift = 0;
+    for (i, &byte) in bytes.get(pos..)?.iter().enumerate() {
+        // 第10个字节只剩最高位，其余位必须都是符号位的延伸，且不能再有后续字节
+        if shift >= 64 || (shift == 63 && byte != 0 && byte != 0x7f) {
+            return None;
+        }
+        result |= ((byte & 0x7f) as i64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                result |= -1i64 << shift;
+            }
+            return Some((result, i + 1));
+        }
+    }
+    None
+}
+
+// 编码后的字节数：有效位数按7位一组向上取整，0也占一个字节
+pub fn unsigned_len(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.max(1).div_ceil(7)
+}
+
+// 有效位数包括一个符号位，负数按取反后的值计算
+pub fn signed_len(value: i64) -> usize {
+    let magnitude = if value < 0 { !value } else { value };
+    let bits = 64 - magnitude.leading_zeros() as usize + 1;
+    bits.div_ceil(7)
+}
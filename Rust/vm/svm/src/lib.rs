@@ -0,0 +1,22 @@
+pub mod assembler;
+pub mod bytecode;
+pub mod compiler;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod heap;
+pub mod instruction;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod leb128;
+pub mod limits;
+pub mod natives;
+pub mod optimizer;
+pub mod profiler;
+pub mod program;
+pub mod register;
+pub mod value;
+pub mod verifier;
+pub mod vm;
+pub mod vmio;
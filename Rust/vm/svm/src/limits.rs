@@ -0,0 +1,58 @@
+// 资源限制，用于运行不可信的字节码。
+//
+// 每项为None时不限制，超出限制时run返回VmError::LimitExceeded：
+// ```
+// let limits = Limits { fuel: Some(10_000), ..Limits::default() };
+// vm.set_limits(limits);
+// ```
+
+use std::fmt;
+
+// 默认的最大调用深度
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // 最多执行的指令条数，每条指令消耗1
+    pub fuel: Option<u64>,
+    // 操作数栈上最多的值个数
+    pub max_stack: Option<usize>,
+    // 最大调用深度
+    pub max_call_depth: Option<usize>,
+    // 堆上字符串和数组的最大字节数，超出时先回收一次，仍然超出才报错
+    pub max_heap: Option<usize>,
+}
+
+impl Default for Limits {
+    // 只限制调用深度，避免无限递归耗尽内存
+    fn default() -> Self {
+        Limits { fuel: None, max_stack: None, max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH), max_heap: None }
+    }
+}
+
+impl Limits {
+    // 不做任何限制
+    pub fn unlimited() -> Self {
+        Limits { fuel: None, max_stack: None, max_call_depth: None, max_heap: None }
+    }
+}
+
+// 超出的限制及其上限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Fuel(u64),
+    Stack(usize),
+    CallDepth(usize),
+    Heap(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Fuel(n) => write!(f, "out of fuel after {} instructions", n),
+            Limit::Stack(n) => write!(f, "stack limit of {} values exceeded", n),
+            Limit::CallDepth(n) => write!(f, "stack overflow: call depth limit of {} exceeded", n),
+            Limit::Heap(n) => write!(f, "heap limit of {} bytes exceeded", n),
+        }
+    }
+}
@@ -1,31 +1,280 @@
 #[macro_use]
 extern crate log;
 
-mod instruction;
-mod vm;
+use std::path::{Path, PathBuf};
+use std::process;
+use svm::error::VmError;
+use svm::limits::Limits;
+use svm::optimizer::{self, Pass};
+use svm::profiler::Profiler;
+use svm::program::Program;
+use svm::register;
+use svm::debugger::Debugger;
+use svm::{bytecode, disassembler, natives, vm};
+use svm::vm::Backend;
 
-use console;
-use std::io::Read;
-use std::borrow::BorrowMut;
+const USAGE: &str = "\
+usage: svm [--log-level <level>] [--optimize <passes>] [--backend <backend>] [--jit <n>] [limits] [profiling] [gc] <command> [args]
+
+commands:
+    run <file>                  run a .svmb bytecode file, a .s assembly file or a .svs script
+    asm <file.s> [-o <out>]     assemble to a .svmb file (default: <file>.svmb)
+    disasm <file>               print the disassembly of a program
+    trace <file>                run and print every instruction with the stack
+    debug <file>                run under an interactive debugger (type `help` at the prompt)
+    dap                         serve the Debug Adapter Protocol over stdin/stdout
+
+optimization (for run, trace, debug, disasm and asm):
+    --optimize <passes>         optimize the bytecode with comma-separated passes, in order:
+                                fold, push-pop, thread, dead-code, or `all` for every pass
+
+backends (for run, trace and disasm):
+    --backend <backend>         `stack` interprets the bytecode directly (default), `register`
+                                translates it to register code first; disasm prints that code
+
+jit (for run with the stack backend, in builds with `--features jit`):
+    --jit <n>                   compile integer-only functions to native code after n calls
+                                (default: 100), or `off` to interpret every call
+
+limits (for run, trace and debug):
+    --fuel <n>                  stop after executing n instructions
+    --max-stack <n>             maximum number of values on the stack
+    --max-call-depth <n>        maximum call depth (default: 1024)
+    --max-heap <n>              maximum bytes of live strings and arrays on the heap
+
+profiling (for run and trace):
+    --profile                   print a hotspot table to stderr at exit
+    --coverage <file>           write lcov coverage mapped to source lines
+
+gc (for run and trace):
+    --gc-stress                 collect garbage before every allocation
+    --gc-stats                  print garbage collector statistics to stderr at exit
+
+log levels: off, error, warn, info, debug, trace (default: warn)";
+
+// 命令行参数错误时的退出码
+const EXIT_USAGE: i32 = 2;
+// 超出资源限制时的退出码
+const EXIT_LIMIT: i32 = 3;
 
 fn main() {
-    simple_logger::SimpleLogger::new().with_level(log::LevelFilter::Info).init().unwrap();
-    info!("simple stack vm impl.");
-
-    // let mut terminal = console::Term::stdout();
-    // terminal.write_line("terminal print test.");
-    //
-    // std::thread::sleep(std::time::Duration::from_secs(3));
-    // terminal.clear_line().unwrap();
-    //
-    // let s = terminal.read_line().unwrap();
-    // info!("read from terminal: {}", s);
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let level = option(&mut args, "--log-level").map_or(log::LevelFilter::Warn, |value| {
+        value.parse().unwrap_or_else(|_| usage_error(&format!("invalid log level `{}`", value)))
+    });
+    // cranelift在info级别输出每个函数的IR，只保留它的警告和错误
+    simple_logger::SimpleLogger::new()
+        .with_level(level)
+        .with_module_level("cranelift_jit", level.min(log::LevelFilter::Warn))
+        .with_module_level("cranelift_codegen", level.min(log::LevelFilter::Warn))
+        .init()
+        .unwrap();
+
+    let passes = option(&mut args, "--optimize").map_or_else(Vec::new, |list| {
+        Pass::parse_list(&list).unwrap_or_else(|e| usage_error(&e))
+    });
+
+    let backend = option(&mut args, "--backend").map_or(Backend::Stack, |name| match name.as_str() {
+        "stack" => Backend::Stack,
+        "register" => Backend::Register,
+        _ => usage_error(&format!("unknown backend `{}`", name)),
+    });
+
+    let jit = option(&mut args, "--jit").map(|value| match value.as_str() {
+        "off" => None,
+        _ => Some(value.parse::<u32>().unwrap_or_else(|_| usage_error(&format!("invalid value `{}` for --jit", value)))),
+    });
+    if jit.is_some() && cfg!(not(feature = "jit")) {
+        usage_error("--jit needs a build with `--features jit`");
+    }
+
+    let mut limits = Limits::default();
+    limits.fuel = number_option(&mut args, "--fuel");
+    limits.max_stack = number_option(&mut args, "--max-stack");
+    limits.max_call_depth = number_option(&mut args, "--max-call-depth").or(limits.max_call_depth);
+    limits.max_heap = number_option(&mut args, "--max-heap");
+    let options = RunOptions {
+        limits,
+        profile: flag(&mut args, "--profile"),
+        coverage: option(&mut args, "--coverage"),
+        gc_stress: flag(&mut args, "--gc-stress"),
+        gc_stats: flag(&mut args, "--gc-stats"),
+        backend,
+        #[cfg(feature = "jit")]
+        jit,
+    };
+    if backend == Backend::Register && (options.profile || options.coverage.is_some()) {
+        usage_error("--profile and --coverage need the stack backend");
+    }
+
+    let code = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["run", file] => run(&load(file, &passes), false, options),
+        ["trace", file] => run(&load(file, &passes), true, options),
+        ["debug", file] => debug(&load(file, &passes), options.limits),
+        ["dap"] => dap(),
+        ["disasm", file] => disasm(&load(file, &passes), backend),
+        ["asm", file] => asm(file, None, &passes),
+        ["asm", file, "-o", out] | ["asm", "-o", out, file] => asm(file, Some(out), &passes),
+        ["help"] | ["--help"] | ["-h"] => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => usage_error("missing or unknown command"),
+    };
+    process::exit(code);
+}
+
+// 取出`name value`形式的选项，可以出现在命令前后任意位置
+fn option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    let value = args.get(i + 1).cloned().unwrap_or_else(|| usage_error(&format!("missing value for {}", name)));
+    args.drain(i..i + 2);
+    Some(value)
+}
+
+fn flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
 
+fn number_option<T: std::str::FromStr>(args: &mut Vec<String>, name: &str) -> Option<T> {
+    option(args, name).map(|value| {
+        value.parse().unwrap_or_else(|_| usage_error(&format!("invalid value `{}` for {}", value, name)))
+    })
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("svm: {}\n\n{}", message, USAGE);
+    process::exit(EXIT_USAGE);
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("svm: {}", message);
+    process::exit(1);
+}
+
+fn load(file: &str, passes: &[Pass]) -> Program {
+    optimize(Program::load(file).unwrap_or_else(|e| fail(e)), passes)
+}
+
+// 按顺序执行优化，没有选择优化遍时原样返回
+fn optimize(program: Program, passes: &[Pass]) -> Program {
+    if passes.is_empty() {
+        return program;
+    }
+    let before = program.codes.len();
+    let program = optimizer::optimize(&program, passes).unwrap_or_else(|e| fail(format!("cannot optimize: {}", e)));
+    info!("optimized: {} -> {} bytes", before, program.codes.len());
+    program
+}
+
+// run和trace的选项
+struct RunOptions {
+    limits: Limits,
+    profile: bool,
+    coverage: Option<String>,
+    gc_stress: bool,
+    gc_stats: bool,
+    backend: Backend,
+    // --jit给出的编译阈值，没有给出时为None
+    #[cfg(feature = "jit")]
+    jit: Option<Option<u32>>,
+}
+
+// 运行程序，返回值作为进程退出码：Exit指令给出的退出码，正常结束为0，出错为1，超出资源限制为3
+fn run(program: &Program, trace: bool, options: RunOptions) -> i32 {
+    let mut svm = vm::Vm::new();
+    svm.import_program(program);
+    natives::register_builtins(&mut svm);
+    svm.set_limits(options.limits);
+    svm.set_gc_stress(options.gc_stress);
+    svm.set_backend(options.backend);
+    #[cfg(feature = "jit")]
+    if let Some(threshold) = options.jit {
+        svm.set_jit_threshold(threshold);
+    }
+    if trace {
+        svm.set_trace(std::io::stderr());
+    }
+    info!("run {}", program.debug.source.as_deref().unwrap_or("program"));
+    let result = if options.profile || options.coverage.is_some() {
+        let mut profiler = Profiler::new();
+        let result = profiler.run(&mut svm);
+        if options.profile {
+            eprint!("{}", profiler.report(program));
+        }
+        if let Some(path) = &options.coverage {
+            std::fs::write(path, profiler.lcov(program)).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+            info!("wrote {}", path);
+        }
+        result
+    } else {
+        svm.run()
+    };
+    info!("fuel used: {}", svm.fuel_used());
+    if options.gc_stats {
+        let stats = svm.gc_stats();
+        eprintln!(
+            "gc: {} collections, {} objects allocated, {} freed, {} live ({} bytes, peak {} bytes)",
+            stats.collections, stats.allocated, stats.freed, stats.objects, stats.bytes, stats.peak_bytes,
+        );
+    }
+    match result {
+        Ok(()) => svm.exit_code().unwrap_or(0),
+        Err(e) => {
+            let line = svm.debug_info().line_of(e.pc()).map(|l| format!(" (line {})", l)).unwrap_or_default();
+            eprintln!("svm: runtime error: {}{}", e, line);
+            if let VmError::LimitExceeded { .. } = e {
+                eprintln!("svm: fuel used: {}", svm.fuel_used());
+                return EXIT_LIMIT;
+            }
+            1
+        }
+    }
+}
+
+fn debug(program: &Program, limits: Limits) -> i32 {
     let mut svm = vm::Vm::new();
-    svm.init();
+    svm.import_program(program);
+    natives::register_builtins(&mut svm);
+    svm.set_limits(limits);
+    let stdin = std::io::stdin();
+    match Debugger::new(stdin.lock(), std::io::stdout()).run(&mut svm) {
+        Ok(()) => svm.exit_code().unwrap_or(0),
+        Err(_) => 1,
+    }
+}
+
+fn dap() -> i32 {
+    let stdin = std::io::stdin();
+    if let Err(e) = svm::dap::serve(stdin.lock(), std::io::stdout()) {
+        fail(format!("dap: {}", e));
+    }
+    0
+}
+
+fn disasm(program: &Program, backend: Backend) -> i32 {
+    match backend {
+        Backend::Stack => print!("{}", disassembler::disassemble(program)),
+        Backend::Register => {
+            let program = register::translate(program).unwrap_or_else(|e| fail(format!("cannot translate: {}", e)));
+            print!("{}", register::disassemble(&program));
+        }
+    }
+    0
+}
 
-    // print(1+2)编译成字节码的结果[17,18,0,4]， 这里需要编写一个编译器，将print(1+2)语句翻译为字节码
-    let codes = vec![17u8, 18, 0, 4];
-    svm.import_codes(codes.as_slice());
-    svm.run();
+fn asm(file: &str, out: Option<&str>, passes: &[Pass]) -> i32 {
+    let bytes = std::fs::read(file).unwrap_or_else(|e| fail(format!("{}: {}", file, e)));
+    let program = optimize(Program::assemble(file, &bytes).unwrap_or_else(|e| fail(e)), passes);
+    let out = out.map(PathBuf::from).unwrap_or_else(|| Path::new(file).with_extension("svmb"));
+    bytecode::save(&program, &out).unwrap_or_else(|e| fail(format!("{}: {}", out.display(), e)));
+    info!("wrote {}", out.display());
+    0
 }
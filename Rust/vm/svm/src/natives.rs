@@ -0,0 +1,28 @@
+// 命令行和DAP默认注册的宿主函数，其他宿主可以用Vm::register_native注册自己的函数。
+
+use crate::value::Value;
+use crate::vm::Vm;
+
+pub fn register_builtins(vm: &mut Vm) {
+    vm.register_native("sqrt", 1, |args| {
+        let x = number(&args[0])?;
+        if x < 0.0 {
+            return Err(format!("square root of negative number {}", x));
+        }
+        Ok(Value::Float(x.sqrt()))
+    });
+    vm.register_native("abs", 1, |args| match &args[0] {
+        Value::Int(i) => i.checked_abs().map(Value::Int).ok_or_else(|| "arithmetic overflow".to_string()),
+        Value::Float(f) => Ok(Value::Float(f.abs())),
+        other => Err(format!("expected number, found {}", other.type_name())),
+    });
+    vm.register_native("floor", 1, |args| Ok(Value::Float(number(&args[0])?.floor())));
+}
+
+fn number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Int(i) => Ok(*i as f64),
+        Value::Float(f) => Ok(*f),
+        other => Err(format!("expected number, found {}", other.type_name())),
+    }
+}
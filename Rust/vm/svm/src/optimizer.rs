@@ -0,0 +1,406 @@
+// 字节码优化：在校验通过的程序上依次执行选定的各遍变换，每遍之后重新布局并回填跳转目标。
+//
+// 每遍开始前重新校验，用校验器的分析得到指令的可达性、跳转目标以及作为地址压栈的push指令。
+// 变换只合并或删除基本块内部的指令，即除第一条外都不是跳转目标的连续指令；被删除的指令
+// 若是跳转目标，跳转改到其后第一条保留的指令。作为地址的push在布局时改写为新的地址，
+// 这要求这些值只用作jmp/call的目标，编译器和手写的汇编都是如此。
+
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::instruction::*;
+use crate::program::Program;
+use crate::value::Value;
+use crate::verifier::{self, Analysis, VerifyError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    // 常量折叠：操作数都是常量的运算、if和条件跳转在编译时求值
+    Fold,
+    // 删除不可达的指令，例如无条件jmp/exit之后没有跳转到达的代码
+    DeadCode,
+    // 跳转到另一条无条件跳转时直接跳到最终的目标
+    Thread,
+    // 删除压栈后立即弹出且没有效果的指令对
+    PushPop,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 4] = [Pass::Fold, Pass::PushPop, Pass::Thread, Pass::DeadCode];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Fold => "fold",
+            Pass::DeadCode => "dead-code",
+            Pass::Thread => "thread",
+            Pass::PushPop => "push-pop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.iter().copied().find(|pass| pass.name() == name)
+    }
+
+    // 解析逗号分隔的优化遍列表，`all`表示全部
+    pub fn parse_list(list: &str) -> Result<Vec<Pass>, String> {
+        if list == "all" {
+            return Ok(Pass::ALL.to_vec());
+        }
+        list.split(',')
+            .map(|name| Pass::from_name(name.trim()).ok_or_else(|| format!("unknown optimization pass `{}`", name.trim())))
+            .collect()
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// 一轮中的各遍会为彼此创造新的机会，例如折叠出的常量条件使分支成为死代码，
+// 所以反复执行直到字节码不再变化，最多这么多轮
+const MAX_ROUNDS: usize = 16;
+
+// 按顺序执行各遍优化，程序无法通过校验时返回校验错误
+pub fn optimize(program: &Program, passes: &[Pass]) -> Result<Program, VerifyError> {
+    let mut program = program.clone();
+    for _ in 0..MAX_ROUNDS {
+        let mut next = program.clone();
+        for &pass in passes {
+            next = run_pass(&next, pass)?;
+        }
+        if next.codes == program.codes {
+            break;
+        }
+        program = next;
+    }
+    Ok(program)
+}
+
+fn run_pass(program: &Program, pass: Pass) -> Result<Program, VerifyError> {
+    let analysis = verifier::verify(program)?;
+    let mut code = Code::new(program, &analysis);
+    match pass {
+        Pass::Fold => code.fold(),
+        Pass::DeadCode => code.dead_code(),
+        Pass::Thread => code.thread(),
+        Pass::PushPop => code.push_pop(),
+    }
+    Ok(code.finish())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    Operand(Operand),
+    // 压栈的代码地址，为原程序中的地址
+    Addr(usize),
+    // 条件跳转的目标在原程序中的地址，以及是否编码为相对偏移
+    Target(usize, bool),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    opcode: OpCode,
+    arg: Arg,
+    // 在原程序中的地址，合并得到的指令取第一条的地址
+    addr: usize,
+    line: Option<usize>,
+}
+
+struct Code<'a> {
+    nodes: Vec<Node>,
+    analysis: &'a Analysis,
+    // 跳转目标和函数入口的地址
+    leaders: BTreeSet<usize>,
+    // 常量池和调试信息，布局后写入字节码
+    out: Program,
+}
+
+impl<'a> Code<'a> {
+    fn new(program: &Program, analysis: &'a Analysis) -> Self {
+        let valid = |addr: i64| addr >= 0 && analysis.instructions.contains_key(&(addr as usize));
+        let nodes = analysis.instructions.iter().map(|(&addr, instruction)| {
+            let arg = match instruction.operand {
+                _ if analysis.address_pushes.contains(&addr) => match address_value(instruction, &program.constants) {
+                    Some(value) if valid(value) => Arg::Addr(value as usize),
+                    _ => Arg::Operand(instruction.operand),
+                },
+                Operand::Target(target) if valid(target.resolve(addr)) => {
+                    Arg::Target(target.resolve(addr) as usize, matches!(target, Target::Relative(_)))
+                }
+                operand => Arg::Operand(operand),
+            };
+            let opcode = match arg {
+                Arg::Addr(_) => OpCode::Push,
+                _ => instruction.opcode,
+            };
+            Node { opcode, arg, addr, line: program.debug.line_of(addr) }
+        }).collect();
+        let mut out = Program::new(Vec::new(), program.constants.clone());
+        out.debug.source = program.debug.source.clone();
+        out.debug.labels = program.debug.labels.clone();
+        let leaders = analysis.targets.values().flatten().copied().collect();
+        Code { nodes, analysis, leaders, out }
+    }
+
+    fn is_leader(&self, node: &Node) -> bool {
+        self.leaders.contains(&node.addr)
+    }
+
+    // 压入常量的指令所压入的值，字符串每次压栈都在堆上分配，不算常量
+    fn constant(&self, node: &Node) -> Option<Value> {
+        match (node.opcode, node.arg) {
+            (OpCode::Push, Arg::Operand(Operand::Int(v))) => Some(Value::Int(v)),
+            (OpCode::PushConst, Arg::Operand(Operand::Const(index))) => match &self.out.constants[index] {
+                Value::Str(_) => None,
+                value => Some(value.clone()),
+            },
+            _ => None,
+        }
+    }
+
+    // 压入value的指令，位置取自at
+    fn push_node(&mut self, value: Value, at: &Node) -> Node {
+        let (opcode, operand) = match value {
+            Value::Int(v) => (OpCode::Push, Operand::Int(v)),
+            value => (OpCode::PushConst, Operand::Const(self.out.add_constant(value))),
+        };
+        Node { opcode, arg: Arg::Operand(operand), addr: at.addr, line: at.line }
+    }
+
+    fn fold(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        let mut out: Vec<Node> = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            out.push(node);
+            if let Some((len, replacement)) = self.fold_tail(&out) {
+                out.truncate(out.len() - len);
+                out.extend(replacement);
+            }
+        }
+        self.nodes = out;
+    }
+
+    // 尝试折叠以nodes最后一条指令结尾的序列，返回被替换的指令条数和替换后的指令
+    fn fold_tail(&mut self, nodes: &[Node]) -> Option<(usize, Vec<Node>)> {
+        let last = nodes.last()?;
+        let arity = match last.opcode {
+            OpCode::Not | OpCode::Neg | OpCode::JmpIfZero | OpCode::JmpIfNonZero => 1,
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => 2,
+            OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or => 2,
+            OpCode::If => 3,
+            _ => return None,
+        };
+        if nodes.len() <= arity {
+            return None;
+        }
+        let window = &nodes[nodes.len() - arity - 1..];
+        if window[1..].iter().any(|node| self.is_leader(node)) {
+            return None;
+        }
+        let first = window[0];
+        let replacement = match last.opcode {
+            // 条件为常量时选中的一边可以是任意压栈指令，包括作为地址的push
+            OpCode::If => {
+                if !window[1..3].iter().all(|node| matches!(node.opcode, OpCode::Push | OpCode::PushConst)) {
+                    return None;
+                }
+                let chosen = if self.constant(&first)?.is_truthy() { window[1] } else { window[2] };
+                vec![Node { addr: first.addr, line: first.line, ..chosen }]
+            }
+            // 一定跳转时改为无条件跳转，一定不跳转时留给push-pop删除
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                let taken = self.constant(&first)?.is_truthy() == (last.opcode == OpCode::JmpIfNonZero);
+                match (taken, last.arg) {
+                    (true, Arg::Target(target, _)) => vec![
+                        Node { opcode: OpCode::Push, arg: Arg::Addr(target), ..first },
+                        Node { opcode: OpCode::Jmp, arg: Arg::Operand(Operand::None), ..*last },
+                    ],
+                    _ => return None,
+                }
+            }
+            OpCode::Not => {
+                let value = !self.constant(&first)?.is_truthy();
+                vec![self.push_node(Value::Bool(value), &first)]
+            }
+            OpCode::Neg => {
+                let value = match self.constant(&first)? {
+                    Value::Int(v) => Value::Int(v.checked_neg()?),
+                    Value::Float(x) => Value::Float(-x),
+                    _ => return None,
+                };
+                vec![self.push_node(value, &first)]
+            }
+            opcode => {
+                let value = fold_binary(opcode, &self.constant(&first)?, &self.constant(&window[1])?)?;
+                vec![self.push_node(value, &first)]
+            }
+        };
+        Some((window.len(), replacement))
+    }
+
+    fn dead_code(&mut self) {
+        let analysis = self.analysis;
+        self.nodes.retain(|node| analysis.is_reachable(node.addr));
+    }
+
+    fn thread(&mut self) {
+        for i in 0..self.nodes.len() {
+            let arg = match self.nodes[i].arg {
+                Arg::Addr(target) => Arg::Addr(self.final_target(target)),
+                Arg::Target(target, relative) => Arg::Target(self.final_target(target), relative),
+                Arg::Operand(_) => continue,
+            };
+            self.nodes[i].arg = arg;
+        }
+    }
+
+    // 沿着`push 目标; jmp`组成的跳转链找到最终的目标，遇到环时停在环上
+    fn final_target(&self, mut target: usize) -> usize {
+        let mut visited = BTreeSet::new();
+        while visited.insert(target) {
+            let i = match self.nodes.binary_search_by_key(&target, |node| node.addr) {
+                Ok(i) => i,
+                Err(_) => break,
+            };
+            match (self.nodes[i], self.nodes.get(i + 1)) {
+                (Node { opcode: OpCode::Push, arg: Arg::Addr(next), .. }, Some(jump))
+                    if jump.opcode == OpCode::Jmp && !self.is_leader(jump) => target = next,
+                _ => break,
+            }
+        }
+        target
+    }
+
+    fn push_pop(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        let mut out = Vec::with_capacity(nodes.len());
+        let mut i = 0;
+        while i < nodes.len() {
+            match self.cancelling(&nodes[i..]) {
+                // 被删除的指令是跳转目标时要跳到其后的指令，所以不能删除末尾的指令
+                Some(len) if i + len < nodes.len() => i += len,
+                _ => {
+                    out.push(nodes[i]);
+                    i += 1;
+                }
+            }
+        }
+        self.nodes = out;
+    }
+
+    // nodes开头互相抵消的指令条数：
+    // `push c; jmpifzero`中c为真或`push c; jmpifnonzero`中c为假时不跳转，
+    // `push 下一条指令; jmp`跳到紧跟其后的指令，
+    // `push i; loadlocal; push i; storelocal`把局部变量写回原处
+    fn cancelling(&self, nodes: &[Node]) -> Option<usize> {
+        let first = nodes.first()?;
+        match nodes.get(1)?.opcode {
+            OpCode::Jmp if !self.is_leader(&nodes[1]) => match (first.arg, nodes.get(2)) {
+                (Arg::Addr(target), Some(next)) if target == next.addr => Some(2),
+                _ => None,
+            },
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero if !self.is_leader(&nodes[1]) => {
+                let taken = self.constant(first)?.is_truthy() == (nodes[1].opcode == OpCode::JmpIfNonZero);
+                if taken { None } else { Some(2) }
+            }
+            OpCode::LoadLocal if nodes.len() >= 4 && nodes[3].opcode == OpCode::StoreLocal => {
+                if nodes[1..4].iter().any(|node| self.is_leader(node)) {
+                    return None;
+                }
+                match (self.constant(first)?, self.constant(&nodes[2])?) {
+                    (Value::Int(a), Value::Int(b)) if a == b => Some(4),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // 布局并编码，地址的编码长度取决于地址本身，反复布局直到各指令地址不再变化
+    fn finish(self) -> Program {
+        let nodes = &self.nodes;
+        // 原地址 -> 新的指令下标，原地址上的指令被删除时为其后第一条保留的指令
+        let index_of = |addr: usize| nodes.partition_point(|node| node.addr < addr);
+        let operand = |i: usize, addrs: &[usize]| match nodes[i].arg {
+            Arg::Operand(operand) => operand,
+            Arg::Addr(addr) => Operand::Int(addrs[index_of(addr)] as i64),
+            Arg::Target(addr, false) => Operand::Target(Target::Absolute(addrs[index_of(addr)] as i64)),
+            Arg::Target(addr, true) => Operand::Target(Target::Relative(addrs[index_of(addr)] as i64 - addrs[i] as i64)),
+        };
+        let mut addrs = vec![0usize; nodes.len() + 1];
+        loop {
+            let mut next = Vec::with_capacity(nodes.len() + 1);
+            let mut addr = 0;
+            for (i, node) in nodes.iter().enumerate() {
+                next.push(addr);
+                addr += Instruction::new(node.opcode, operand(i, &addrs)).len;
+            }
+            next.push(addr);
+            if next == addrs {
+                break;
+            }
+            addrs = next;
+        }
+
+        let mut out = self.out;
+        for (i, node) in nodes.iter().enumerate() {
+            if let Some(line) = node.line {
+                out.debug.add_line(addrs[i], line);
+            }
+            Instruction::new(node.opcode, operand(i, &addrs)).encode(&mut out.codes);
+        }
+        for label in &mut out.debug.labels {
+            label.1 = addrs[index_of(label.1)];
+        }
+        out
+    }
+}
+
+// 作为地址压栈的值
+fn address_value(instruction: &Instruction, constants: &[Value]) -> Option<i64> {
+    match instruction.operand {
+        Operand::Int(v) => Some(v),
+        Operand::Const(index) => match constants.get(index) {
+            Some(Value::Int(v)) => Some(*v),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 与虚拟机执行的结果一致，运行时会出错的运算（类型不符、除以0、溢出）不折叠
+fn fold_binary(opcode: OpCode, first: &Value, second: &Value) -> Option<Value> {
+    let test: fn(Ordering) -> bool = match opcode {
+        OpCode::Lt => Ordering::is_lt,
+        OpCode::Le => Ordering::is_le,
+        OpCode::Gt => Ordering::is_gt,
+        OpCode::Ge => Ordering::is_ge,
+        _ => return fold_arithmetic(opcode, first, second),
+    };
+    match (first, second) {
+        (Value::Int(a), Value::Int(b)) => Some(Value::Bool(test(a.cmp(b)))),
+        (Value::Float(a), Value::Float(b)) => Some(Value::Bool(a.partial_cmp(b).is_some_and(test))),
+        _ => None,
+    }
+}
+
+fn fold_arithmetic(opcode: OpCode, first: &Value, second: &Value) -> Option<Value> {
+    let value = match (opcode, first, second) {
+        (OpCode::Add, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_add(*b)?),
+        (OpCode::Sub, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_sub(*b)?),
+        (OpCode::Mul, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_mul(*b)?),
+        (OpCode::Div, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_div(*b)?),
+        (OpCode::Add, Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+        (OpCode::Sub, Value::Float(a), Value::Float(b)) => Value::Float(a - b),
+        (OpCode::Mul, Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+        (OpCode::Div, Value::Float(a), Value::Float(b)) => Value::Float(a / b),
+        (OpCode::Eq, _, _) => Value::Bool(first == second),
+        (OpCode::Ne, _, _) => Value::Bool(first != second),
+        (OpCode::And, _, _) => Value::Bool(first.is_truthy() && second.is_truthy()),
+        (OpCode::Or, _, _) => Value::Bool(first.is_truthy() || second.is_truthy()),
+        _ => return None,
+    };
+    Some(value)
+}
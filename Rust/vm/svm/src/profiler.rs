@@ -0,0 +1,177 @@
+// 性能分析与覆盖率统计，`svm run --profile`和`--coverage <file>`使用。
+//
+// 基于Vm::run_with的钩子，统计每个地址和每个操作码的执行次数，并为每个函数计时。
+// 函数按调用帧的入口地址区分，时间包含其调用的其他函数，递归调用只计最外层的一次。
+// 覆盖率按调试信息中的行号输出为lcov格式，没有行号的指令不计入。
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use crate::disassembler;
+use crate::error::VmError;
+use crate::instruction::{Instruction, OpCode, OPCODES};
+use crate::program::Program;
+use crate::vm::{Control, Vm};
+
+// 报告中列出的最热指令条数
+const HOTSPOTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    // 下标为指令地址
+    counts: Vec<u64>,
+    // 下标为操作码的值
+    opcodes: Vec<u64>,
+    // 函数入口地址 -> 统计，None为main
+    functions: BTreeMap<Option<usize>, FunctionStats>,
+    // 正在执行的函数及其开始时间，与Vm的调用帧对应，第一项为main
+    active: Vec<(Option<usize>, Instant)>,
+    // 钩子看到的上一条指令，执行成功后才计数
+    pending: Option<(usize, OpCode)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    // 运行vm中已加载的程序并统计，多次调用时累加
+    pub fn run(&mut self, vm: &mut Vm) -> Result<(), VmError> {
+        self.counts.resize(vm.program().codes.len(), 0);
+        self.opcodes.resize(OPCODES.len(), 0);
+        self.enter(None);
+        let result = vm.run_with(|vm, instruction| {
+            self.record(vm, instruction);
+            Control::Continue
+        });
+        // 出错时最后一条指令没有执行完，比如耗尽fuel时根本没有执行
+        if result.is_ok() {
+            self.count_pending();
+        }
+        self.pending = None;
+        while !self.active.is_empty() {
+            self.leave();
+        }
+        result
+    }
+
+    // 钩子在执行指令之前调用，此时上一条指令已经执行成功
+    fn record(&mut self, vm: &Vm, instruction: &Instruction) {
+        self.count_pending();
+        self.pending = Some((vm.pc(), instruction.opcode));
+        // Call和Return在下一条指令前才能看到调用帧的变化
+        let frames = vm.frames();
+        while self.active.len() > frames.len() + 1 {
+            self.leave();
+        }
+        while self.active.len() < frames.len() + 1 {
+            self.enter(Some(frames[self.active.len() - 1].entry));
+        }
+    }
+
+    fn count_pending(&mut self) {
+        if let Some((pc, opcode)) = self.pending.take() {
+            self.counts[pc] += 1;
+            self.opcodes[opcode as usize] += 1;
+        }
+    }
+
+    fn enter(&mut self, function: Option<usize>) {
+        self.functions.entry(function).or_default().calls += 1;
+        self.active.push((function, Instant::now()));
+    }
+
+    fn leave(&mut self) {
+        let (function, start) = self.active.pop().unwrap();
+        if self.active.iter().all(|(f, _)| *f != function) {
+            self.functions.entry(function).or_default().time += start.elapsed();
+        }
+    }
+
+    // 地址addr处的指令执行的次数
+    pub fn count(&self, addr: usize) -> u64 {
+        self.counts.get(addr).copied().unwrap_or(0)
+    }
+
+    pub fn opcode_count(&self, opcode: OpCode) -> u64 {
+        self.opcodes.get(opcode as usize).copied().unwrap_or(0)
+    }
+
+    // 入口地址为entry的函数的统计，None为main
+    pub fn function(&self, entry: Option<usize>) -> Option<FunctionStats> {
+        self.functions.get(&entry).copied()
+    }
+
+    // 热点指令、操作码和函数三张表
+    pub fn report(&self, program: &Program) -> String {
+        let mut out = String::new();
+        let mut hot: Vec<(usize, u64)> = self.counts.iter().copied().enumerate().filter(|&(_, n)| n > 0).collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(out, "{:>12}  {:>4}  {:<24}  line", "count", "addr", "instruction").unwrap();
+        for &(addr, count) in hot.iter().take(HOTSPOTS) {
+            let text = Instruction::decode(&program.codes, addr)
+                .map(|instruction| disassembler::instruction_text(&instruction, &program.constants))
+                .unwrap_or_default();
+            let line = program.debug.line_of(addr).map(|l| l.to_string()).unwrap_or_default();
+            writeln!(out, "{:>12}  {:04}  {:<24}  {}", count, addr, text, line).unwrap();
+        }
+
+        let mut opcodes: Vec<(OpCode, u64)> = OPCODES.iter().map(|&op| (op, self.opcode_count(op))).filter(|&(_, n)| n > 0).collect();
+        opcodes.sort_by_key(|&(_, count)| Reverse(count));
+        writeln!(out, "\n{:>12}  opcode", "count").unwrap();
+        for (opcode, count) in opcodes {
+            writeln!(out, "{:>12}  {}", count, opcode.mnemonic()).unwrap();
+        }
+
+        let mut functions: Vec<(&Option<usize>, &FunctionStats)> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, stats)| Reverse(stats.time));
+        writeln!(out, "\n{:>12}  {:>12}  function", "calls", "total ms").unwrap();
+        for (entry, stats) in functions {
+            let name = match entry {
+                Some(addr) => match program.debug.labels.iter().find(|(_, a)| a == addr) {
+                    Some((name, _)) => name.clone(),
+                    None => format!("{:04}", addr),
+                },
+                None => "main".to_string(),
+            };
+            writeln!(out, "{:>12}  {:>12.3}  {}", stats.calls, stats.time.as_secs_f64() * 1000.0, name).unwrap();
+        }
+        out
+    }
+
+    // lcov格式的覆盖率，一行中有多条指令时取执行次数最多的一条
+    pub fn lcov(&self, program: &Program) -> String {
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut addr = 0;
+        while addr < program.codes.len() {
+            let len = match Instruction::decode(&program.codes, addr) {
+                Ok(instruction) => {
+                    if let Some(line) = program.debug.line_of(addr) {
+                        let count = lines.entry(line).or_default();
+                        *count = (*count).max(self.count(addr));
+                    }
+                    instruction.len
+                }
+                Err(_) => 1,
+            };
+            addr += len;
+        }
+
+        let mut out = String::from("TN:\n");
+        writeln!(out, "SF:{}", program.debug.source.as_deref().unwrap_or("")).unwrap();
+        for (line, count) in &lines {
+            writeln!(out, "DA:{},{}", line, count).unwrap();
+        }
+        writeln!(out, "LF:{}", lines.len()).unwrap();
+        writeln!(out, "LH:{}", lines.values().filter(|&&n| n > 0).count()).unwrap();
+        out.push_str("end_of_record\n");
+        out
+    }
+}
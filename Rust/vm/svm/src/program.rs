@@ -0,0 +1,115 @@
+use std::path::Path;
+use crate::assembler;
+use crate::bytecode;
+use crate::compiler;
+use crate::value::Value;
+
+// 一段可执行的程序：字节码、PushConst引用的常量池以及调试信息
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub codes: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub debug: DebugInfo,
+}
+
+// 调试信息，把字节码地址映射回源码
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugInfo {
+    // 源文件名
+    pub source: Option<String>,
+    // (地址, 行号)，按地址递增，每项覆盖到下一项之前的所有字节码
+    pub lines: Vec<(usize, usize)>,
+    // (标号名, 地址)
+    pub labels: Vec<(String, usize)>,
+}
+
+impl DebugInfo {
+    // 地址对应的源码行号
+    pub fn line_of(&self, addr: usize) -> Option<usize> {
+        match self.lines.binary_search_by_key(&addr, |&(a, _)| a) {
+            Ok(i) => Some(self.lines[i].1),
+            Err(0) => None,
+            Err(i) => Some(self.lines[i - 1].1),
+        }
+    }
+
+    // 某一行对应的第一个地址
+    pub fn addr_of_line(&self, line: usize) -> Option<usize> {
+        self.lines.iter().find(|&&(_, l)| l == line).map(|&(addr, _)| addr)
+    }
+
+    // 一行的代码可能分成几段，addr是某一段的第一个地址时返回该行
+    pub fn line_starting_at(&self, addr: usize) -> Option<usize> {
+        self.lines.binary_search_by_key(&addr, |&(a, _)| a).ok().map(|i| self.lines[i].1)
+    }
+
+    pub fn label_addr(&self, name: &str) -> Option<usize> {
+        self.labels.iter().find(|(n, _)| n == name).map(|&(_, addr)| addr)
+    }
+
+    // 记录地址addr开始对应第line行，与上一项同一行时合并
+    pub fn add_line(&mut self, addr: usize, line: usize) {
+        match self.lines.last() {
+            Some(&(_, last)) if last == line => {}
+            Some(&(last_addr, _)) if last_addr == addr => {
+                self.lines.pop();
+                self.lines.push((addr, line));
+            }
+            _ => self.lines.push((addr, line)),
+        }
+    }
+}
+
+impl Program {
+    pub fn new(codes: Vec<u8>, constants: Vec<Value>) -> Self {
+        Program { codes, constants, debug: DebugInfo::default() }
+    }
+
+    // 按内容加载程序：.svmb文件按文件格式解析，.s文件先汇编，.svs脚本先编译，其余当作裸字节码。
+    // 出错时返回带文件名的错误信息
+    pub fn load(file: &str) -> Result<Program, String> {
+        let bytes = std::fs::read(file).map_err(|e| format!("{}: {}", file, e))?;
+        if bytecode::is_bytecode_file(&bytes) {
+            return bytecode::deserialize(&bytes).map_err(|e| format!("{}: {}", file, e));
+        }
+        match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+            Some("s") => return Program::assemble(file, &bytes),
+            Some("svs") => return Program::compile(file, &bytes),
+            _ => {}
+        }
+        Ok(Program::new(bytes, Vec::new()))
+    }
+
+    // 汇编源文件，调试信息中记下文件名
+    pub fn assemble(file: &str, bytes: &[u8]) -> Result<Program, String> {
+        let source = std::str::from_utf8(bytes).map_err(|_| format!("{}: not utf-8 text", file))?;
+        let mut program = assembler::assemble(source).map_err(|e| format!("{}:{}", file, e))?;
+        program.debug.source = Some(file.to_string());
+        Ok(program)
+    }
+
+    // 编译脚本，调试信息中记下文件名
+    pub fn compile(file: &str, bytes: &[u8]) -> Result<Program, String> {
+        let source = std::str::from_utf8(bytes).map_err(|_| format!("{}: not utf-8 text", file))?;
+        let mut program = compiler::compile(source).map_err(|e| format!("{}:{}", file, e))?;
+        program.debug.source = Some(file.to_string());
+        Ok(program)
+    }
+
+    // 加入常量池，已有相同常量时复用，返回其下标
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        if let Some(i) = self.constants.iter().position(|c| same_constant(c, &value)) {
+            return i;
+        }
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+}
+
+// 浮点数按位比较，保证NaN、0.0与-0.0各自独立
+fn same_constant(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(x), Value::Float(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
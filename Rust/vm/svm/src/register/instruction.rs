@@ -0,0 +1,263 @@
+use crate::instruction::OpCode;
+use crate::leb128;
+
+// 源操作数：当前帧的寄存器或常量池中的常量。
+// 编码为一个无符号LEB128整数，最低位为1表示常量，其余位为寄存器号或常量下标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Src {
+    Reg(usize),
+    Const(usize),
+}
+
+impl Src {
+    fn raw(self) -> u64 {
+        match self {
+            Src::Reg(r) => (r as u64) << 1,
+            Src::Const(k) => (k as u64) << 1 | 1,
+        }
+    }
+
+    fn from_raw(raw: u64) -> Src {
+        if raw & 1 == 0 {
+            Src::Reg((raw >> 1) as usize)
+        } else {
+            Src::Const((raw >> 1) as usize)
+        }
+    }
+}
+
+// 寄存器指令。dst和base是目标寄存器，寄存器号都相对当前帧；跳转目标是寄存器字节码的地址。
+//
+// 编码为一个字节的操作码，后面依次是各个操作数的LEB128编码，Src按上面的规则编码，其余为无符号整数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegOp {
+    Move { dst: usize, src: Src },
+    // opcode为栈指令中的add/sub/mul/div、比较或and/or
+    Binary { opcode: OpCode, dst: usize, lhs: Src, rhs: Src },
+    Not { dst: usize, src: Src },
+    Neg { dst: usize, src: Src },
+    Print(Src),
+    ReadLine(usize),
+    Jump(usize),
+    // cond的真假等于when时跳转
+    JumpIf { when: bool, cond: Src, target: usize },
+    // 目标为栈字节码的地址，运行时查表换成寄存器字节码的地址
+    JumpIndirect(Src),
+    Select { dst: usize, cond: Src, then: Src, otherwise: Src },
+    // 参数在寄存器base..base+argc中，被调用函数的帧从base开始，返回值写入base
+    Call { base: usize, argc: usize, target: usize },
+    CallIndirect { base: usize, argc: usize, target: Src },
+    // name为函数名在常量池中的下标
+    CallNative { base: usize, argc: usize, name: usize },
+    Return(Src),
+    // depth为执行后当前帧的栈深度，停机时只保留这些寄存器，使栈与栈式后端一致
+    Exit { code: Src, depth: usize },
+    Halt { depth: usize },
+    // 下标不是常量时按下标访问局部变量，下标必须小于limit
+    LoadLocal { dst: usize, index: Src, limit: usize },
+    StoreLocal { index: Src, value: Src, limit: usize },
+    NewArray { dst: usize, len: Src },
+    ArrayGet { dst: usize, array: Src, index: Src },
+    ArraySet { array: Src, index: Src, value: Src },
+    ArrayLen { dst: usize, src: Src },
+    Concat { dst: usize, lhs: Src, rhs: Src },
+    Substring { dst: usize, string: Src, start: Src, len: Src },
+}
+
+// 可以作为Binary的栈指令，操作码依次为1..=12
+pub const BINARY_OPCODES: [OpCode; 12] = [
+    OpCode::Add,
+    OpCode::Sub,
+    OpCode::Mul,
+    OpCode::Div,
+    OpCode::Eq,
+    OpCode::Ne,
+    OpCode::Lt,
+    OpCode::Le,
+    OpCode::Gt,
+    OpCode::Ge,
+    OpCode::And,
+    OpCode::Or,
+];
+
+const MOVE: u8 = 0;
+const NOT: u8 = 13;
+const PRINT: u8 = 14;
+const READ_LINE: u8 = 15;
+const JUMP: u8 = 16;
+const JUMP_IF_FALSE: u8 = 17;
+const JUMP_IF_TRUE: u8 = 18;
+const JUMP_INDIRECT: u8 = 19;
+const SELECT: u8 = 20;
+const CALL: u8 = 21;
+const CALL_INDIRECT: u8 = 22;
+const CALL_NATIVE: u8 = 23;
+const RETURN: u8 = 24;
+const EXIT: u8 = 25;
+const HALT: u8 = 26;
+const LOAD_LOCAL: u8 = 27;
+const STORE_LOCAL: u8 = 28;
+const NEW_ARRAY: u8 = 29;
+const ARRAY_GET: u8 = 30;
+const ARRAY_SET: u8 = 31;
+const ARRAY_LEN: u8 = 32;
+const CONCAT: u8 = 33;
+const SUBSTRING: u8 = 34;
+const NEG: u8 = 35;
+
+// 操作数的写法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg {
+    Reg(usize),
+    Src(Src),
+    Count(usize),
+    Addr(usize),
+    Const(usize),
+}
+
+impl RegOp {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            RegOp::Move { .. } => "move",
+            RegOp::Binary { opcode, .. } => opcode.mnemonic(),
+            RegOp::Not { .. } => "not",
+            RegOp::Neg { .. } => "neg",
+            RegOp::Print(_) => "print",
+            RegOp::ReadLine(_) => "readline",
+            RegOp::Jump(_) => "jump",
+            RegOp::JumpIf { when: false, .. } => "jumpiffalse",
+            RegOp::JumpIf { when: true, .. } => "jumpiftrue",
+            RegOp::JumpIndirect(_) => "jumpindirect",
+            RegOp::Select { .. } => "select",
+            RegOp::Call { .. } => "call",
+            RegOp::CallIndirect { .. } => "callindirect",
+            RegOp::CallNative { .. } => "callnative",
+            RegOp::Return(_) => "return",
+            RegOp::Exit { .. } => "exit",
+            RegOp::Halt { .. } => "halt",
+            RegOp::LoadLocal { .. } => "loadlocal",
+            RegOp::StoreLocal { .. } => "storelocal",
+            RegOp::NewArray { .. } => "newarray",
+            RegOp::ArrayGet { .. } => "arrayget",
+            RegOp::ArraySet { .. } => "arrayset",
+            RegOp::ArrayLen { .. } => "arraylen",
+            RegOp::Concat { .. } => "concat",
+            RegOp::Substring { .. } => "substring",
+        }
+    }
+
+    // 操作码和按编码顺序排列的操作数
+    pub fn args(&self) -> (u8, Vec<Arg>) {
+        use Arg::*;
+        match *self {
+            RegOp::Move { dst, src } => (MOVE, vec![Reg(dst), Src(src)]),
+            RegOp::Binary { opcode, dst, lhs, rhs } => {
+                let index = BINARY_OPCODES.iter().position(|&op| op == opcode).expect("not a binary opcode");
+                (1 + index as u8, vec![Reg(dst), Src(lhs), Src(rhs)])
+            }
+            RegOp::Not { dst, src } => (NOT, vec![Reg(dst), Src(src)]),
+            RegOp::Neg { dst, src } => (NEG, vec![Reg(dst), Src(src)]),
+            RegOp::Print(src) => (PRINT, vec![Src(src)]),
+            RegOp::ReadLine(dst) => (READ_LINE, vec![Reg(dst)]),
+            RegOp::Jump(target) => (JUMP, vec![Addr(target)]),
+            RegOp::JumpIf { when, cond, target } => (if when { JUMP_IF_TRUE } else { JUMP_IF_FALSE }, vec![Src(cond), Addr(target)]),
+            RegOp::JumpIndirect(target) => (JUMP_INDIRECT, vec![Src(target)]),
+            RegOp::Select { dst, cond, then, otherwise } => (SELECT, vec![Reg(dst), Src(cond), Src(then), Src(otherwise)]),
+            RegOp::Call { base, argc, target } => (CALL, vec![Reg(base), Count(argc), Addr(target)]),
+            RegOp::CallIndirect { base, argc, target } => (CALL_INDIRECT, vec![Reg(base), Count(argc), Src(target)]),
+            RegOp::CallNative { base, argc, name } => (CALL_NATIVE, vec![Reg(base), Count(argc), Const(name)]),
+            RegOp::Return(src) => (RETURN, vec![Src(src)]),
+            RegOp::Exit { code, depth } => (EXIT, vec![Src(code), Count(depth)]),
+            RegOp::Halt { depth } => (HALT, vec![Count(depth)]),
+            RegOp::LoadLocal { dst, index, limit } => (LOAD_LOCAL, vec![Reg(dst), Src(index), Count(limit)]),
+            RegOp::StoreLocal { index, value, limit } => (STORE_LOCAL, vec![Src(index), Src(value), Count(limit)]),
+            RegOp::NewArray { dst, len } => (NEW_ARRAY, vec![Reg(dst), Src(len)]),
+            RegOp::ArrayGet { dst, array, index } => (ARRAY_GET, vec![Reg(dst), Src(array), Src(index)]),
+            RegOp::ArraySet { array, index, value } => (ARRAY_SET, vec![Src(array), Src(index), Src(value)]),
+            RegOp::ArrayLen { dst, src } => (ARRAY_LEN, vec![Reg(dst), Src(src)]),
+            RegOp::Concat { dst, lhs, rhs } => (CONCAT, vec![Reg(dst), Src(lhs), Src(rhs)]),
+            RegOp::Substring { dst, string, start, len } => (SUBSTRING, vec![Reg(dst), Src(string), Src(start), Src(len)]),
+        }
+    }
+}
+
+// 解码后的一条寄存器指令，len为其占用的字节数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegInstruction {
+    pub op: RegOp,
+    pub len: usize,
+}
+
+impl RegInstruction {
+    pub fn new(op: RegOp) -> Self {
+        let mut out = Vec::new();
+        RegInstruction { op, len: 0 }.encode(&mut out);
+        RegInstruction { op, len: out.len() }
+    }
+
+    // 解码codes[pc..]处的一条指令，非法操作码或操作数不完整时返回None
+    pub fn decode(codes: &[u8], pc: usize) -> Option<RegInstruction> {
+        let tag = *codes.get(pc)?;
+        let mut r = Reader { codes, pos: pc + 1 };
+        let op = match tag {
+            MOVE => RegOp::Move { dst: r.uint()?, src: r.src()? },
+            1..=12 => RegOp::Binary { opcode: BINARY_OPCODES[tag as usize - 1], dst: r.uint()?, lhs: r.src()?, rhs: r.src()? },
+            NOT => RegOp::Not { dst: r.uint()?, src: r.src()? },
+            NEG => RegOp::Neg { dst: r.uint()?, src: r.src()? },
+            PRINT => RegOp::Print(r.src()?),
+            READ_LINE => RegOp::ReadLine(r.uint()?),
+            JUMP => RegOp::Jump(r.uint()?),
+            JUMP_IF_FALSE | JUMP_IF_TRUE => RegOp::JumpIf { when: tag == JUMP_IF_TRUE, cond: r.src()?, target: r.uint()? },
+            JUMP_INDIRECT => RegOp::JumpIndirect(r.src()?),
+            SELECT => RegOp::Select { dst: r.uint()?, cond: r.src()?, then: r.src()?, otherwise: r.src()? },
+            CALL => RegOp::Call { base: r.uint()?, argc: r.uint()?, target: r.uint()? },
+            CALL_INDIRECT => RegOp::CallIndirect { base: r.uint()?, argc: r.uint()?, target: r.src()? },
+            CALL_NATIVE => RegOp::CallNative { base: r.uint()?, argc: r.uint()?, name: r.uint()? },
+            RETURN => RegOp::Return(r.src()?),
+            EXIT => RegOp::Exit { code: r.src()?, depth: r.uint()? },
+            HALT => RegOp::Halt { depth: r.uint()? },
+            LOAD_LOCAL => RegOp::LoadLocal { dst: r.uint()?, index: r.src()?, limit: r.uint()? },
+            STORE_LOCAL => RegOp::StoreLocal { index: r.src()?, value: r.src()?, limit: r.uint()? },
+            NEW_ARRAY => RegOp::NewArray { dst: r.uint()?, len: r.src()? },
+            ARRAY_GET => RegOp::ArrayGet { dst: r.uint()?, array: r.src()?, index: r.src()? },
+            ARRAY_SET => RegOp::ArraySet { array: r.src()?, index: r.src()?, value: r.src()? },
+            ARRAY_LEN => RegOp::ArrayLen { dst: r.uint()?, src: r.src()? },
+            CONCAT => RegOp::Concat { dst: r.uint()?, lhs: r.src()?, rhs: r.src()? },
+            SUBSTRING => RegOp::Substring { dst: r.uint()?, string: r.src()?, start: r.src()?, len: r.src()? },
+            _ => return None,
+        };
+        Some(RegInstruction { op, len: r.pos - pc })
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, args) = self.op.args();
+        out.push(tag);
+        for arg in args {
+            let raw = match arg {
+                Arg::Src(src) => src.raw(),
+                Arg::Reg(v) | Arg::Count(v) | Arg::Addr(v) | Arg::Const(v) => v as u64,
+            };
+            leb128::write_unsigned(out, raw);
+        }
+    }
+}
+
+// 依次读取操作数
+struct Reader<'a> {
+    codes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn uint(&mut self) -> Option<usize> {
+        let (v, len) = leb128::read_unsigned(self.codes, self.pos)?;
+        self.pos += len;
+        Some(v as usize)
+    }
+
+    fn src(&mut self) -> Option<Src> {
+        let (v, len) = leb128::read_unsigned(self.codes, self.pos)?;
+        self.pos += len;
+        Some(Src::from_raw(v))
+    }
+}
@@ -0,0 +1,75 @@
+// 基于寄存器的指令集，以及从栈字节码到寄存器字节码的翻译。
+//
+// 栈上第d个位置（相对当前帧）对应寄存器d，局部变量就是前几个寄存器，因此`loadlocal 1; push 1; add; storelocal 1`
+// 这样的四条栈指令翻译为一条`add r1, r1, 1`。每个函数的帧都有registers个寄存器，调用时参数所在的寄存器
+// 成为被调用函数帧的前几个寄存器，返回值写回其中第一个。
+//
+// 翻译依赖verifier的分析结果，只有校验通过的程序才能翻译；运行时的类型检查和出错信息与栈式后端相同，
+// 出错地址是对应的栈指令的地址。用Vm::set_backend(Backend::Register)选择寄存器后端。
+
+mod instruction;
+mod translate;
+
+pub use instruction::*;
+pub use translate::translate;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::disassembler::literal;
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RegProgram {
+    pub codes: Vec<u8>,
+    // 源操作数中引用的常量，包括push的整数
+    pub constants: Vec<Value>,
+    // 每帧的寄存器个数
+    pub registers: usize,
+    // 栈字节码中的跳转目标和函数入口 -> 寄存器字节码中的地址
+    pub labels: BTreeMap<usize, usize>,
+    // (寄存器指令地址, 栈指令地址)，按地址递增，用于报告出错位置
+    pub origins: Vec<(usize, usize)>,
+}
+
+impl RegProgram {
+    // 寄存器指令addr是由哪条栈指令翻译来的
+    pub fn origin(&self, addr: usize) -> usize {
+        match self.origins.binary_search_by_key(&addr, |&(a, _)| a) {
+            Ok(i) => self.origins[i].1,
+            Err(0) => 0,
+            Err(i) => self.origins[i - 1].1,
+        }
+    }
+
+    // 按顺序解码全部指令
+    pub fn instructions(&self) -> Vec<(usize, RegInstruction)> {
+        let mut instructions = Vec::new();
+        let mut addr = 0;
+        while let Some(instruction) = RegInstruction::decode(&self.codes, addr) {
+            instructions.push((addr, instruction));
+            addr += instruction.len;
+        }
+        instructions
+    }
+}
+
+// 每行一条指令，寄存器写作r0、r1，常量写作字面量，跳转目标写作四位地址
+pub fn disassemble(program: &RegProgram) -> String {
+    let mut out = String::new();
+    for (addr, instruction) in program.instructions() {
+        writeln!(out, "{:04}  {}", addr, instruction_text(&instruction, &program.constants)).unwrap();
+    }
+    out
+}
+
+// 单条指令的汇编文本
+pub fn instruction_text(instruction: &RegInstruction, constants: &[Value]) -> String {
+    let (_, args) = instruction.op.args();
+    let args: Vec<String> = args.iter().map(|arg| match *arg {
+        Arg::Reg(r) | Arg::Src(Src::Reg(r)) => format!("r{}", r),
+        Arg::Src(Src::Const(k)) | Arg::Const(k) => constants.get(k).map_or(format!("#{}", k), literal),
+        Arg::Count(n) => n.to_string(),
+        Arg::Addr(target) => format!("{:04}", target),
+    }).collect();
+    format!("{:<12} {}", instruction.op.mnemonic(), args.join(", "))
+}
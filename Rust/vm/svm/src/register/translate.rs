@@ -0,0 +1,414 @@
+// 栈字节码 -> 寄存器字节码。
+//
+// 按地址顺序逐条翻译可达的指令，基本块内用slots记录各栈位置上的值在哪里：Src::Reg(i)表示已经在对应的寄存器中，
+// Src::Reg(j)（j < i）表示与寄存器j相同，Src::Const表示常量。push和常量下标的loadlocal只改写slots，
+// 运算指令直接读取这些位置，结果写入栈顶对应的寄存器。在基本块的边界、跳转、调用和停机之前把尚未写入寄存器的
+// 位置都写进去（flush），使各条路径汇合时寄存器与栈的内容一致。
+//
+// 若slots中有位置引用寄存器j，则位置j一定已经在寄存器j中，所以flush按位置从低到高写入不会覆盖还要读取的寄存器；
+// storelocal改写寄存器j之前先把引用它的位置写入各自的寄存器。
+
+use std::collections::{BTreeSet, HashMap};
+use super::{RegInstruction, RegOp, RegProgram, Src};
+use crate::instruction::*;
+use crate::program::Program;
+use crate::value::Value;
+use crate::verifier::{verify, Analysis, VerifyError};
+
+pub fn translate(program: &Program) -> Result<RegProgram, VerifyError> {
+    let analysis = verify(program)?;
+    let mut leaders: BTreeSet<usize> = analysis.targets.values().flatten().copied().collect();
+    leaders.extend(analysis.functions.keys());
+    leaders.insert(0);
+    let mut translator = Translator {
+        program,
+        analysis: &analysis,
+        leaders,
+        code: Vec::new(),
+        labels: HashMap::new(),
+        constants: Vec::new(),
+        interned: HashMap::new(),
+        slots: Vec::new(),
+        origin: 0,
+        open: false,
+    };
+    translator.run();
+    Ok(translator.finish())
+}
+
+// 常量去重用的键，浮点数按位比较，避免0.0与-0.0合并
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(String),
+}
+
+struct Translator<'a> {
+    program: &'a Program,
+    analysis: &'a Analysis,
+    // 基本块的起点：跳转目标、函数入口和地址0
+    leaders: BTreeSet<usize>,
+    // 生成的指令及其对应的栈指令地址，跳转目标暂时是栈字节码的地址
+    code: Vec<(RegOp, usize)>,
+    // 基本块起点 -> code中的下标
+    labels: HashMap<usize, usize>,
+    constants: Vec<Value>,
+    interned: HashMap<Key, usize>,
+    slots: Vec<Src>,
+    // 正在翻译的栈指令地址
+    origin: usize,
+    // 上一条指令之后是否会继续执行下一条
+    open: bool,
+}
+
+impl<'a> Translator<'a> {
+    fn run(&mut self) {
+        let analysis = self.analysis;
+        // 已经与前面的指令合并翻译的最后一条指令
+        let mut fused = None;
+        for (&pc, &instruction) in &analysis.instructions {
+            let depth = match analysis.depths.get(&pc) {
+                Some(_) if fused.is_some_and(|last| pc <= last) => continue,
+                Some(&depth) => depth,
+                None => continue,
+            };
+            self.origin = pc;
+            if self.leaders.contains(&pc) {
+                if self.open {
+                    self.flush();
+                }
+                self.labels.insert(pc, self.code.len());
+                self.slots = (0..depth).map(Src::Reg).collect();
+            }
+            self.open = true;
+            fused = self.instruction(pc, instruction);
+            let last = fused.unwrap_or(pc);
+            // 执行到字节码末尾时停机
+            if self.open && last + analysis.instructions[&last].len >= self.program.codes.len() {
+                self.flush();
+                let depth = self.slots.len();
+                self.emit(RegOp::Halt { depth });
+                self.open = false;
+            }
+        }
+    }
+
+    // 翻译一条指令，与后面的指令合并翻译时返回合并的最后一条指令的地址
+    fn instruction(&mut self, pc: usize, instruction: Instruction) -> Option<usize> {
+        let opcode = instruction.opcode;
+        let next = pc + instruction.len;
+        match opcode {
+            OpCode::Push => {
+                if let Operand::Int(v) = instruction.operand {
+                    let src = self.constant(Value::Int(v));
+                    self.slots.push(src);
+                }
+            }
+            OpCode::PushConst => {
+                if let Operand::Const(index) = instruction.operand {
+                    let src = self.constant(self.program.constants[index].clone());
+                    self.slots.push(src);
+                }
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le
+            | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                return self.result(next, |dst| RegOp::Binary { opcode, dst, lhs, rhs });
+            }
+            OpCode::Not => {
+                let src = self.pop();
+                return self.result(next, |dst| RegOp::Not { dst, src });
+            }
+            OpCode::Neg => {
+                let src = self.pop();
+                return self.result(next, |dst| RegOp::Neg { dst, src });
+            }
+            OpCode::NewArray => {
+                let len = self.pop();
+                return self.result(next, |dst| RegOp::NewArray { dst, len });
+            }
+            OpCode::ArrayLen => {
+                let src = self.pop();
+                return self.result(next, |dst| RegOp::ArrayLen { dst, src });
+            }
+            OpCode::ArrayGet => {
+                let index = self.pop();
+                let array = self.pop();
+                return self.result(next, |dst| RegOp::ArrayGet { dst, array, index });
+            }
+            OpCode::ArraySet => {
+                let value = self.pop();
+                let index = self.pop();
+                let array = self.pop();
+                self.emit(RegOp::ArraySet { array, index, value });
+            }
+            OpCode::Concat => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                return self.result(next, |dst| RegOp::Concat { dst, lhs, rhs });
+            }
+            OpCode::Substring => {
+                let len = self.pop();
+                let start = self.pop();
+                let string = self.pop();
+                return self.result(next, |dst| RegOp::Substring { dst, string, start, len });
+            }
+            OpCode::Print => {
+                let src = self.pop();
+                self.emit(RegOp::Print(src));
+            }
+            OpCode::ReadLine => return self.result(next, RegOp::ReadLine),
+            OpCode::If => {
+                let otherwise = self.pop();
+                let then = self.pop();
+                let cond = self.pop();
+                // `if; jmp`选择跳转目标时翻译为条件跳转
+                let fusable = self.follows(next, OpCode::Jmp).is_some();
+                if let (true, Some(then), Some(otherwise)) = (fusable, self.address(then), self.address(otherwise)) {
+                    self.flush();
+                    self.emit(RegOp::JumpIf { when: true, cond, target: then });
+                    self.emit(RegOp::Jump(otherwise));
+                    self.open = false;
+                    return Some(next);
+                }
+                return self.result(next, |dst| RegOp::Select { dst, cond, then, otherwise });
+            }
+            OpCode::Jmp => {
+                let target = self.pop();
+                self.flush();
+                match self.address(target) {
+                    Some(addr) => self.emit(RegOp::Jump(addr)),
+                    None => self.emit(RegOp::JumpIndirect(target)),
+                }
+                self.open = false;
+            }
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                let cond = self.pop();
+                self.flush();
+                if let Operand::Target(target) = instruction.operand {
+                    let target = target.resolve(pc) as usize;
+                    self.emit(RegOp::JumpIf { when: opcode == OpCode::JmpIfNonZero, cond, target });
+                }
+            }
+            OpCode::Call => {
+                let target = self.pop();
+                self.pop();
+                let argc = self.analysis.arg_counts[&pc];
+                let base = self.slots.len() - argc;
+                self.flush();
+                match self.address(target) {
+                    Some(target) => self.emit(RegOp::Call { base, argc, target }),
+                    None => self.emit(RegOp::CallIndirect { base, argc, target }),
+                }
+                self.slots.truncate(base);
+                self.slots.push(Src::Reg(base));
+            }
+            OpCode::CallNative => {
+                self.pop();
+                let argc = self.analysis.arg_counts[&pc];
+                let base = self.slots.len() - argc;
+                self.flush();
+                if let Operand::Const(index) = instruction.operand {
+                    if let Src::Const(name) = self.constant(self.program.constants[index].clone()) {
+                        self.emit(RegOp::CallNative { base, argc, name });
+                    }
+                }
+                self.slots.truncate(base);
+                self.slots.push(Src::Reg(base));
+            }
+            OpCode::Return => {
+                let src = self.pop();
+                self.emit(RegOp::Return(src));
+                self.open = false;
+            }
+            OpCode::Exit => {
+                let code = self.pop();
+                self.flush();
+                let depth = self.slots.len();
+                self.emit(RegOp::Exit { code, depth });
+                self.open = false;
+            }
+            OpCode::LoadLocal => {
+                let index = self.pop();
+                match self.local(index) {
+                    Some(i) => self.slots.push(self.slots[i]),
+                    None => {
+                        self.flush();
+                        let limit = self.slots.len();
+                        return self.result(next, |dst| RegOp::LoadLocal { dst, index, limit });
+                    }
+                }
+            }
+            OpCode::StoreLocal => {
+                let index = self.pop();
+                let value = self.pop();
+                match self.local(index) {
+                    Some(i) => {
+                        self.clobber(i);
+                        if value != Src::Reg(i) {
+                            self.emit(RegOp::Move { dst: i, src: value });
+                        }
+                        self.slots[i] = Src::Reg(i);
+                    }
+                    None => {
+                        self.flush();
+                        let limit = self.slots.len();
+                        self.emit(RegOp::StoreLocal { index, value, limit });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn emit(&mut self, op: RegOp) {
+        self.code.push((op, self.origin));
+    }
+
+    // 结果写入栈顶对应的寄存器。后面紧跟`push i; storelocal`时直接写入局部变量i，返回storelocal的地址
+    fn result(&mut self, next: usize, op: impl FnOnce(usize) -> RegOp) -> Option<usize> {
+        let dst = self.slots.len();
+        let store = self.follows(next, OpCode::Push).and_then(|push| {
+            let local = match push.operand {
+                Operand::Int(i) if i >= 0 && (i as usize) < dst => i as usize,
+                _ => return None,
+            };
+            self.follows(next + push.len, OpCode::StoreLocal).map(|_| (local, next + push.len))
+        });
+        match store {
+            Some((local, last)) => {
+                self.clobber(local);
+                self.emit(op(local));
+                self.slots[local] = Src::Reg(local);
+                Some(last)
+            }
+            None => {
+                self.emit(op(dst));
+                self.slots.push(Src::Reg(dst));
+                None
+            }
+        }
+    }
+
+    // 位于addr且不是基本块起点的opcode指令
+    fn follows(&self, addr: usize, opcode: OpCode) -> Option<Instruction> {
+        match self.analysis.instructions.get(&addr) {
+            Some(&instruction) if instruction.opcode == opcode && !self.leaders.contains(&addr) => Some(instruction),
+            _ => None,
+        }
+    }
+
+    // 寄存器i将被改写，先把引用它的其他位置写入各自的寄存器
+    fn clobber(&mut self, i: usize) {
+        for k in 0..self.slots.len() {
+            if k != i && self.slots[k] == Src::Reg(i) {
+                self.materialize(k);
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Src {
+        self.slots.pop().expect("verified stack depth")
+    }
+
+    // 把第i个位置的值写入寄存器i
+    fn materialize(&mut self, i: usize) {
+        if self.slots[i] != Src::Reg(i) {
+            self.emit(RegOp::Move { dst: i, src: self.slots[i] });
+            self.slots[i] = Src::Reg(i);
+        }
+    }
+
+    fn flush(&mut self) {
+        for i in 0..self.slots.len() {
+            self.materialize(i);
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> Src {
+        let key = match &value {
+            Value::Nil => Key::Nil,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Int(i) => Key::Int(*i),
+            Value::Float(x) => Key::Float(x.to_bits()),
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Obj(_) => unreachable!("constant pool holds no heap objects"),
+        };
+        let constants = &mut self.constants;
+        let index = *self.interned.entry(key).or_insert_with(|| {
+            constants.push(value);
+            constants.len() - 1
+        });
+        Src::Const(index)
+    }
+
+    fn int(&self, src: Src) -> Option<i64> {
+        match src {
+            Src::Const(k) => match self.constants[k] {
+                Value::Int(v) => Some(v),
+                _ => None,
+            },
+            Src::Reg(_) => None,
+        }
+    }
+
+    // 常量的跳转目标，必须是基本块的起点
+    fn address(&self, src: Src) -> Option<usize> {
+        self.int(src).filter(|&v| v >= 0 && self.leaders.contains(&(v as usize))).map(|v| v as usize)
+    }
+
+    // 常量且不越界的局部变量下标
+    fn local(&self, src: Src) -> Option<usize> {
+        self.int(src).filter(|&v| v >= 0 && (v as usize) < self.slots.len()).map(|v| v as usize)
+    }
+
+    // 确定各条指令的地址，跳转目标换成寄存器字节码的地址。地址的编码长度取决于地址本身，反复计算直到不再变化
+    fn finish(self) -> RegProgram {
+        let registers = self.analysis.depths.values().max().map_or(0, |&depth| depth + 1);
+        let mut addrs = vec![0; self.code.len() + 1];
+        loop {
+            let mut changed = false;
+            let mut addr = 0;
+            for (i, &(op, _)) in self.code.iter().enumerate() {
+                if addrs[i] != addr {
+                    addrs[i] = addr;
+                    changed = true;
+                }
+                addr += RegInstruction::new(self.resolve(op, &addrs)).len;
+            }
+            if addrs[self.code.len()] != addr {
+                addrs[self.code.len()] = addr;
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut codes = Vec::new();
+        for &(op, _) in &self.code {
+            RegInstruction::new(self.resolve(op, &addrs)).encode(&mut codes);
+        }
+        RegProgram {
+            codes,
+            constants: self.constants.clone(),
+            registers,
+            labels: self.labels.iter().map(|(&pc, &i)| (pc, addrs[i])).collect(),
+            origins: self.code.iter().enumerate().map(|(i, &(_, origin))| (addrs[i], origin)).collect(),
+        }
+    }
+
+    fn resolve(&self, op: RegOp, addrs: &[usize]) -> RegOp {
+        let addr = |target: usize| addrs[self.labels[&target]];
+        match op {
+            RegOp::Jump(target) => RegOp::Jump(addr(target)),
+            RegOp::JumpIf { when, cond, target } => RegOp::JumpIf { when, cond, target: addr(target) },
+            RegOp::Call { base, argc, target } => RegOp::Call { base, argc, target: addr(target) },
+            op => op,
+        }
+    }
+}
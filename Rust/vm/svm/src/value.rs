@@ -0,0 +1,91 @@
+use std::fmt;
+use crate::heap::ObjRef;
+
+// 操作数栈上的值
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    // 常量池中的字符串，交给宿主函数的参数和宿主函数的返回值也用这种形式
+    Str(String),
+    // 堆上的字符串或数组，内容要通过Heap访问
+    Obj(ObjRef),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Obj(_) => "object",
+        }
+    }
+
+    // nil、false、0和0.0为假，其余都为真
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Float(f) => *f != 0.0,
+            Value::Str(_) | Value::Obj(_) => true,
+        }
+    }
+
+    pub fn as_obj(&self) -> Option<ObjRef> {
+        match self {
+            Value::Obj(r) => Some(*r),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            // 保留小数点，与整数区分开
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(s) => write!(f, "{}", s),
+            // 没有堆无法显示内容，用Heap::display代替
+            Value::Obj(r) => write!(f, "<object #{}>", r.index()),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Str(v)
+    }
+}
@@ -0,0 +1,459 @@
+// 字节码校验器，在执行前对整个程序做一次静态检查：
+//
+// - 所有字节都能解码为合法指令，常量下标不越界；
+// - jmp/call的目标是编译期可知的常量（来自push，可经过if选择），且落在指令边界上，
+//   条件跳转立即数给出的目标也要落在指令边界上；
+// - call和callnative的参数个数是常量，同一函数的参数个数一致，callnative的函数名是字符串常量；
+// - 各控制流汇合处栈深度一致，任何路径都不会出现栈下溢，loadlocal/storelocal的常量下标不越界。
+//
+// 栈深度都是相对当前帧计算的：主程序从深度0开始，函数入口的深度为参数个数。
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use crate::instruction::*;
+use crate::program::Program;
+use crate::value::Value;
+
+// 一个栈位置上可能的常量值超过这个数量后视为未知
+const MAX_TRACKED_VALUES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    InvalidOpcode(u8),
+    TruncatedOperand,
+    ConstantOutOfRange(usize),
+    StackUnderflow { needed: usize, depth: usize },
+    DepthMismatch { expected: usize, found: usize },
+    UnknownTarget,
+    TargetOutOfRange(i64),
+    TargetNotOnBoundary(usize),
+    UnknownArgCount,
+    ArgCountMismatch { expected: usize, found: usize },
+    LocalOutOfRange(i64),
+    ReturnOutsideFunction,
+    // 同一段代码既能从主程序执行到，又能在函数中执行到，其中的return是否合法取决于路径
+    MixedFunctionCode,
+    NativeNameNotString(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub pc: usize,
+    // 非法字节无法解码时为None
+    pub opcode: Option<OpCode>,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            VerifyErrorKind::InvalidOpcode(byte) => write!(f, "invalid opcode {}", byte)?,
+            VerifyErrorKind::TruncatedOperand => write!(f, "truncated operand")?,
+            VerifyErrorKind::ConstantOutOfRange(index) => write!(f, "constant {} out of range", index)?,
+            VerifyErrorKind::StackUnderflow { needed, depth } => {
+                write!(f, "stack underflow: needs {} values, stack depth is {}", needed, depth)?
+            }
+            VerifyErrorKind::DepthMismatch { expected, found } => {
+                write!(f, "inconsistent stack depth: {} on one path, {} on another", expected, found)?
+            }
+            VerifyErrorKind::UnknownTarget => write!(f, "jump target is not a constant")?,
+            VerifyErrorKind::TargetOutOfRange(target) => write!(f, "jump target {} out of range", target)?,
+            VerifyErrorKind::TargetNotOnBoundary(target) => {
+                write!(f, "jump target {} is not on an instruction boundary", target)?
+            }
+            VerifyErrorKind::UnknownArgCount => write!(f, "argument count is not a constant")?,
+            VerifyErrorKind::ArgCountMismatch { expected, found } => {
+                write!(f, "function called with {} arguments, previously with {}", found, expected)?
+            }
+            VerifyErrorKind::LocalOutOfRange(index) => write!(f, "local {} out of range", index)?,
+            VerifyErrorKind::ReturnOutsideFunction => write!(f, "return outside of a function")?,
+            VerifyErrorKind::MixedFunctionCode => write!(f, "code is reachable both from main code and from a function")?,
+            VerifyErrorKind::NativeNameNotString(index) => write!(f, "native function name (constant {}) is not a string", index)?,
+        }
+        match self.opcode {
+            Some(opcode) => write!(f, " at {:04} ({})", self.pc, opcode.mnemonic()),
+            None => write!(f, " at {:04}", self.pc),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+// 抽象的栈位置：可能的整数常量（None表示未知）以及产生这些值的push指令地址
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+struct Slot {
+    values: Option<BTreeSet<i64>>,
+    origins: BTreeSet<usize>,
+}
+
+impl Slot {
+    fn unknown() -> Self {
+        Slot::default()
+    }
+
+    fn constant(value: i64, origin: usize) -> Self {
+        Slot { values: Some(std::iter::once(value).collect()), origins: std::iter::once(origin).collect() }
+    }
+
+    fn join(&self, other: &Slot) -> Slot {
+        let values = match (&self.values, &other.values) {
+            (Some(a), Some(b)) => {
+                let union: BTreeSet<i64> = a.union(b).copied().collect();
+                if union.len() > MAX_TRACKED_VALUES { None } else { Some(union) }
+            }
+            _ => None,
+        };
+        Slot { values, origins: self.origins.union(&other.origins).copied().collect() }
+    }
+
+    // 唯一确定的常量值
+    fn single(&self) -> Option<i64> {
+        match &self.values {
+            Some(set) if set.len() == 1 => set.iter().next().copied(),
+            _ => None,
+        }
+    }
+}
+
+// 某条指令执行前的抽象状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    stack: Vec<Slot>,
+    // 是否在函数中执行，决定return是否合法；汇合的各条路径必须一致
+    in_function: bool,
+}
+
+impl State {
+    fn join(&self, other: &State, pc: usize, opcode: OpCode) -> Result<State, VerifyError> {
+        if self.stack.len() != other.stack.len() {
+            return Err(VerifyError {
+                pc,
+                opcode: Some(opcode),
+                kind: VerifyErrorKind::DepthMismatch { expected: self.stack.len(), found: other.stack.len() },
+            });
+        }
+        if self.in_function != other.in_function {
+            return Err(VerifyError { pc, opcode: Some(opcode), kind: VerifyErrorKind::MixedFunctionCode });
+        }
+        Ok(State {
+            stack: self.stack.iter().zip(&other.stack).map(|(a, b)| a.join(b)).collect(),
+            in_function: self.in_function,
+        })
+    }
+}
+
+// 校验通过后得到的程序信息，供后续的优化、翻译等使用
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    // 按地址排列的全部指令
+    pub instructions: BTreeMap<usize, Instruction>,
+    // 可达指令执行前的栈深度（相对当前帧）
+    pub depths: BTreeMap<usize, usize>,
+    // jmp/call和条件跳转指令的地址 -> 可能的目标地址
+    pub targets: BTreeMap<usize, BTreeSet<usize>>,
+    // 函数入口地址 -> 参数个数
+    pub functions: BTreeMap<usize, usize>,
+    // 值被用作jmp/call目标地址的push或pushconst指令
+    pub address_pushes: BTreeSet<usize>,
+    // call和callnative指令的地址 -> 参数个数
+    pub arg_counts: BTreeMap<usize, usize>,
+}
+
+impl Analysis {
+    pub fn is_reachable(&self, addr: usize) -> bool {
+        self.depths.contains_key(&addr)
+    }
+}
+
+pub fn verify(program: &Program) -> Result<Analysis, VerifyError> {
+    let mut analysis = Analysis::default();
+
+    // 线性解码全部字节码，确定指令边界
+    let mut addr = 0;
+    while addr < program.codes.len() {
+        let instruction = Instruction::decode(&program.codes, addr).map_err(|e| match e {
+            DecodeError::InvalidOpcode(byte) => VerifyError { pc: addr, opcode: None, kind: VerifyErrorKind::InvalidOpcode(byte) },
+            DecodeError::TruncatedOperand(opcode) => VerifyError { pc: addr, opcode: Some(opcode), kind: VerifyErrorKind::TruncatedOperand },
+        })?;
+        if let Operand::Const(index) = instruction.operand {
+            let error = |kind| VerifyError { pc: addr, opcode: Some(instruction.opcode), kind };
+            match program.constants.get(index) {
+                None => return Err(error(VerifyErrorKind::ConstantOutOfRange(index))),
+                Some(Value::Str(_)) => {}
+                Some(_) if instruction.opcode == OpCode::CallNative => {
+                    return Err(error(VerifyErrorKind::NativeNameNotString(index)));
+                }
+                Some(_) => {}
+            }
+        }
+        analysis.instructions.insert(addr, instruction);
+        addr += instruction.len;
+    }
+
+    let mut verifier = Verifier {
+        program,
+        analysis,
+        states: BTreeMap::new(),
+        walks: BTreeMap::new(),
+        worklist: VecDeque::new(),
+    };
+    if !program.codes.is_empty() {
+        verifier.merge(0, State { stack: Vec::new(), in_function: false }, 0, OpCode::Jmp)?;
+    }
+    while let Some(leader) = verifier.worklist.pop_front() {
+        verifier.walk(leader)?;
+    }
+
+    let mut analysis = verifier.analysis;
+    analysis.depths = verifier.walks.iter().map(|(&addr, &(_, depth))| (addr, depth)).collect();
+    Ok(analysis)
+}
+
+// 只在跳转、调用的目标和程序入口（基本块的起点）保存抽象状态，从起点顺序执行到下一个起点或跳转为止，
+// 中间的指令就地修改同一个状态。状态的大小与栈深度成正比，逐条指令保存的话，
+// 上万个局部变量的程序需要的内存是指令数乘以栈深度
+struct Verifier<'a> {
+    program: &'a Program,
+    analysis: Analysis,
+    // 基本块起点 -> 执行前的状态，即各条路径汇合后的状态
+    states: BTreeMap<usize, State>,
+    // 已经检查过的指令 -> (所在基本块的起点, 执行前的栈深度)
+    walks: BTreeMap<usize, (usize, usize)>,
+    worklist: VecDeque<usize>,
+}
+
+impl<'a> Verifier<'a> {
+    // 把state合并到基本块起点target处的状态，状态有变化时重新检查target开始的基本块
+    fn merge(&mut self, target: usize, state: State, pc: usize, opcode: OpCode) -> Result<(), VerifyError> {
+        if target >= self.program.codes.len() {
+            // 执行到字节码末尾时正常结束
+            return Ok(());
+        }
+        // 跳到已经检查过的基本块中间时，从那里拆成两个基本块，重新检查前一半以得到target处的状态
+        if !self.states.contains_key(&target) {
+            if let Some(&(leader, _)) = self.walks.get(&target) {
+                self.worklist.push_back(leader);
+            }
+        }
+        let merged = match self.states.get(&target) {
+            Some(old) => {
+                let merged = old.join(&state, target, self.analysis.instructions[&target].opcode)
+                    .map_err(|e| VerifyError { pc, opcode: Some(opcode), ..e })?;
+                if &merged == old {
+                    return Ok(());
+                }
+                merged
+            }
+            None => state,
+        };
+        self.states.insert(target, merged);
+        self.worklist.push_back(target);
+        Ok(())
+    }
+
+    // 从基本块起点leader开始顺序检查，直到跳转、停机或下一个基本块的起点
+    fn walk(&mut self, leader: usize) -> Result<(), VerifyError> {
+        let mut state = self.states[&leader].clone();
+        let mut pc = leader;
+        loop {
+            self.walks.insert(pc, (leader, state.stack.len()));
+            let instruction = self.analysis.instructions[&pc];
+            if !self.step(pc, instruction, &mut state)? {
+                return Ok(());
+            }
+            let next = pc + instruction.len;
+            if next >= self.program.codes.len() || self.states.contains_key(&next) {
+                return self.merge(next, state, pc, instruction.opcode);
+            }
+            pc = next;
+        }
+    }
+
+    // 在state上执行pc处的指令，返回是否接着执行下一条指令
+    fn step(&mut self, pc: usize, instruction: Instruction, state: &mut State) -> Result<bool, VerifyError> {
+        let opcode = instruction.opcode;
+        let error = |kind| VerifyError { pc, opcode: Some(opcode), kind };
+
+        let needed = match opcode {
+            OpCode::Push | OpCode::PushConst | OpCode::ReadLine => 0,
+            OpCode::Print | OpCode::Jmp | OpCode::Exit | OpCode::Return | OpCode::LoadLocal | OpCode::CallNative => 1,
+            OpCode::NewArray | OpCode::ArrayLen | OpCode::Not | OpCode::Neg | OpCode::JmpIfZero | OpCode::JmpIfNonZero => 1,
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Call | OpCode::StoreLocal => 2,
+            OpCode::ArrayGet | OpCode::Concat => 2,
+            OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or => 2,
+            OpCode::If | OpCode::ArraySet | OpCode::Substring => 3,
+        };
+        if state.stack.len() < needed {
+            return Err(error(VerifyErrorKind::StackUnderflow { needed, depth: state.stack.len() }));
+        }
+
+        match opcode {
+            OpCode::Push => {
+                if let Operand::Int(v) = instruction.operand {
+                    state.stack.push(Slot::constant(v, pc));
+                }
+            }
+            OpCode::PushConst => {
+                let slot = match instruction.operand {
+                    Operand::Const(index) => match self.program.constants[index] {
+                        Value::Int(v) => Slot::constant(v, pc),
+                        _ => Slot::unknown(),
+                    },
+                    _ => Slot::unknown(),
+                };
+                state.stack.push(slot);
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => {
+                state.stack.truncate(state.stack.len() - 2);
+                state.stack.push(Slot::unknown());
+            }
+            OpCode::NewArray | OpCode::ArrayLen | OpCode::ArrayGet | OpCode::Concat | OpCode::Substring
+            | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or
+            | OpCode::Not | OpCode::Neg => {
+                state.stack.truncate(state.stack.len() - needed);
+                state.stack.push(Slot::unknown());
+            }
+            OpCode::ArraySet => state.stack.truncate(state.stack.len() - 3),
+            OpCode::Print => {
+                state.stack.pop();
+            }
+            OpCode::ReadLine => state.stack.push(Slot::unknown()),
+            OpCode::If => {
+                let f = state.stack.pop().unwrap();
+                let t = state.stack.pop().unwrap();
+                let condition = state.stack.pop().unwrap();
+                let slot = match condition.single() {
+                    Some(0) => f,
+                    Some(_) => t,
+                    None => t.join(&f),
+                };
+                state.stack.push(slot);
+            }
+            // 两条路径都要检查，不论条件是否为常量
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                state.stack.pop();
+                if let Operand::Target(target) = instruction.operand {
+                    let addr = target.resolve(pc);
+                    if addr < 0 || addr as usize >= self.program.codes.len() {
+                        return Err(error(VerifyErrorKind::TargetOutOfRange(addr)));
+                    }
+                    if !self.analysis.instructions.contains_key(&(addr as usize)) {
+                        return Err(error(VerifyErrorKind::TargetNotOnBoundary(addr as usize)));
+                    }
+                    self.analysis.targets.entry(pc).or_default().insert(addr as usize);
+                    self.merge(addr as usize, state.clone(), pc, opcode)?;
+                }
+            }
+            OpCode::Jmp => {
+                let target = state.stack.pop().unwrap();
+                for addr in self.resolve_targets(pc, &target).map_err(error)? {
+                    self.merge(addr, state.clone(), pc, opcode)?;
+                }
+                return Ok(false);
+            }
+            OpCode::Call => {
+                let target = state.stack.pop().unwrap();
+                let argc = state.stack.pop().unwrap();
+                let argc = match argc.single() {
+                    Some(n) if n >= 0 => n as usize,
+                    _ => return Err(error(VerifyErrorKind::UnknownArgCount)),
+                };
+                if state.stack.len() < argc {
+                    return Err(error(VerifyErrorKind::StackUnderflow { needed: argc + 2, depth: state.stack.len() + 2 }));
+                }
+                self.analysis.arg_counts.insert(pc, argc);
+                let args = state.stack.split_off(state.stack.len() - argc);
+                for addr in self.resolve_targets(pc, &target).map_err(error)? {
+                    if let Some(&expected) = self.analysis.functions.get(&addr) {
+                        if expected != argc {
+                            return Err(error(VerifyErrorKind::ArgCountMismatch { expected, found: argc }));
+                        }
+                    }
+                    self.analysis.functions.insert(addr, argc);
+                    self.merge(addr, State { stack: args.clone(), in_function: true }, pc, opcode)?;
+                }
+                state.stack.push(Slot::unknown());
+            }
+            OpCode::CallNative => {
+                let argc = match state.stack.pop().unwrap().single() {
+                    Some(n) if n >= 0 => n as usize,
+                    _ => return Err(error(VerifyErrorKind::UnknownArgCount)),
+                };
+                if state.stack.len() < argc {
+                    return Err(error(VerifyErrorKind::StackUnderflow { needed: argc + 1, depth: state.stack.len() + 1 }));
+                }
+                self.analysis.arg_counts.insert(pc, argc);
+                state.stack.truncate(state.stack.len() - argc);
+                state.stack.push(Slot::unknown());
+            }
+            OpCode::Return => {
+                if !state.in_function {
+                    return Err(error(VerifyErrorKind::ReturnOutsideFunction));
+                }
+                return Ok(false);
+            }
+            OpCode::Exit => return Ok(false),
+            OpCode::LoadLocal => {
+                let index = state.stack.pop().unwrap();
+                let slot = match local_indexes(&index, state.stack.len()).map_err(error)? {
+                    Some(indexes) => indexes.iter().skip(1).fold(state.stack[indexes[0]].clone(), |acc, &i| acc.join(&state.stack[i])),
+                    None => Slot::unknown(),
+                };
+                state.stack.push(slot);
+            }
+            OpCode::StoreLocal => {
+                let index = state.stack.pop().unwrap();
+                let value = state.stack.pop().unwrap();
+                match local_indexes(&index, state.stack.len()).map_err(error)? {
+                    Some(indexes) if indexes.len() == 1 => state.stack[indexes[0]] = value,
+                    Some(indexes) => {
+                        for i in indexes {
+                            state.stack[i] = state.stack[i].join(&value);
+                        }
+                    }
+                    // 下标未知时任何局部变量都可能被改写
+                    None => {
+                        for slot in state.stack.iter_mut() {
+                            *slot = Slot::unknown();
+                        }
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // 解析jmp/call的目标地址集合，并记录产生目标地址的push指令
+    fn resolve_targets(&mut self, pc: usize, target: &Slot) -> Result<BTreeSet<usize>, VerifyErrorKind> {
+        let values = target.values.as_ref().ok_or(VerifyErrorKind::UnknownTarget)?;
+        let mut targets = BTreeSet::new();
+        for &value in values {
+            if value < 0 || value as usize >= self.program.codes.len() {
+                return Err(VerifyErrorKind::TargetOutOfRange(value));
+            }
+            if !self.analysis.instructions.contains_key(&(value as usize)) {
+                return Err(VerifyErrorKind::TargetNotOnBoundary(value as usize));
+            }
+            targets.insert(value as usize);
+        }
+        self.analysis.address_pushes.extend(target.origins.iter().copied());
+        self.analysis.targets.entry(pc).or_default().extend(targets.iter().copied());
+        Ok(targets)
+    }
+}
+
+// 局部变量下标为常量时检查范围，返回可能的下标；下标未知时返回None，留给运行时检查
+fn local_indexes(index: &Slot, depth: usize) -> Result<Option<Vec<usize>>, VerifyErrorKind> {
+    let values = match &index.values {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+    let mut indexes = Vec::new();
+    for &i in values {
+        if i < 0 || i as usize >= depth {
+            return Err(VerifyErrorKind::LocalOutOfRange(i));
+        }
+        indexes.push(i as usize);
+    }
+    Ok(Some(indexes))
+}
@@ -1,142 +1,841 @@
 use std::vec::Vec;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use crate::disassembler;
+use crate::error::VmError;
+use crate::heap::{GcStats, Heap, ObjRef, Object};
 use crate::instruction::*;
-use std::borrow::BorrowMut;
-use std::io::Read;
+use crate::limits::{Limit, Limits};
+use crate::bytecode::{self, FormatError};
+use crate::program::{DebugInfo, Program};
+use crate::verifier;
+use crate::value::Value;
+use crate::vmio::{StdIo, VmIo};
 
-// 基于栈的虚拟机需要一个栈，所有的操作都是基于栈的。
-static mut VmStack: Vec<u8> = Vec::new();
-static mut PointerCounter: usize = 0;
+#[cfg(feature = "dispatch-table")]
+mod dispatch_table;
+#[cfg(feature = "jit")]
+mod jit;
+mod register;
+
+// 执行指令时的错误，由run补上出错位置和操作码后转换为VmError
+enum Trap {
+    StackUnderflow,
+    DivisionByZero,
+    ArithmeticOverflow,
+    TypeMismatch { expected: &'static str, found: &'static str },
+    JumpOutOfRange(i64),
+    CallStackUnderflow,
+    LimitExceeded(Limit),
+    LocalOutOfRange(i64),
+    ConstantOutOfRange(usize),
+    Io(String),
+    UnknownNative(String),
+    Native { name: String, message: String },
+    IndexOutOfRange { index: i64, len: usize },
+    InvalidLength(i64),
+}
+
+impl Trap {
+    fn at(self, pc: usize, opcode: OpCode) -> VmError {
+        match self {
+            Trap::StackUnderflow => VmError::StackUnderflow { pc, opcode },
+            Trap::DivisionByZero => VmError::DivisionByZero { pc, opcode },
+            Trap::ArithmeticOverflow => VmError::ArithmeticOverflow { pc, opcode },
+            Trap::TypeMismatch { expected, found } => VmError::TypeMismatch { pc, opcode, expected, found },
+            Trap::JumpOutOfRange(target) => VmError::JumpOutOfRange { pc, opcode, target },
+            Trap::CallStackUnderflow => VmError::CallStackUnderflow { pc, opcode },
+            Trap::LimitExceeded(limit) => VmError::LimitExceeded { pc, opcode, limit },
+            Trap::LocalOutOfRange(index) => VmError::LocalOutOfRange { pc, opcode, index },
+            Trap::ConstantOutOfRange(index) => VmError::ConstantOutOfRange { pc, opcode, index },
+            Trap::Io(message) => VmError::Io { pc, opcode, message },
+            Trap::UnknownNative(name) => VmError::UnknownNative { pc, opcode, name },
+            Trap::Native { name, message } => VmError::Native { pc, opcode, name, message },
+            Trap::IndexOutOfRange { index, len } => VmError::IndexOutOfRange { pc, opcode, index, len },
+            Trap::InvalidLength(length) => VmError::InvalidLength { pc, opcode, length },
+        }
+    }
+}
+
+// 数组长度的上限，与堆的限制无关，避免一条newarray就耗尽内存
+pub const MAX_ARRAY_LEN: i64 = 1 << 24;
+
+// 调用帧，参数和局部变量都放在操作数栈上，从base开始
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub ret: usize,
+    pub base: usize,
+    // 被调用函数的入口地址
+    pub entry: usize,
+}
+
+// 宿主注册的函数，参数按压栈顺序传入，返回值压回栈上，出错时返回错误信息。
+// 堆上的字符串以Value::Str传入，数组仍是句柄；返回的Value::Str会分配到堆上。
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String> + Send;
+
+struct Native {
+    arity: usize,
+    function: Box<NativeFn>,
+}
+
+// Vm::run_with的钩子决定是否继续执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+// 执行程序的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    // 直接解释栈字节码
+    #[default]
+    Stack,
+    // 先翻译为寄存器字节码（见crate::register）再解释
+    Register,
+}
 
 pub struct Vm {
-    dispatch_table: HashMap<OpCode, Box<FnMut()>>,
-    codes: Vec<u8>,
+    program: Program,
+    // 预先解码的指令，下标为指令的地址，不在指令边界上的地址按该处的字节解码
+    decoded: Vec<Result<Instruction, DecodeError>>,
+    // 执行前是否先用verifier校验，verified记录当前程序是否已经校验通过
+    verify: bool,
+    verified: bool,
+    // 基于栈的虚拟机需要一个栈，所有的操作都是基于栈的。
+    stack: Vec<Value>,
+    // 程序计数器，指向下一条要执行的字节码
+    pc: usize,
+    // 调用帧栈，每次Call压入一帧
+    frames: Vec<Frame>,
+    // 执行Exit后记录的退出码
+    exit_code: Option<i32>,
+    // Print和ReadLine的输入输出，由宿主提供
+    io: Box<dyn VmIo + Send>,
+    // CallNative按名字调用的宿主函数
+    natives: HashMap<String, Native>,
+    // 设置后每条指令执行前输出地址、指令和栈
+    trace: Option<Box<dyn Write + Send>>,
+    // 资源限制，以及本次run已经消耗的指令数
+    limits: Limits,
+    fuel_used: u64,
+    // 字符串和数组所在的堆，每次run清空
+    heap: Heap,
+    backend: Backend,
+    // 寄存器后端翻译好的程序，第一次以寄存器后端运行时生成
+    compiled: Option<Box<register::Compiled>>,
+    // 热点函数编译成的机器码，jit_active表示本次执行是否使用
+    #[cfg(feature = "jit")]
+    jit: crate::jit::Jit,
+    #[cfg(feature = "jit")]
+    jit_active: bool,
 }
 
 impl Vm {
     pub fn new() -> Self {
         Vm {
-            dispatch_table: HashMap::new(),
-            codes: Vec::new(),
-        }
-    }
-
-    pub fn init(&mut self) {
-        // let add = || {
-        //     let value = self.pop() + self.pop();
-        //     self.push(value);
-        // };
-
-        // let sub = || {
-        //     let second = self.pop();
-        //     let value = self.pop() - second;
-        //     self.push(value);
-        // };
-        self.dispatch_table.insert(OpCode::Add, Box::new(|| {
-            let value = pop() + pop();
-            push(value);
-        }));
-
-        self.dispatch_table.insert(OpCode::Sub, Box::new(|| {
-            let second = pop();
-            let value = pop() - second;
-            push(value);
-        }));
-
-        self.dispatch_table.insert(OpCode::Mul, Box::new(|| {
-            let value = pop() * pop();
-            push(value)
-        }));
-
-        self.dispatch_table.insert(OpCode::Div, Box::new(|| {
-            let second = pop();
-            let value = pop() / second;
-            push(value);
-        }));
-
-        self.dispatch_table.insert(OpCode::Print, Box::new(|| {
-            print();
-        }));
-
-        self.dispatch_table.insert(OpCode::Jmp, Box::new(|| {
-            let addr = pop() as usize;
-            // if addr >= self.codes.len() {
-            //     panic!("invalid point counter addr.");
-            // }
-            unsafe {
-                PointerCounter = addr;        // fixme: 这里缺少错误处理
-            }
-        }));
+            program: Program::default(),
+            decoded: Vec::new(),
+            verify: true,
+            verified: false,
+            stack: Vec::new(),
+            pc: 0,
+            frames: Vec::new(),
+            exit_code: None,
+            io: Box::new(StdIo),
+            natives: HashMap::new(),
+            trace: None,
+            limits: Limits::default(),
+            fuel_used: 0,
+            heap: Heap::new(),
+            backend: Backend::Stack,
+            compiled: None,
+            #[cfg(feature = "jit")]
+            jit: crate::jit::Jit::new(Some(crate::jit::DEFAULT_THRESHOLD)),
+            #[cfg(feature = "jit")]
+            jit_active: false,
+        }
     }
 
+    // 原来用于填充分派表，现在由execute直接按操作码分派，保留为空函数以兼容调用它的代码
+    #[deprecated(note = "instructions are dispatched without a table; `init` does nothing")]
+    pub fn init(&mut self) {}
+
     pub fn import_codes(&mut self, codes: &[u8]) {
-        self.codes = codes.to_vec();
-    }
-
-    pub fn run(&mut self) {
-        let opcodes_len = self.codes.len();
-        unsafe {
-            while PointerCounter < opcodes_len {
-                let opcode = self.codes[PointerCounter];
-                if is_opcode(opcode) {      // 如果是操作码，解析操作码并执行
-                    let opcode = OpCode::from(opcode);
-                    if let Some(action) = self.dispatch_table.get_mut(&opcode) {
-                        action();
-                    } else {
-                        panic!("opcode not found.");
+        self.import_program(&Program::new(codes.to_vec(), Vec::new()));
+    }
+
+    pub fn import_program(&mut self, program: &Program) {
+        self.program = program.clone();
+        self.decoded = (0..program.codes.len()).map(|pc| Instruction::decode(&program.codes, pc)).collect();
+        self.verified = false;
+        self.compiled = None;
+        #[cfg(feature = "jit")]
+        self.jit.clear();
+        self.reset();
+    }
+
+    // 关闭执行前的校验，只依靠运行时检查，用于已知可信的字节码。寄存器后端的翻译依赖校验，总是校验
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    // 选择run使用的后端。寄存器后端的pc()和frames()中的地址是寄存器字节码的地址，
+    // fuel按寄存器指令计算，栈的限制按寄存器个数计算
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    // 加载.svmb字节码文件
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), FormatError> {
+        let program = bytecode::load(path)?;
+        self.import_program(&program);
+        Ok(())
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.program.debug
+    }
+
+    // 注册宿主函数，字节码中用`callnative name, arity`调用，同名时覆盖
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + Send + 'static,
+    ) {
+        self.natives.insert(name.to_string(), Native { arity, function: Box::new(function) });
+    }
+
+    // 替换Print和ReadLine使用的输入输出
+    pub fn set_io(&mut self, io: impl VmIo + Send + 'static) {
+        self.io = Box::new(io);
+    }
+
+    // 打开指令跟踪，每条指令执行前向writer写一行
+    pub fn set_trace(&mut self, writer: impl Write + Send + 'static) {
+        self.trace = Some(Box::new(writer));
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    // 打开垃圾回收的压力模式，每次分配都做一次完整的回收
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    // 清空执行状态，每次run都从第0条字节码开始
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.frames.clear();
+        self.pc = 0;
+        self.exit_code = None;
+        self.fuel_used = 0;
+        self.heap.clear();
+    }
+
+    pub fn run(&mut self) -> Result<(), VmError> {
+        match self.backend {
+            Backend::Stack => self.run_stack(),
+            Backend::Register => self.run_registers(),
+        }
+    }
+
+    // 不带钩子运行栈式后端，打开jit特性时见vm/jit.rs
+    #[cfg(not(feature = "jit"))]
+    fn run_stack(&mut self) -> Result<(), VmError> {
+        self.run_with(|_, _| Control::Continue)
+    }
+
+    // 以栈式后端运行，每条指令执行前先调用hook，此时pc()为该指令的地址。
+    // hook返回Control::Stop时停止执行，run_with返回Ok，pc停在该指令上。
+    pub fn run_with(&mut self, mut hook: impl FnMut(&Vm, &Instruction) -> Control) -> Result<(), VmError> {
+        if self.verify && !self.verified {
+            verifier::verify(&self.program).map_err(VmError::Verify)?;
+            self.verified = true;
+        }
+        self.reset();
+        while self.pc < self.decoded.len() && self.exit_code.is_none() {
+            let pc = self.pc;
+            let instruction = self.decoded[pc].map_err(|e| match e {
+                DecodeError::InvalidOpcode(byte) => VmError::InvalidOpcode { pc, byte },
+                DecodeError::TruncatedOperand(opcode) => VmError::TruncatedOperand { pc, opcode },
+            })?;
+            let opcode = instruction.opcode;
+            if hook(self, &instruction) == Control::Stop {
+                break;
+            }
+            if self.limits.fuel.is_some_and(|fuel| self.fuel_used >= fuel) {
+                return Err(VmError::LimitExceeded { pc, opcode, limit: Limit::Fuel(self.fuel_used) });
+            }
+            self.fuel_used += 1;
+            if let Some(writer) = &mut self.trace {
+                let text = disassembler::instruction_text(&instruction, &self.program.constants);
+                let heap = &self.heap;
+                let stack: Vec<String> = self.stack.iter().map(|v| heap.repr(v)).collect();
+                writeln!(writer, "{:04}  {:<24} [{}]", pc, text, stack.join(", "))
+                    .map_err(|e| VmError::Io { pc, opcode, message: e.to_string() })?;
+            }
+            // 先移动pc再执行，跳转指令可以直接改写pc
+            self.pc += instruction.len;
+            self.execute(instruction).map_err(|trap| trap.at(pc, opcode))?;
+            self.check_limits().map_err(|trap| trap.at(pc, opcode))?;
+        }
+        Ok(())
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // 最近一次run执行的指令条数，出错或超出限制时也有效
+    pub fn fuel_used(&self) -> u64 {
+        self.fuel_used
+    }
+
+    // 堆上字符串和数组当前占用的字节数，包括尚未回收的垃圾
+    pub fn heap_used(&self) -> usize {
+        self.heap.bytes()
+    }
+
+    // 栈上的Value::Obj要通过堆取得内容
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    // 最近一次run的垃圾回收统计
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    // 以栈为根立即做一次回收
+    pub fn collect_garbage(&mut self) {
+        self.heap.collect(&self.stack);
+    }
+
+    // 程序通过Exit结束时的退出码
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // 执行一条指令，pc已经指向下一条指令
+    #[inline]
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Trap> {
+        match instruction.opcode {
+            OpCode::Push => {
+                if let Operand::Int(v) = instruction.operand {
+                    self.push(Value::Int(v));
+                }
+                Ok(())
+            }
+            OpCode::PushConst => {
+                if let Operand::Const(index) = instruction.operand {
+                    let value = match self.program.constants.get(index) {
+                        Some(Value::Str(s)) => self.alloc(Object::Str(s.clone()))?,
+                        Some(value) => value.clone(),
+                        None => return Err(Trap::ConstantOutOfRange(index)),
+                    };
+                    self.push(value);
+                }
+                Ok(())
+            }
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le
+            | OpCode::Gt | OpCode::Ge | OpCode::And | OpCode::Or => {
+                let second = self.pop()?;
+                let first = self.pop()?;
+                let value = self.binary(instruction.opcode, &first, &second)?;
+                self.push(value);
+                Ok(())
+            }
+            OpCode::Print => {
+                let value = self.pop()?;
+                self.print(&value)
+            }
+            OpCode::Jmp => {
+                let addr = self.pop_int()?;
+                self.jump(addr)
+            }
+            OpCode::If => self.fi(),
+            OpCode::ReadLine => {
+                let value = self.read_line()?;
+                self.push(value);
+                Ok(())
+            }
+            OpCode::Call => self.call(),
+            OpCode::CallNative => match instruction.operand {
+                Operand::Const(index) => self.call_native(index),
+                _ => Ok(()),
+            },
+            OpCode::Return => self.ret(),
+            OpCode::LoadLocal => {
+                let slot = self.local_slot()?;
+                let value = self.stack[slot].clone();
+                self.push(value);
+                Ok(())
+            }
+            // 栈顶依次为局部变量的下标、要写入的值
+            OpCode::StoreLocal => {
+                let slot = self.local_slot()?;
+                let value = self.pop()?;
+                if slot >= self.stack.len() {
+                    return Err(Trap::LocalOutOfRange((slot - self.base()) as i64));
+                }
+                self.stack[slot] = value;
+                Ok(())
+            }
+            // 栈顶为退出码，执行后停机
+            OpCode::Exit => {
+                let code = self.pop_int()?;
+                self.exit(code)
+            }
+            // 栈顶为长度，创建元素都为nil的数组
+            OpCode::NewArray => {
+                let len = self.pop_int()?;
+                let value = self.new_array(len)?;
+                self.push(value);
+                Ok(())
+            }
+            OpCode::ArrayGet => {
+                let index = self.pop_int()?;
+                let array = self.pop()?;
+                let array = self.array(&array)?;
+                let value = self.array_get(array, index)?;
+                self.push(value);
+                Ok(())
+            }
+            // 栈顶依次为要写入的值、下标、数组
+            OpCode::ArraySet => {
+                let value = self.pop()?;
+                let index = self.pop_int()?;
+                let array = self.pop()?;
+                let array = self.array(&array)?;
+                self.array_set(array, index, value)
+            }
+            OpCode::ArrayLen => {
+                let value = self.pop()?;
+                let len = self.array_len(&value)?;
+                self.push(Value::Int(len));
+                Ok(())
+            }
+            OpCode::Concat => {
+                let second = self.pop_string()?;
+                let first = self.pop_string()?;
+                self.reserve(first.len() + second.len())?;
+                let value = self.alloc(Object::Str(first + &second))?;
+                self.push(value);
+                Ok(())
+            }
+            // 栈顶依次为长度、起始位置、字符串
+            OpCode::Substring => {
+                let len = self.pop_int()?;
+                let start = self.pop_int()?;
+                let s = self.pop_string()?;
+                let value = self.substring(&s, start, len)?;
+                self.push(value);
+                Ok(())
+            }
+            OpCode::Not => {
+                let value = self.pop()?;
+                self.push(Value::Bool(!value.is_truthy()));
+                Ok(())
+            }
+            OpCode::Neg => {
+                let value = self.pop()?;
+                let value = self.negate(&value)?;
+                self.push(value);
+                Ok(())
+            }
+            OpCode::JmpIfZero | OpCode::JmpIfNonZero => {
+                let condition = self.pop()?.is_truthy();
+                if let Operand::Target(target) = instruction.operand {
+                    if condition == (instruction.opcode == OpCode::JmpIfNonZero) {
+                        // 相对目标以跳转指令本身的地址为起点
+                        self.jump(target.resolve(self.pc - instruction.len))?;
                     }
-                } else {        // 如果不是操作码就是操作数，压栈处理
-                    let value = opcode - IR_OFFSET;     //减掉指令偏移量
-                    push(value);
                 }
-                PointerCounter = PointerCounter + 1;
+                Ok(())
             }
         }
     }
-}
 
-fn pop() -> u8 {
-    unsafe {
-        let top = VmStack.pop().unwrap();
-        top
+    // 二元运算：算术、比较和逻辑运算，两个后端共用
+    fn binary(&self, opcode: OpCode, first: &Value, second: &Value) -> Result<Value, Trap> {
+        match opcode {
+            OpCode::Add => self.arithmetic(first, second, i64::checked_add, |a, b| a + b),
+            OpCode::Sub => self.arithmetic(first, second, i64::checked_sub, |a, b| a - b),
+            OpCode::Mul => self.arithmetic(first, second, i64::checked_mul, |a, b| a * b),
+            OpCode::Div if *second == Value::Int(0) => Err(Trap::DivisionByZero),
+            OpCode::Div => self.arithmetic(first, second, i64::checked_div, |a, b| a / b),
+            OpCode::Eq => Ok(Value::Bool(self.equal(first, second))),
+            OpCode::Ne => Ok(Value::Bool(!self.equal(first, second))),
+            OpCode::Lt => self.compare(first, second, Ordering::is_lt),
+            OpCode::Le => self.compare(first, second, Ordering::is_le),
+            OpCode::Gt => self.compare(first, second, Ordering::is_gt),
+            OpCode::Ge => self.compare(first, second, Ordering::is_ge),
+            OpCode::And => Ok(Value::Bool(first.is_truthy() && second.is_truthy())),
+            OpCode::Or => Ok(Value::Bool(first.is_truthy() || second.is_truthy())),
+            _ => unreachable!("not a binary opcode"),
+        }
     }
-}
 
-fn push(value: u8) {
-    unsafe {
-        VmStack.push(value);
+    // 字符串按内容比较，数组按是否为同一个对象比较，类型不同时不相等
+    fn equal(&self, first: &Value, second: &Value) -> bool {
+        match (self.heap.str(first), self.heap.str(second)) {
+            (Some(a), Some(b)) => a == b,
+            (Some(_), None) | (None, Some(_)) => false,
+            (None, None) => first == second,
+        }
     }
-}
 
-fn print() {
-    let terminal = console::Term::stdout();
+    // 两个操作数必须同为int、同为float或同为字符串，与NaN比较总是为假
+    fn compare(&self, first: &Value, second: &Value, test: fn(Ordering) -> bool) -> Result<Value, Trap> {
+        let ordering = match (first, second) {
+            (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Int(_), _) | (Value::Float(_), _) => {
+                return Err(Trap::TypeMismatch { expected: first.type_name(), found: self.heap.type_name(second) });
+            }
+            _ => match (self.heap.str(first), self.heap.str(second)) {
+                (Some(a), Some(b)) => Some(a.cmp(b)),
+                (Some(_), None) => return Err(Trap::TypeMismatch { expected: "string", found: self.heap.type_name(second) }),
+                _ => return Err(Trap::TypeMismatch { expected: "number", found: self.heap.type_name(first) }),
+            },
+        };
+        Ok(Value::Bool(ordering.is_some_and(test)))
+    }
+
+    // 两个操作数必须同为int或同为float，int溢出时报错
+    fn arithmetic(
+        &self,
+        first: &Value,
+        second: &Value,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<Value, Trap> {
+        match (first, second) {
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(int_op(*a, *b).ok_or(Trap::ArithmeticOverflow)?)),
+            (Value::Float(a), Value::Float(b)) => Ok(Value::Float(float_op(*a, *b))),
+            (Value::Int(_), _) | (Value::Float(_), _) => {
+                Err(Trap::TypeMismatch { expected: first.type_name(), found: self.heap.type_name(second) })
+            }
+            _ => Err(Trap::TypeMismatch { expected: "number", found: self.heap.type_name(first) }),
+        }
+    }
 
-    let top = unsafe {
-        VmStack.pop().unwrap()
-    };
+    // int取反溢出时报错，float直接取反
+    fn negate(&self, value: &Value) -> Result<Value, Trap> {
+        match value {
+            Value::Int(v) => Ok(Value::Int(v.checked_neg().ok_or(Trap::ArithmeticOverflow)?)),
+            Value::Float(x) => Ok(Value::Float(-x)),
+            _ => Err(Trap::TypeMismatch { expected: "number", found: self.heap.type_name(value) }),
+        }
+    }
+
+    // 创建元素都为nil的数组
+    fn new_array(&mut self, len: i64) -> Result<Value, Trap> {
+        if !(0..=MAX_ARRAY_LEN).contains(&len) {
+            return Err(Trap::InvalidLength(len));
+        }
+        // 先检查堆的限制再分配，过大的数组不会真正分配
+        self.reserve(len as usize * mem::size_of::<Value>())?;
+        self.alloc(Object::Array(vec![Value::Nil; len as usize]))
+    }
+
+    fn array_get(&mut self, array: ObjRef, index: i64) -> Result<Value, Trap> {
+        let items = self.array_mut(array);
+        Ok(items[array_index(index, items.len())?].clone())
+    }
+
+    fn array_set(&mut self, array: ObjRef, index: i64, value: Value) -> Result<(), Trap> {
+        let items = self.array_mut(array);
+        let index = array_index(index, items.len())?;
+        items[index] = value;
+        Ok(())
+    }
+
+    // 字符串按字符计算长度
+    fn array_len(&self, value: &Value) -> Result<i64, Trap> {
+        let len = match (value, self.heap.str(value)) {
+            (_, Some(s)) => s.chars().count(),
+            (Value::Obj(r), None) => match self.heap.get(*r) {
+                Object::Array(items) => items.len(),
+                Object::Str(s) => s.chars().count(),
+            },
+            _ => return Err(Trap::TypeMismatch { expected: "array", found: self.heap.type_name(value) }),
+        };
+        Ok(len as i64)
+    }
+
+    // 按字符而不是字节计算位置
+    fn substring(&mut self, s: &str, start: i64, len: i64) -> Result<Value, Trap> {
+        if len < 0 {
+            return Err(Trap::InvalidLength(len));
+        }
+        let count = s.chars().count();
+        if start < 0 || start as usize > count {
+            return Err(Trap::IndexOutOfRange { index: start, len: count });
+        }
+        if len as usize > count - start as usize {
+            return Err(Trap::IndexOutOfRange { index: start.saturating_add(len), len: count });
+        }
+        self.alloc(Object::Str(s.chars().skip(start as usize).take(len as usize).collect()))
+    }
+
+    // 栈顶依次为函数地址、参数个数，参数留在栈上作为新帧的前几个局部变量
+    fn call(&mut self) -> Result<(), Trap> {
+        let addr = self.pop_int()?;
+        let argc = self.pop_int()?;
+        if argc < 0 || argc as usize > self.stack.len() - self.base() {
+            return Err(Trap::StackUnderflow);
+        }
+        self.check_call_depth()?;
+        #[cfg(feature = "jit")]
+        if self.call_jit(addr, argc as usize) {
+            return Ok(());
+        }
+        let ret = self.pc;
+        self.jump(addr)?;
+        let base = self.stack.len() - argc as usize;
+        self.frames.push(Frame { ret, base, entry: addr as usize });
+        Ok(())
+    }
+
+    fn check_call_depth(&self) -> Result<(), Trap> {
+        match self.limits.max_call_depth {
+            Some(max) if self.frames.len() >= max => Err(Trap::LimitExceeded(Limit::CallDepth(max))),
+            _ => Ok(()),
+        }
+    }
+
+    // 栈顶为参数个数，其下为参数，立即数为函数名所在的常量
+    fn call_native(&mut self, index: usize) -> Result<(), Trap> {
+        let name = native_name(&self.program.constants, index)?;
+        let argc = self.pop_int()?;
+        self.check_native(&name, argc)?;
+        if argc as usize > self.stack.len() - self.base() {
+            return Err(Trap::StackUnderflow);
+        }
+        let args: Vec<Value> = self.stack.split_off(self.stack.len() - argc as usize).iter().map(|v| self.heap.export(v)).collect();
+        let value = self.invoke_native(name, &args)?;
+        self.push(value);
+        Ok(())
+    }
+
+    // 函数必须已经注册，参数个数必须一致
+    fn check_native(&self, name: &str, argc: i64) -> Result<(), Trap> {
+        let native = self.natives.get(name).ok_or_else(|| Trap::UnknownNative(name.to_string()))?;
+        if argc < 0 || argc as usize != native.arity {
+            let message = format!("expected {} arguments, found {}", native.arity, argc);
+            return Err(Trap::Native { name: name.to_string(), message });
+        }
+        Ok(())
+    }
+
+    // args已经由Heap::export转换，返回的字符串分配到堆上
+    fn invoke_native(&mut self, name: String, args: &[Value]) -> Result<Value, Trap> {
+        let native = &self.natives[&name];
+        match (native.function)(args).map_err(|message| Trap::Native { name, message })? {
+            Value::Str(s) => self.alloc(Object::Str(s)),
+            value => Ok(value),
+        }
+    }
+
+    // 栈顶为返回值，丢弃当前帧的参数和局部变量后压入返回值
+    fn ret(&mut self) -> Result<(), Trap> {
+        if self.frames.is_empty() {
+            return Err(Trap::CallStackUnderflow);
+        }
+        let value = self.pop()?;
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base);
+        self.stack.push(value);
+        self.pc = frame.ret;
+        Ok(())
+    }
+
+    // 当前帧在栈上的起始位置，不在函数中时为0
+    fn base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.base)
+    }
 
-    let s = format!("{}", top);
-    terminal.write_line(s.as_str());
+    // 弹出局部变量下标，返回其在栈上的位置
+    fn local_slot(&mut self) -> Result<usize, Trap> {
+        let index = self.pop_int()?;
+        if index < 0 || self.base() + index as usize >= self.stack.len() {
+            return Err(Trap::LocalOutOfRange(index));
+        }
+        Ok(self.base() + index as usize)
+    }
+
+    // 不允许弹出当前帧之下的数据
+    fn pop(&mut self) -> Result<Value, Trap> {
+        if self.stack.len() <= self.base() {
+            return Err(Trap::StackUnderflow);
+        }
+        self.stack.pop().ok_or(Trap::StackUnderflow)
+    }
+
+    fn pop_int(&mut self) -> Result<i64, Trap> {
+        let value = self.pop()?;
+        self.int(&value)
+    }
+
+    fn pop_string(&mut self) -> Result<String, Trap> {
+        let value = self.pop()?;
+        self.string(&value)
+    }
+
+    // 地址、下标等操作数必须是整数
+    fn int(&self, value: &Value) -> Result<i64, Trap> {
+        match value {
+            Value::Int(i) => Ok(*i),
+            other => Err(Trap::TypeMismatch { expected: "int", found: self.heap.type_name(other) }),
+        }
+    }
+
+    // 复制字符串的内容，之后的分配可能回收该字符串
+    fn string(&self, value: &Value) -> Result<String, Trap> {
+        match self.heap.str(value) {
+            Some(s) => Ok(s.to_string()),
+            None => Err(Trap::TypeMismatch { expected: "string", found: self.heap.type_name(value) }),
+        }
+    }
+
+    fn array(&self, value: &Value) -> Result<ObjRef, Trap> {
+        match value {
+            Value::Obj(r) if matches!(self.heap.get(*r), Object::Array(_)) => Ok(*r),
+            other => Err(Trap::TypeMismatch { expected: "array", found: self.heap.type_name(other) }),
+        }
+    }
+
+    // r必须是array取得的数组
+    fn array_mut(&mut self, r: ObjRef) -> &mut Vec<Value> {
+        match self.heap.get_mut(r) {
+            Object::Array(items) => items,
+            Object::Str(_) => unreachable!("not an array"),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+
+    // 在堆上分配对象，栈上的值都是根，调用前弹出的句柄可能失效
+    fn alloc(&mut self, object: Object) -> Result<Value, Trap> {
+        self.reserve(object.size())?;
+        Ok(Value::Obj(self.heap.alloc(object, &self.stack)))
+    }
+
+    // 分配bytes字节之前检查堆的限制，超出时先回收一次再判断。
+    // 数组和拼接的字符串在创建之前就检查，一条指令也不能越过限制
+    fn reserve(&mut self, bytes: usize) -> Result<(), Trap> {
+        if let Some(max) = self.limits.max_heap {
+            if self.heap.bytes().saturating_add(bytes) > max {
+                self.heap.collect(&self.stack);
+            }
+            if self.heap.bytes().saturating_add(bytes) > max {
+                return Err(Trap::LimitExceeded(Limit::Heap(max)));
+            }
+        }
+        Ok(())
+    }
+
+    // 每条指令执行后检查栈的限制，堆的限制在分配时检查
+    fn check_limits(&mut self) -> Result<(), Trap> {
+        if let Some(max) = self.limits.max_stack {
+            if self.stack.len() > max {
+                return Err(Trap::LimitExceeded(Limit::Stack(max)));
+            }
+        }
+        Ok(())
+    }
+
+    fn jump(&mut self, addr: i64) -> Result<(), Trap> {
+        if addr < 0 || addr as usize >= self.program.codes.len() {
+            return Err(Trap::JumpOutOfRange(addr));
+        }
+        self.pc = addr as usize;
+        Ok(())
+    }
+
+    fn exit(&mut self, code: i64) -> Result<(), Trap> {
+        self.exit_code = Some(i32::try_from(code).map_err(|_| Trap::ArithmeticOverflow)?);
+        Ok(())
+    }
+
+    fn print(&mut self, value: &Value) -> Result<(), Trap> {
+        let line = self.heap.display(value);
+        self.io.write_line(&line).map_err(|e| Trap::Io(e.to_string()))
+    }
+
+    // 读取一行输入，能解析为数字时返回int或float，否则返回字符串
+    fn read_line(&mut self) -> Result<Value, Trap> {
+        let line = self.io.read_line()
+            .map_err(|e| Trap::Io(e.to_string()))?
+            .ok_or_else(|| Trap::Io("unexpected end of input".to_string()))?;
+        if let Ok(i) = line.trim().parse() {
+            Ok(Value::Int(i))
+        } else if let Ok(f) = line.trim().parse() {
+            Ok(Value::Float(f))
+        } else {
+            self.alloc(Object::Str(line))
+        }
+    }
+
+    fn fi(&mut self) -> Result<(), Trap> {
+        let f = self.pop()?;
+        let t = self.pop()?;
+        let condition = self.pop()?;
+        self.push(if condition.is_truthy() { t } else { f });
+        Ok(())
+    }
 }
 
-fn read_line() {
-    let mut term = console::Term::buffered_stdout();
-    // let input = term.read_line().unwrap();
-    let mut input = Vec::new();
-    term.read(input.as_mut_slice()).unwrap();
-    for i in input {
-        push(i);
+// callnative立即数所指的函数名
+fn native_name(constants: &[Value], index: usize) -> Result<String, Trap> {
+    match constants.get(index) {
+        Some(Value::Str(name)) => Ok(name.clone()),
+        Some(other) => Err(Trap::TypeMismatch { expected: "string", found: other.type_name() }),
+        None => Err(Trap::ConstantOutOfRange(index)),
     }
 }
 
-fn fi() {
-    let f = pop();
-    let t = pop();
-    let condition = pop();
-    if condition != 0 {
-        push(t);
-    } else {
-        push(f);
+// 检查数组下标是否越界
+fn array_index(index: i64, len: usize) -> Result<usize, Trap> {
+    if index < 0 || index as usize >= len {
+        return Err(Trap::IndexOutOfRange { index, len });
     }
+    Ok(index as usize)
 }
 
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
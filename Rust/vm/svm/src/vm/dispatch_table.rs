@@ -0,0 +1,38 @@
+// 只供benches/dispatch.rs比较用，打开dispatch-table特性时才编译：改为match分派之前的执行方式，每条指令执行时才解码，
+// 再按操作码在HashMap中查找处理函数并间接调用。处理函数都转给execute，
+// 所以与run的差别只在取指和分派上。没有钩子、trace和jit。
+
+use super::*;
+
+type Handler = fn(&mut Vm, Instruction) -> Result<(), Trap>;
+
+impl Vm {
+    pub fn run_dispatch_table(&mut self) -> Result<(), VmError> {
+        let mut dispatch_table: HashMap<OpCode, Handler> = HashMap::new();
+        for opcode in OPCODES {
+            dispatch_table.insert(opcode, |vm, instruction| vm.execute(instruction));
+        }
+        if self.verify && !self.verified {
+            verifier::verify(&self.program).map_err(VmError::Verify)?;
+            self.verified = true;
+        }
+        self.reset();
+        while self.pc < self.program.codes.len() && self.exit_code.is_none() {
+            let pc = self.pc;
+            let instruction = Instruction::decode(&self.program.codes, pc).map_err(|e| match e {
+                DecodeError::InvalidOpcode(byte) => VmError::InvalidOpcode { pc, byte },
+                DecodeError::TruncatedOperand(opcode) => VmError::TruncatedOperand { pc, opcode },
+            })?;
+            let opcode = instruction.opcode;
+            if self.limits.fuel.is_some_and(|fuel| self.fuel_used >= fuel) {
+                return Err(VmError::LimitExceeded { pc, opcode, limit: Limit::Fuel(self.fuel_used) });
+            }
+            self.fuel_used += 1;
+            self.pc += instruction.len;
+            let action = dispatch_table[&opcode];
+            action(self, instruction).map_err(|trap| trap.at(pc, opcode))?;
+            self.check_limits().map_err(|trap| trap.at(pc, opcode))?;
+        }
+        Ok(())
+    }
+}
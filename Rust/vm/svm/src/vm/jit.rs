@@ -0,0 +1,57 @@
+// jit特性：栈式后端的run中，被调用次数达到阈值的整数函数由crate::jit编译为机器码执行。
+//
+// 只在run中使用，run_with的钩子、trace和fuel都要看到每一条指令，栈和调用深度的限制之外的资源限制
+// 也要逐条指令检查，这些情况下全部解释执行。机器码中执行的指令不计入fuel_used。
+
+use super::*;
+use crate::jit::MAX_NATIVE_DEPTH;
+
+impl Vm {
+    // 函数被调用threshold次后编译为机器码，None时全部解释执行，默认为jit::DEFAULT_THRESHOLD
+    pub fn set_jit_threshold(&mut self, threshold: Option<u32>) {
+        self.jit.set_threshold(threshold);
+    }
+
+    pub fn jit_threshold(&self) -> Option<u32> {
+        self.jit.threshold()
+    }
+
+    // 已经编译为机器码的函数的入口地址
+    pub fn jit_functions(&self) -> Vec<usize> {
+        self.jit.functions()
+    }
+
+    pub(super) fn run_stack(&mut self) -> Result<(), VmError> {
+        self.jit_active = self.trace.is_none() && self.limits.fuel.is_none() && self.limits.max_stack.is_none();
+        let result = self.run_with(|_, _| Control::Continue);
+        self.jit_active = false;
+        result
+    }
+
+    // 由call在参数个数和调用深度检查通过后调用，参数都是int并且函数有机器码时执行，
+    // 返回值代替参数留在栈上。返回false时由解释器执行这次调用
+    pub(super) fn call_jit(&mut self, addr: i64, argc: usize) -> bool {
+        if !self.jit_active {
+            return false;
+        }
+        let start = self.stack.len() - argc;
+        let args: Option<Vec<i64>> = self.stack[start..].iter().map(|value| match value {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }).collect();
+        let (entry, args) = match (usize::try_from(addr), args) {
+            (Ok(entry), Some(args)) => (entry, args),
+            _ => return false,
+        };
+        // 被调用函数的帧之下还允许的嵌套调用层数
+        let depth = self.limits.max_call_depth.map_or(MAX_NATIVE_DEPTH, |max| max - self.frames.len() - 1);
+        match self.jit.call(&self.program, entry, &args, depth.min(MAX_NATIVE_DEPTH)) {
+            Some(result) => {
+                self.stack.truncate(start);
+                self.stack.push(Value::Int(result));
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// 寄存器后端：解释crate::register翻译得到的寄存器字节码。
+//
+// 操作数栈用作寄存器文件，当前帧的寄存器r就是stack[base + r]，每帧占RegProgram::registers个位置。
+// 运算与栈式后端共用Vm的辅助函数，出错时按origins换成对应栈指令的地址和操作码。
+
+use super::*;
+use crate::register::{self, RegInstruction, RegOp, RegProgram, Src};
+
+pub(super) struct Compiled {
+    program: RegProgram,
+    // 预先解码的指令，下标为指令的地址
+    decoded: Vec<Option<RegInstruction>>,
+}
+
+impl Compiled {
+    fn new(program: RegProgram) -> Self {
+        let mut decoded = vec![None; program.codes.len()];
+        for (addr, instruction) in program.instructions() {
+            decoded[addr] = Some(instruction);
+        }
+        Compiled { program, decoded }
+    }
+}
+
+impl Vm {
+    // 最近一次以寄存器后端运行的程序，尚未运行过时为None
+    pub fn register_program(&self) -> Option<&RegProgram> {
+        self.compiled.as_ref().map(|compiled| &compiled.program)
+    }
+
+    pub(super) fn run_registers(&mut self) -> Result<(), VmError> {
+        let compiled = match self.compiled.take() {
+            Some(compiled) => compiled,
+            None => {
+                let program = register::translate(&self.program).map_err(VmError::Verify)?;
+                self.verified = true;
+                Box::new(Compiled::new(program))
+            }
+        };
+        let result = self.execute_registers(&compiled);
+        self.compiled = Some(compiled);
+        result
+    }
+
+    fn execute_registers(&mut self, compiled: &Compiled) -> Result<(), VmError> {
+        self.reset();
+        self.stack.resize(compiled.program.registers, Value::Nil);
+        let mut base = 0;
+        while self.pc < compiled.decoded.len() && self.exit_code.is_none() {
+            let pc = self.pc;
+            let instruction = compiled.decoded[pc].expect("jump into the middle of a register instruction");
+            if self.limits.fuel.is_some_and(|fuel| self.fuel_used >= fuel) {
+                return Err(self.locate(compiled, pc, Trap::LimitExceeded(Limit::Fuel(self.fuel_used))));
+            }
+            self.fuel_used += 1;
+            if let Some(writer) = &mut self.trace {
+                let text = register::instruction_text(&instruction, &compiled.program.constants);
+                let heap = &self.heap;
+                let registers: Vec<String> = self.stack[base..].iter().map(|v| heap.repr(v)).collect();
+                if let Err(e) = writeln!(writer, "{:04}  {:<24} [{}]", pc, text, registers.join(", ")) {
+                    return Err(self.locate(compiled, pc, Trap::Io(e.to_string())));
+                }
+            }
+            self.pc += instruction.len;
+            if let Err(trap) = self.step(compiled, instruction.op, &mut base).and_then(|_| self.check_limits()) {
+                return Err(self.locate(compiled, pc, trap));
+            }
+        }
+        Ok(())
+    }
+
+    // 寄存器指令pc处的错误，报告为对应栈指令处的错误
+    fn locate(&self, compiled: &Compiled, pc: usize, trap: Trap) -> VmError {
+        let origin = compiled.program.origin(pc);
+        let opcode = self.decoded[origin].map_or(OpCode::Exit, |instruction| instruction.opcode);
+        trap.at(origin, opcode)
+    }
+
+    // 源操作数的值
+    #[inline]
+    fn operand<'a>(&'a self, constants: &'a [Value], base: usize, src: Src) -> &'a Value {
+        match src {
+            Src::Reg(r) => &self.stack[base + r],
+            Src::Const(k) => &constants[k],
+        }
+    }
+
+    #[inline]
+    fn step(&mut self, compiled: &Compiled, op: RegOp, base: &mut usize) -> Result<(), Trap> {
+        let program = &compiled.program;
+        let constants = &program.constants[..];
+        let b = *base;
+        match op {
+            RegOp::Move { dst, src } => {
+                self.stack[b + dst] = self.operand(constants, b, src).clone();
+            }
+            RegOp::Binary { opcode, dst, lhs, rhs } => {
+                self.stack[b + dst] = self.binary(opcode, self.operand(constants, b, lhs), self.operand(constants, b, rhs))?;
+            }
+            RegOp::Not { dst, src } => {
+                self.stack[b + dst] = Value::Bool(!self.operand(constants, b, src).is_truthy());
+            }
+            RegOp::Neg { dst, src } => {
+                self.stack[b + dst] = self.negate(self.operand(constants, b, src))?;
+            }
+            RegOp::Print(src) => {
+                let value = self.operand(constants, b, src).clone();
+                self.print(&value)?;
+            }
+            RegOp::ReadLine(dst) => {
+                self.stack[b + dst] = self.read_line()?;
+            }
+            RegOp::Jump(target) => self.pc = target,
+            RegOp::JumpIf { when, cond, target } => {
+                if self.operand(constants, b, cond).is_truthy() == when {
+                    self.pc = target;
+                }
+            }
+            RegOp::JumpIndirect(src) => {
+                self.pc = self.label(program, self.operand(constants, b, src))?;
+            }
+            RegOp::Select { dst, cond, then, otherwise } => {
+                let src = if self.operand(constants, b, cond).is_truthy() { then } else { otherwise };
+                self.stack[b + dst] = self.operand(constants, b, src).clone();
+            }
+            RegOp::Call { base: first, target, .. } => {
+                *base = self.enter(program, b + first, target)?;
+            }
+            RegOp::CallIndirect { base: first, target, .. } => {
+                let target = self.label(program, self.operand(constants, b, target))?;
+                *base = self.enter(program, b + first, target)?;
+            }
+            RegOp::CallNative { base: first, argc, name } => {
+                let name = native_name(constants, name)?;
+                self.check_native(&name, argc as i64)?;
+                let start = b + first;
+                let args: Vec<Value> = self.stack[start..start + argc].iter().map(|v| self.heap.export(v)).collect();
+                self.stack[start] = self.invoke_native(name, &args)?;
+            }
+            // 返回值写入被调用函数帧的第一个寄存器，也就是调用者的base寄存器
+            RegOp::Return(src) => {
+                if self.frames.is_empty() {
+                    return Err(Trap::CallStackUnderflow);
+                }
+                let value = self.operand(constants, b, src).clone();
+                let frame = self.frames.pop().unwrap();
+                self.stack[frame.base] = value;
+                *base = self.base();
+                self.stack.truncate(*base + program.registers);
+                self.pc = frame.ret;
+            }
+            RegOp::Exit { code, depth } => {
+                let code = self.int(self.operand(constants, b, code))?;
+                self.exit(code)?;
+                self.stack.truncate(b + depth);
+            }
+            RegOp::Halt { depth } => {
+                self.stack.truncate(b + depth);
+                self.pc = compiled.decoded.len();
+            }
+            RegOp::LoadLocal { dst, index, limit } => {
+                let index = self.local_index(self.operand(constants, b, index), limit)?;
+                self.stack[b + dst] = self.stack[b + index].clone();
+            }
+            RegOp::StoreLocal { index, value, limit } => {
+                let index = self.local_index(self.operand(constants, b, index), limit)?;
+                self.stack[b + index] = self.operand(constants, b, value).clone();
+            }
+            RegOp::NewArray { dst, len } => {
+                let len = self.int(self.operand(constants, b, len))?;
+                self.stack[b + dst] = self.new_array(len)?;
+            }
+            RegOp::ArrayGet { dst, array, index } => {
+                let index = self.int(self.operand(constants, b, index))?;
+                let array = self.array(self.operand(constants, b, array))?;
+                self.stack[b + dst] = self.array_get(array, index)?;
+            }
+            RegOp::ArraySet { array, index, value } => {
+                let index = self.int(self.operand(constants, b, index))?;
+                let array = self.array(self.operand(constants, b, array))?;
+                let value = self.operand(constants, b, value).clone();
+                self.array_set(array, index, value)?;
+            }
+            RegOp::ArrayLen { dst, src } => {
+                self.stack[b + dst] = Value::Int(self.array_len(self.operand(constants, b, src))?);
+            }
+            RegOp::Concat { dst, lhs, rhs } => {
+                let second = self.string(self.operand(constants, b, rhs))?;
+                let first = self.string(self.operand(constants, b, lhs))?;
+                self.reserve(first.len() + second.len())?;
+                self.stack[b + dst] = self.alloc(Object::Str(first + &second))?;
+            }
+            RegOp::Substring { dst, string, start, len } => {
+                let len = self.int(self.operand(constants, b, len))?;
+                let start = self.int(self.operand(constants, b, start))?;
+                let s = self.string(self.operand(constants, b, string))?;
+                self.stack[b + dst] = self.substring(&s, start, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 调用函数，新帧从寄存器文件的base处开始，返回新帧的base
+    fn enter(&mut self, program: &RegProgram, base: usize, target: usize) -> Result<usize, Trap> {
+        self.check_call_depth()?;
+        self.frames.push(Frame { ret: self.pc, base, entry: target });
+        self.stack.resize(base + program.registers, Value::Nil);
+        self.pc = target;
+        Ok(base)
+    }
+
+    // 栈字节码中的地址对应的寄存器字节码地址
+    fn label(&self, program: &RegProgram, value: &Value) -> Result<usize, Trap> {
+        let addr = self.int(value)?;
+        usize::try_from(addr).ok().and_then(|addr| program.labels.get(&addr)).copied().ok_or(Trap::JumpOutOfRange(addr))
+    }
+
+    fn local_index(&self, value: &Value, limit: usize) -> Result<usize, Trap> {
+        let index = self.int(value)?;
+        if index < 0 || index as usize >= limit {
+            return Err(Trap::LocalOutOfRange(index));
+        }
+        Ok(index as usize)
+    }
+}
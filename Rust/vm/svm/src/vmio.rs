@@ -0,0 +1,117 @@
+// 虚拟机与宿主之间的输入输出，Print和ReadLine都通过VmIo完成。
+//
+// 宿主用Vm::set_io提供实现，默认为StdIo。BufferIo把输入输出放在内存中，便于测试；
+// FileIo从文件读取输入并把输出写到文件。
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub trait VmIo {
+    // Print输出一行，line不含换行符
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+    // ReadLine读取一行，返回的内容不含换行符，输入结束时返回None
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+}
+
+// 标准输出和标准输入
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdIo;
+
+impl VmIo for StdIo {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        read_line_from(&mut io::stdin().lock())
+    }
+}
+
+// 内存中的输入输出，克隆得到的BufferIo共享同一份数据，
+// 交给Vm之后仍可以通过保留的一份取得输出
+#[derive(Debug, Clone, Default)]
+pub struct BufferIo {
+    inner: Arc<Mutex<Buffer>>,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    input: VecDeque<String>,
+    output: String,
+}
+
+impl BufferIo {
+    // input按行拆分作为ReadLine依次读到的内容
+    pub fn new(input: &str) -> Self {
+        let buffer = Buffer { input: input.lines().map(str::to_string).collect(), output: String::new() };
+        BufferIo { inner: Arc::new(Mutex::new(buffer)) }
+    }
+
+    // 到目前为止的全部输出
+    pub fn output(&self) -> String {
+        self.inner.lock().unwrap().output.clone()
+    }
+
+    // 取出并清空已有的输出
+    pub fn take_output(&self) -> String {
+        std::mem::take(&mut self.inner.lock().unwrap().output)
+    }
+}
+
+impl VmIo for BufferIo {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let mut buffer = self.inner.lock().unwrap();
+        buffer.output.push_str(line);
+        buffer.output.push('\n');
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.inner.lock().unwrap().input.pop_front())
+    }
+}
+
+// 从文件读取输入，输出写到另一个文件
+#[derive(Debug)]
+pub struct FileIo {
+    input: Option<BufReader<File>>,
+    output: File,
+}
+
+impl FileIo {
+    // 创建或清空output；input为None时ReadLine总是读到输入结束
+    pub fn open(input: Option<&Path>, output: &Path) -> io::Result<Self> {
+        let input = match input {
+            Some(path) => Some(BufReader::new(File::open(path)?)),
+            None => None,
+        };
+        Ok(FileIo { input, output: File::create(output)? })
+    }
+}
+
+impl VmIo for FileIo {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.output, "{}", line)
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        match &mut self.input {
+            Some(reader) => read_line_from(reader),
+            None => Ok(None),
+        }
+    }
+}
+
+fn read_line_from(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
@@ -0,0 +1,65 @@
+use svm::assembler::{assemble, AsmError, AsmErrorKind};
+use svm::value::Value;
+use svm::vm::Vm;
+
+fn error(source: &str) -> AsmError {
+    assemble(source).unwrap_err()
+}
+
+#[test]
+fn labels_resolve_to_instruction_addresses() {
+    let program = assemble("
+        push 1
+    start:
+        push 2
+        add
+    end:
+        exit
+    ").unwrap();
+    assert_eq!(program.debug.label_addr("start"), Some(2));
+    assert_eq!(program.debug.label_addr("end"), Some(5));
+    // 标号与对应地址的整数写法生成相同的字节码
+    let labelled = assemble("push start\nstart:\njmp end\nend:\npush 0").unwrap();
+    let numeric = assemble("push 2\npush 5\njmp\npush 0").unwrap();
+    assert_eq!(labelled.codes, numeric.codes);
+}
+
+#[test]
+fn forward_references() {
+    let program = assemble("
+        jmp skip
+        push 1
+        exit
+    skip:
+        push 2
+        jmpifnonzero done
+        push 3
+        exit
+    done:
+        push 4
+    ").unwrap();
+    let mut vm = Vm::new();
+    vm.import_program(&program);
+    vm.run().unwrap();
+    assert_eq!(vm.stack(), &[Value::Int(4)]);
+    assert_eq!(vm.exit_code(), None);
+}
+
+#[test]
+fn errors_report_line_and_column() {
+    let e = error("push 1\n   frob 2");
+    assert_eq!((e.line, e.column), (2, 4));
+    assert_eq!(e.kind, AsmErrorKind::UnknownMnemonic("frob".to_string()));
+    assert_eq!(e.to_string(), "2:4: unknown mnemonic `frob`");
+
+    let e = error("push 1\n\n  jmp nowhere");
+    assert_eq!((e.line, e.column, e.kind), (3, 7, AsmErrorKind::UndefinedLabel("nowhere".to_string())));
+    let e = error("a:\npush 1\n  a: push 2");
+    assert_eq!((e.line, e.column, e.kind), (3, 3, AsmErrorKind::DuplicateLabel("a".to_string())));
+    let e = error("push \"abc");
+    assert_eq!((e.line, e.column, e.kind), (1, 6, AsmErrorKind::UnterminatedString));
+    let e = error("  push");
+    assert_eq!((e.line, e.column, e.kind), (1, 3, AsmErrorKind::MissingOperand));
+    let e = error("add 1 ; comment");
+    assert_eq!((e.line, e.column, e.kind), (1, 5, AsmErrorKind::UnexpectedOperand));
+}
@@ -0,0 +1,113 @@
+use svm::assembler::assemble;
+use svm::bytecode::{self, crc32, deserialize, serialize, FormatError, SectionKind, MAGIC};
+use svm::program::Program;
+use svm::value::Value;
+
+fn sample() -> Program {
+    let mut program = assemble("
+    start:
+        push 300
+        push \"héllo\"
+        push 2.5
+        push true
+        push nil
+        push -7000000000
+        jmp start
+    ").unwrap();
+    program.debug.source = Some("sample.s".to_string());
+    program
+}
+
+// 重新计算末尾的校验和，用来构造校验和正确但内容有误的文件
+fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.truncate(bytes.len() - 4);
+    let checksum = crc32(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+// 由若干段组成的文件
+fn file(sections: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&bytecode::VERSION.to_le_bytes());
+    out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+    for (kind, data) in sections {
+        out.push(*kind);
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    out.extend_from_slice(&[0; 4]);
+    reseal(out)
+}
+
+#[test]
+fn serialize_and_load_round_trip() {
+    let program = sample();
+    let loaded = deserialize(&serialize(&program)).unwrap();
+    assert_eq!(loaded.codes, program.codes);
+    assert_eq!(loaded.constants, program.constants);
+    assert!(loaded.constants.contains(&Value::Str("héllo".to_string())));
+    assert_eq!(loaded.debug.source.as_deref(), Some("sample.s"));
+    assert_eq!(loaded.debug.lines, program.debug.lines);
+    assert_eq!(loaded.debug.labels, program.debug.labels);
+
+    let path = std::env::temp_dir().join(format!("svm-bytecode-{}.svmb", std::process::id()));
+    bytecode::save(&program, &path).unwrap();
+    let loaded = bytecode::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.codes, program.codes);
+}
+
+#[test]
+fn unknown_sections_are_skipped() {
+    let bytes = file(&[(9, &[1, 2, 3]), (SectionKind::Code as u8, &[0])]);
+    assert_eq!(deserialize(&bytes).unwrap().codes, vec![0]);
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut bytes = serialize(&sample());
+    bytes[10] ^= 1;
+    assert!(matches!(deserialize(&bytes), Err(FormatError::ChecksumMismatch { .. })));
+}
+
+#[test]
+fn bad_magic_and_version() {
+    assert_eq!(deserialize(b"push 1\n").unwrap_err(), FormatError::BadMagic);
+    assert_eq!(deserialize(b"").unwrap_err(), FormatError::BadMagic);
+    let mut bytes = serialize(&sample());
+    bytes[4] = 2;
+    assert_eq!(deserialize(&reseal(bytes)).unwrap_err(), FormatError::UnsupportedVersion(2));
+}
+
+#[test]
+fn truncated_header_and_sections() {
+    assert_eq!(deserialize(b"SVMB\x01\x00").unwrap_err(), FormatError::Truncated);
+    let bytes = serialize(&sample());
+    // 截断后重新计算校验和，在各个位置都报告文件不完整或格式错误
+    for len in 9..bytes.len() - 4 {
+        let mut cut = bytes[..len].to_vec();
+        cut.extend_from_slice(&[0; 4]);
+        assert!(matches!(deserialize(&reseal(cut)), Err(FormatError::Truncated) | Err(FormatError::Malformed(_))), "{}", len);
+    }
+    // 段的长度超出文件
+    let mut bytes = file(&[(SectionKind::Code as u8, &[0, 0])]);
+    bytes[9] = 200;
+    assert_eq!(deserialize(&reseal(bytes)).unwrap_err(), FormatError::Truncated);
+    // 常量池声明了两个常量，只有一个
+    let bytes = file(&[(SectionKind::Code as u8, &[0]), (SectionKind::Constants as u8, &[2, 0])]);
+    assert_eq!(deserialize(&bytes).unwrap_err(), FormatError::Truncated);
+}
+
+#[test]
+fn duplicate_sections_and_trailing_section_data() {
+    let constants: &[u8] = &[1, 0];
+    let bytes = file(&[(SectionKind::Code as u8, &[0]), (SectionKind::Constants as u8, constants), (SectionKind::Constants as u8, constants)]);
+    assert_eq!(deserialize(&bytes).unwrap_err(), FormatError::Malformed("duplicate section"));
+    let bytes = file(&[(SectionKind::Code as u8, &[0]), (SectionKind::Code as u8, &[0])]);
+    assert_eq!(deserialize(&bytes).unwrap_err(), FormatError::Malformed("duplicate section"));
+    let bytes = file(&[(SectionKind::Code as u8, &[0]), (SectionKind::Constants as u8, &[1, 0, 0])]);
+    assert_eq!(deserialize(&bytes).unwrap_err(), FormatError::Malformed("section with trailing data"));
+    let bytes = file(&[(SectionKind::Constants as u8, constants)]);
+    assert_eq!(deserialize(&bytes).unwrap_err(), FormatError::Malformed("file without code section"));
+}
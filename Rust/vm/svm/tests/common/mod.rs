@@ -0,0 +1,28 @@
+// 各测试文件共用的辅助函数，每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use svm::assembler::assemble;
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+// 汇编source并载入新的Vm
+pub fn vm_with(source: &str) -> Vm {
+    let mut vm = Vm::new();
+    vm.import_program(&assemble(source).unwrap());
+    vm
+}
+
+// 同vm_with，输入为空，输出写到返回的BufferIo
+pub fn vm_with_io(source: &str) -> (Vm, BufferIo) {
+    let mut vm = vm_with(source);
+    let io = BufferIo::new("");
+    vm.set_io(io.clone());
+    (vm, io)
+}
+
+// 运行到结束，出错时测试失败
+pub fn run(source: &str) -> Vm {
+    let mut vm = vm_with(source);
+    vm.run().unwrap();
+    vm
+}
@@ -0,0 +1,189 @@
+use svm::compiler::compile;
+use svm::compiler::parser::MAX_DEPTH;
+use svm::natives::register_builtins;
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+// 编译并运行脚本，返回输出
+fn run(source: &str) -> String {
+    let mut vm = Vm::new();
+    vm.import_program(&compile(source).unwrap());
+    register_builtins(&mut vm);
+    let io = BufferIo::new("");
+    vm.set_io(io.clone());
+    vm.run().unwrap();
+    io.output()
+}
+
+fn error(source: &str) -> String {
+    compile(source).unwrap_err().to_string()
+}
+
+#[test]
+fn expressions_still_compile() {
+    assert_eq!(run("print(1+2)；print((7 - 1) * 2 / 3)"), "3\n4\n");
+}
+
+#[test]
+fn operator_precedence_and_parentheses() {
+    assert_eq!(run("print(1 + 2 * 3); print((1 + 2) * 3); print(2 * 3 + 4 * 5);"), "7\n9\n26\n");
+    // 同一优先级从左到右结合
+    assert_eq!(run("print(10 - 4 - 3); print(24 / 4 / 2); print(10 - (4 - 3));"), "3\n3\n9\n");
+    assert_eq!(run("print(-2 * 3); print(-(2 + 3) * -1); print(((((7)))));"), "-6\n5\n7\n");
+    assert_eq!(run("print(1 + 2 < 4 && 2 * 2 == 4 || 0);"), "true\n");
+}
+
+#[test]
+fn negating_floats_and_ints() {
+    assert_eq!(run("let x = 2.5; print(-x); print(-(x * 2.0));"), "-2.5\n-5.0\n");
+    assert_eq!(run("fn half(n) { return n / 2.0; } print(-half(3.0)); let i = 7; print(-i - -i);"), "-1.5\n0\n");
+}
+
+#[test]
+fn compile_errors_carry_spans() {
+    let e = compile("print(1 + )").unwrap_err();
+    assert_eq!((e.span.line, e.span.column, e.span.start, e.span.end), (1, 11, 10, 11));
+    assert_eq!(e.to_string(), "1:11: expected expression, found `)`");
+    assert_eq!(error("print(1 2)"), "1:9: expected `)`, found `2`");
+    assert_eq!(error("print((1 + 2)"), "1:14: expected `)`, found end of input");
+    assert_eq!(error("print(1);\n  print(3 $ 4)"), "2:11: unexpected character `$`");
+    let e = compile("print(99999999999999999999)").unwrap_err();
+    assert_eq!((e.span.start, e.span.end), (6, 26));
+    assert_eq!(e.message, "number `99999999999999999999` is too large");
+}
+
+#[test]
+fn smallest_integer_is_written_with_a_minus_sign() {
+    assert_eq!(run("print(-9223372036854775808); print(- 9223372036854775808 + 1);"), "-9223372036854775808\n-9223372036854775807\n");
+    assert_eq!(error("print(9223372036854775808)"), "1:7: number `9223372036854775808` is too large");
+    assert_eq!(error("print(1 - 9223372036854775808)"), "1:11: number `9223372036854775808` is too large");
+    assert_eq!(error("print(-(9223372036854775808))"), "1:9: number `9223372036854775808` is too large");
+    assert_eq!(error("print(18446744073709551616)"), "1:7: number `18446744073709551616` is too large");
+}
+
+#[test]
+fn nesting_is_limited() {
+    let nested = |open: &str, inner: &str, close: &str, n: usize| format!("{}{}{}", open.repeat(n), inner, close.repeat(n));
+    let depth = MAX_DEPTH - 2;
+    assert_eq!(run(&format!("print({});", nested("(", "1", ")", depth))), "1\n");
+    assert_eq!(run(&format!("print({});", nested("-", "1", "", depth))), "1\n");
+    assert_eq!(run(&nested("if 1 { ", "print(2);", " }", depth)), "2\n");
+
+    let e = compile(&format!("print({});", nested("(", "1", ")", 1000))).unwrap_err();
+    assert_eq!(e.message, "too deeply nested");
+    assert_eq!((e.span.line, e.span.start), (1, 6 + MAX_DEPTH));
+    assert_eq!(error(&nested("while 1 {\n", "", "}", 1000)), format!("{}:7: too deeply nested", MAX_DEPTH + 1));
+    assert_eq!(error(&format!("print({});", nested("!", "1", "", 1000))), format!("1:{}: too deeply nested", 7 + MAX_DEPTH));
+    assert_eq!(error(&nested("if 0 {} else ", "{}", "", 1000)), format!("1:{}: too deeply nested", 4 + 13 * MAX_DEPTH));
+}
+
+#[test]
+fn let_assignment_and_while() {
+    let source = "
+        let i = 5;
+        let total = 0;
+        while i {
+            total = total + i;
+            i = i - 1;
+        }
+        print(total);
+    ";
+    assert_eq!(run(source), "15\n");
+}
+
+#[test]
+fn if_else_chains() {
+    let source = "
+        fn sign(x) {
+            if x - 0 * x {
+                if x - 1 { return \"many\"; } else { return \"one\"; }
+            } else if nil {
+                return \"unreachable\";
+            } else {
+                return \"zero\";
+            }
+        }
+        print(sign(0));
+        print(sign(1));
+        print(sign(7));
+    ";
+    assert_eq!(run(source), "zero\none\nmany\n");
+}
+
+#[test]
+fn recursive_functions_and_implicit_return() {
+    let source = "
+        print(fib(15));
+        print(nothing());
+        fn fib(n) {
+            if n - 1 {
+                if n { return fib(n - 1) + fib(n - 2); }
+                return 0;
+            }
+            return 1;
+        }
+        fn nothing() {}
+    ";
+    assert_eq!(run(source), "610\nnil\n");
+}
+
+#[test]
+fn program_may_end_with_a_loop() {
+    assert_eq!(run("let i = 2;\nwhile i { print(i); i = i - 1; }"), "2\n1\n");
+    assert_eq!(run("if 0 { print(1); }"), "");
+}
+
+#[test]
+fn block_scopes_and_shadowing() {
+    let source = "
+        let x = 1;
+        if x {
+            let x = x + 10;
+            print(x);
+        }
+        print(x);
+        let x = \"shadowed\";
+        print(x);
+    ";
+    assert_eq!(run(source), "11\n1\nshadowed\n");
+}
+
+#[test]
+fn arrays_strings_and_natives() {
+    let source = "
+        let a = array(3);
+        a[0] = \"x\";
+        a[1] = concat(a[0], \"y\");
+        a[2] = -len(a[1]);
+        print(a);
+        print(substring(\"hello\", 1, 3));
+        print(sqrt(2.25));
+        abs(-1);
+    ";
+    assert_eq!(run(source), "[\"x\", \"xy\", -2]\nell\n1.5\n");
+}
+
+#[test]
+fn scope_errors() {
+    assert_eq!(error("print(y)"), "1:7: undefined variable `y`");
+    assert_eq!(error("let x = 1;\nfn f() { return x; }"), "2:17: undefined variable `x`");
+    assert_eq!(error("{"), "1:1: expected expression, found `{`");
+    assert_eq!(error("return 1;"), "1:1: return outside of a function");
+    assert_eq!(error("fn f(a, a) {}"), "1:9: duplicate parameter `a`");
+    assert_eq!(error("fn f() {}\nfn f() {}"), "2:4: function `f` is already defined");
+    assert_eq!(error("fn len(a) {}"), "1:4: `len` is a builtin function");
+    assert_eq!(error("fn f(a) {}\nf(1, 2);"), "2:1: function `f` takes 1 arguments, found 2");
+    assert_eq!(error("fn f() {}\nprint(f);"), "2:7: `f` is a function, not a variable");
+    assert_eq!(error("1 = 2;"), "1:1: invalid assignment target");
+    assert_eq!(error("while 1 { fn g() {} }"), "1:11: functions can only be defined at the top level");
+    assert_eq!(error("print(\"abc)"), "1:7: unterminated string");
+}
+
+#[test]
+fn debug_info_maps_lines_and_functions() {
+    let program = compile("let a = 1;\n\nfn f() {\n    return 2;\n}\nprint(f());\n").unwrap();
+    let f = program.debug.label_addr("f").unwrap();
+    assert_eq!(program.debug.line_of(f), Some(4));
+    assert_eq!(program.debug.line_of(0), Some(1));
+    assert!(program.debug.addr_of_line(6).is_some());
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{run, vm_with};
+use svm::assembler::assemble;
+use svm::compiler::compile;
+use svm::disassembler::disassemble;
+use svm::error::VmError;
+use svm::instruction::{Instruction, OpCode, Operand, Target};
+use svm::value::Value;
+use svm::verifier::{verify, VerifyErrorKind};
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+fn top(source: &str) -> Value {
+    run(source).stack().last().cloned().unwrap()
+}
+
+#[test]
+fn comparisons() {
+    assert_eq!(top("push 1\npush 2\nlt"), Value::Bool(true));
+    assert_eq!(top("push 2\npush 2\nle"), Value::Bool(true));
+    assert_eq!(top("push 2\npush 1\ngt"), Value::Bool(true));
+    assert_eq!(top("push 1\npush 2\nge"), Value::Bool(false));
+    assert_eq!(top("push 1.5\npush 0.5\ngt"), Value::Bool(true));
+    assert_eq!(top("push \"abc\"\npush \"abd\"\nlt"), Value::Bool(true));
+    // NaN与任何值比较都为假
+    assert_eq!(top("push 0.0\npush 0.0\ndiv\npush 1.0\nlt"), Value::Bool(false));
+}
+
+#[test]
+fn equality_compares_strings_by_content() {
+    assert_eq!(top("push \"ab\"\npush \"a\"\npush \"b\"\nconcat\neq"), Value::Bool(true));
+    assert_eq!(top("push 1\npush 1\nne"), Value::Bool(false));
+    assert_eq!(top("push nil\npush nil\neq"), Value::Bool(true));
+    // 不同类型的值不相等，整数和浮点数也不例外
+    assert_eq!(top("push 1\npush 1.0\neq"), Value::Bool(false));
+    assert_eq!(top("push \"1\"\npush 1\nne"), Value::Bool(true));
+}
+
+#[test]
+fn ordering_requires_same_type() {
+    let mut vm = vm_with("push 1\npush \"a\"\nlt");
+    assert_eq!(vm.run(), Err(VmError::TypeMismatch { pc: 4, opcode: OpCode::Lt, expected: "int", found: "string" }));
+}
+
+#[test]
+fn logic_uses_truthiness() {
+    assert_eq!(top("push 1\npush \"\"\nand"), Value::Bool(true));
+    assert_eq!(top("push 1\npush 0\nand"), Value::Bool(false));
+    assert_eq!(top("push nil\npush 2\nor"), Value::Bool(true));
+    assert_eq!(top("push 0\nnot"), Value::Bool(true));
+    assert_eq!(top("push false\nnot\nnot"), Value::Bool(false));
+}
+
+#[test]
+fn loop_with_absolute_and_relative_targets() {
+    // 计算5+4+3+2+1，loop用标号，跳过的push用相对偏移
+    let source = "
+        push 0
+        push 5
+    loop:
+        loadlocal 1
+        jmpifzero done
+        loadlocal 0
+        loadlocal 1
+        add
+        storelocal 0
+        loadlocal 1
+        push 1
+        sub
+        storelocal 1
+        push 1
+        jmpifnonzero loop
+    done:
+        push 1
+        jmpifnonzero +6
+        push 99
+        print
+        loadlocal 0
+        exit
+    ";
+    let vm = run(source);
+    assert_eq!(vm.exit_code(), Some(15));
+}
+
+#[test]
+fn relative_targets_encode_as_given() {
+    let program = assemble("push 0\njmpifzero +2\nexit").unwrap();
+    let jump = Instruction::decode(&program.codes, 2).unwrap();
+    assert_eq!(jump.opcode, OpCode::JmpIfZero);
+    assert_eq!(jump.operand, Operand::Target(Target::Relative(2)));
+    assert_eq!(Target::Relative(-3).resolve(10), 7);
+}
+
+#[test]
+fn disassembly_reassembles() {
+    let program = assemble("top:\npush 0\njmpifzero +2\nexit\npush 1\njmpifnonzero top").unwrap();
+    let text = disassemble(&program);
+    assert!(text.contains("jmpifzero +2    ; L"), "{}", text);
+    assert!(text.contains("jmpifnonzero L0"), "{}", text);
+    let source: String = text.lines()
+        .map(|line| if line.ends_with(':') { format!("{}\n", line) } else { format!("{}\n", &line[6..]) })
+        .collect();
+    assert_eq!(assemble(&source).unwrap().codes, program.codes);
+}
+
+#[test]
+fn verifier_checks_immediate_targets() {
+    let kind = |source: &str| verify(&assemble(source).unwrap()).unwrap_err().kind;
+    assert_eq!(kind("push 0\njmpifzero +100"), VerifyErrorKind::TargetOutOfRange(102));
+    assert_eq!(kind("push 0\njmpifzero -3"), VerifyErrorKind::TargetOutOfRange(-1));
+    assert_eq!(kind("push 1000\njmpifzero 1"), VerifyErrorKind::TargetNotOnBoundary(1));
+    // 两条路径到达同一位置时栈深度必须一致
+    assert_eq!(kind("push 0\njmpifzero end\npush 1\nend:\npush 0\nexit"), VerifyErrorKind::DepthMismatch { expected: 0, found: 1 });
+}
+
+#[test]
+fn script_operators() {
+    let source = "
+        fn fib(n) {
+            if n <= 1 { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        let i = 0;
+        while i < 10 && !(i == 7) {
+            i = i + 1;
+        }
+        print(i);
+        print(fib(10) >= 55 || fib(100));
+        print(1 != 1 || 2 > 3);
+        print(\"a\" == concat(\"\", \"a\"));
+    ";
+    let mut vm = Vm::new();
+    vm.import_program(&compile(source).unwrap());
+    let io = BufferIo::new("");
+    vm.set_io(io.clone());
+    vm.run().unwrap();
+    assert_eq!(io.output(), "7\ntrue\nfalse\ntrue\n");
+}
+
+#[test]
+fn unknown_single_operator_characters() {
+    let error = compile("print(1 & 2)").unwrap_err().to_string();
+    assert!(error.contains('&'), "{}", error);
+}
@@ -0,0 +1,190 @@
+// 向`svm dap`写入一组DAP请求，检查返回的响应和事件
+use std::io::Write;
+use std::process::{Command, Stdio};
+use serde_json::{json, Value};
+
+const PROGRAM: &str = "\
+push 3
+call double, 1
+print
+push 0
+exit
+double:
+loadlocal 0
+push 2
+mul
+return
+";
+
+fn frame(message: &Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(seq: i64, command: &str, arguments: Value) -> String {
+    frame(&json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }))
+}
+
+fn spawn(input: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_svm"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// 运行一次调试会话，返回服务端发出的全部消息
+fn session(requests: &[String]) -> Vec<Value> {
+    let output = spawn(&requests.concat());
+    assert!(output.status.success());
+
+    let mut messages = Vec::new();
+    let mut rest = String::from_utf8(output.stdout).unwrap();
+    while let Some(start) = rest.find("\r\n\r\n") {
+        let length: usize = rest["Content-Length: ".len()..start].parse().unwrap();
+        let body = &rest[start + 4..start + 4 + length];
+        messages.push(serde_json::from_str(body).unwrap());
+        rest = rest[start + 4 + length..].to_string();
+    }
+    messages
+}
+
+fn program_file(name: &str) -> String {
+    source_file(name, PROGRAM)
+}
+
+fn source_file(name: &str, source: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, source).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn response(messages: &[Value], request_seq: i64) -> &Value {
+    messages.iter().find(|m| m["type"] == "response" && m["request_seq"] == request_seq).unwrap()
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|m| m["event"] == event).collect()
+}
+
+#[test]
+fn breakpoint_stack_and_variables() {
+    let path = program_file("dap_breakpoint.s");
+    let messages = session(&[
+        request(1, "initialize", json!({ "adapterID": "svm" })),
+        request(2, "launch", json!({ "program": path })),
+        request(3, "setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 9 }, { "line": 100 }] })),
+        request(4, "configurationDone", json!({})),
+        request(5, "stackTrace", json!({ "threadId": 1 })),
+        request(6, "scopes", json!({ "frameId": 0 })),
+        request(7, "variables", json!({ "variablesReference": 1 })),
+        request(8, "continue", json!({ "threadId": 1 })),
+        request(9, "disconnect", json!({})),
+    ]);
+
+    assert_eq!(response(&messages, 1)["body"]["supportsConfigurationDoneRequest"], true);
+    let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+    assert_eq!(breakpoints[0], json!({ "verified": true, "line": 9 }));
+    assert_eq!(breakpoints[1], json!({ "verified": false, "line": 100 }));
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, 5)["body"]["stackFrames"];
+    assert_eq!(frames.as_array().unwrap().len(), 2);
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 9);
+    assert_eq!(frames[0]["source"]["path"], path.as_str());
+    assert_eq!(frames[1]["name"], "main");
+    assert_eq!(frames[1]["line"], 2);
+
+    assert_eq!(response(&messages, 6)["body"]["scopes"][0]["variablesReference"], 1);
+    let variables = &response(&messages, 7)["body"]["variables"];
+    // 参数之后是当前帧压入的临时值
+    let values: Vec<&Value> = variables.as_array().unwrap().iter().map(|v| &v["value"]).collect();
+    assert_eq!(values, ["3", "3", "2"]);
+
+    assert_eq!(events(&messages, "output")[0]["body"]["output"], "6\n");
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+    assert_eq!(response(&messages, 9)["success"], true);
+}
+
+#[test]
+fn step_in_and_next_follow_source_lines() {
+    let path = program_file("dap_step.s");
+    let messages = session(&[
+        request(1, "initialize", json!({})),
+        request(2, "launch", json!({ "program": path, "stopOnEntry": true })),
+        request(3, "configurationDone", json!({})),
+        request(4, "next", json!({ "threadId": 1 })),
+        request(5, "stackTrace", json!({ "threadId": 1 })),
+        request(6, "stepIn", json!({ "threadId": 1 })),
+        request(7, "stackTrace", json!({ "threadId": 1 })),
+        request(8, "disconnect", json!({})),
+    ]);
+
+    let reasons: Vec<&Value> = events(&messages, "stopped").iter().map(|e| &e["body"]["reason"]).collect();
+    assert_eq!(reasons, ["entry", "step", "step"]);
+    assert_eq!(response(&messages, 5)["body"]["stackFrames"][0]["line"], 2);
+    let frames = &response(&messages, 7)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 7);
+    // 断开连接后程序不再继续执行
+    assert!(events(&messages, "exited").is_empty());
+}
+
+#[test]
+fn launch_error_is_reported() {
+    let messages = session(&[
+        request(1, "initialize", json!({})),
+        request(2, "launch", json!({ "program": "/nonexistent/prog.s" })),
+        request(3, "disconnect", json!({})),
+    ]);
+    assert_eq!(response(&messages, 2)["success"], false);
+    assert_eq!(response(&messages, 3)["success"], true);
+}
+
+#[test]
+fn breakpoint_stops_at_every_part_of_a_line() {
+    // while所在的第2行分成两段：循环条件和跳回条件的jmp
+    let path = source_file("dap_loop.svs", "let i = 0;\nwhile i < 2 {\n    i = i + 1;\n}\nprint(i);\n");
+    let mut requests = vec![
+        request(1, "initialize", json!({})),
+        request(2, "launch", json!({ "program": path })),
+        request(3, "setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] })),
+        request(4, "configurationDone", json!({})),
+    ];
+    for seq in 0..5 {
+        requests.push(request(10 + seq * 2, "stackTrace", json!({ "threadId": 1 })));
+        requests.push(request(11 + seq * 2, "continue", json!({ "threadId": 1 })));
+    }
+    requests.push(request(30, "disconnect", json!({})));
+    let messages = session(&requests);
+
+    assert_eq!(events(&messages, "stopped").len(), 5);
+    let pcs: Vec<&Value> = (0..5).map(|seq| &response(&messages, 10 + seq * 2)["body"]["stackFrames"][0]["instructionPointerReference"]).collect();
+    assert_eq!(pcs, ["0007", "0024", "0007", "0024", "0007"]);
+    assert_eq!(events(&messages, "output")[0]["body"]["output"], "2\n");
+}
+
+#[test]
+fn malformed_headers_are_protocol_errors() {
+    let output = spawn("Content-Type: application/json\r\n\r\n{}");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing Content-Length header"));
+
+    // 不按过大的长度分配内存
+    let output = spawn("Content-Length: 99999999999999\r\n\r\n{}");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("exceeds the limit"));
+
+    // 消息之间的空行可以忽略
+    let messages = session(&[format!("\r\n{}", request(1, "initialize", json!({}))), request(2, "disconnect", json!({}))]);
+    assert_eq!(response(&messages, 2)["success"], true);
+}
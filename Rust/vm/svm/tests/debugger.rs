@@ -0,0 +1,66 @@
+mod common;
+
+use common::vm_with;
+use std::io::Cursor;
+use svm::debugger::Debugger;
+use svm::vm::Vm;
+
+const PROGRAM: &str = "
+    push 3
+    call f, 1
+    push 0
+    exit
+f:
+    loadlocal 0
+    push 2
+    mul
+    storelocal 0
+    loadlocal 0
+    return
+";
+
+// 按顺序输入命令，返回调试器的全部输出
+fn debug(commands: &str) -> (Vm, String) {
+    let mut vm = vm_with(PROGRAM);
+    let mut output = Vec::new();
+    Debugger::new(Cursor::new(commands.to_string()), &mut output).run(&mut vm).unwrap();
+    (vm, String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn stops_before_first_instruction() {
+    let (vm, output) = debug("pc\nquit\n");
+    assert_eq!(vm.exit_code(), None);
+    assert_eq!(output, "0000  push 3                   ; line 2\n(svm) 0000  push 3                   ; line 2\n(svm) program stopped\n");
+}
+
+#[test]
+fn break_at_label_and_print_locals() {
+    let (vm, output) = debug("b f\nc\nlocals\nstack\nc\n");
+    assert_eq!(vm.exit_code(), Some(0));
+    assert!(output.contains("breakpoint set at 0010\n"));
+    assert!(output.contains("breakpoint at 0010\n0010  push 0"));
+    assert!(output.contains("(svm)    0: 3\n(svm)    0: 3\n"));
+    assert!(output.ends_with("program exited with code 0\n"));
+}
+
+#[test]
+fn step_executes_one_instruction() {
+    let (_, output) = debug("s\ns\nstack\nq\n");
+    assert!(output.contains("(svm) 0002  push 1"));
+    assert!(output.contains("(svm) 0004  push 10"));
+    assert!(output.contains("(svm)    0: 3\n   1: 1\n"));
+}
+
+#[test]
+fn watch_stops_when_slot_changes() {
+    let (_, output) = debug("b f\nc\nwatch 0\nc\nq\n");
+    assert!(output.contains("watching slot 0 = 3\n"));
+    assert!(output.contains("slot 0: 3 -> 6\n"));
+}
+
+#[test]
+fn unknown_breakpoint_location() {
+    let (_, output) = debug("b nowhere\nq\n");
+    assert!(output.contains("unknown address or label `nowhere`"));
+}
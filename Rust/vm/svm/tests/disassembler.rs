@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use svm::assembler::assemble;
+use svm::disassembler::disassemble;
+use svm::instruction::OpCode;
+use svm::program::Program;
+
+// 去掉每行开头的地址，得到可以再次汇编的文本
+fn source_of(program: &Program) -> String {
+    disassemble(program).lines()
+        .map(|line| if line.ends_with(':') { line.to_string() } else { line[6..].to_string() })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn jump_targets_are_labels() {
+    let program = assemble("
+        jmp end
+    start:
+        push 1
+        jmpifzero -2
+        call start, 0
+        push \"a\\\"b\"
+        loadlocal 0
+    end:
+        push 0
+        exit
+    ").unwrap();
+    assert_eq!(disassemble(&program), "\
+0000  jmp L17
+L3:
+0003  push 1
+0005  jmpifzero -2    ; L3
+0007  call L3, 0
+0012  pushconst \"a\\\"b\"
+0014  loadlocal 0
+L17:
+0017  push 0
+0019  exit
+");
+}
+
+#[test]
+fn invalid_bytes_are_shown() {
+    let program = Program::new(vec![0xff, OpCode::Push as u8], Vec::new());
+    assert_eq!(disassemble(&program), format!("0000  .byte 255    ; invalid opcode\n0001  .byte {}    ; truncated push\n", OpCode::Push as u8));
+}
+
+#[test]
+fn disassembly_assembles_to_the_same_program() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.extension().is_some_and(|ext| ext == "s" || ext == "svs") {
+            continue;
+        }
+        let program = Program::load(path.to_str().unwrap()).unwrap();
+        let again = assemble(&source_of(&program)).unwrap();
+        assert_eq!(again.codes, program.codes, "{}", path.display());
+        assert_eq!(again.constants, program.constants, "{}", path.display());
+    }
+}
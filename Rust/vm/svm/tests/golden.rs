@@ -0,0 +1,56 @@
+// 运行tests/golden下的每个.s和.svs程序，输出与同名的.out文件比较。
+// 同名的.in文件作为ReadLine的输入，运行出错时错误信息追加在输出最后。
+use std::fs;
+use std::path::{Path, PathBuf};
+use svm::program::Program;
+use svm::vm::Vm;
+use svm::vmio::{BufferIo, FileIo};
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn run(path: &Path, io: BufferIo) -> String {
+    let mut vm = Vm::new();
+    vm.import_program(&Program::load(path.to_str().unwrap()).unwrap());
+    vm.set_io(io.clone());
+    let result = vm.run();
+    let mut output = io.output();
+    if let Err(e) = result {
+        output += &format!("error: {}\n", e);
+    }
+    output
+}
+
+#[test]
+fn golden_outputs() {
+    let mut programs: Vec<PathBuf> = fs::read_dir(golden_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "s" || ext == "svs"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    let mut failures = Vec::new();
+    for program in &programs {
+        let input = fs::read_to_string(program.with_extension("in")).unwrap_or_default();
+        let expected = fs::read_to_string(program.with_extension("out")).unwrap();
+        let actual = run(program, BufferIo::new(&input));
+        if actual != expected {
+            failures.push(format!("{}:\n--- expected\n{}--- actual\n{}", program.display(), expected, actual));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn file_io_reads_and_writes_files() {
+    let program = golden_dir().join("echo.s");
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("echo.out");
+    let mut vm = Vm::new();
+    vm.import_program(&Program::load(program.to_str().unwrap()).unwrap());
+    vm.set_io(FileIo::open(Some(&program.with_extension("in")), &output).unwrap());
+    vm.run().unwrap();
+    drop(vm);
+    assert_eq!(fs::read_to_string(&output).unwrap(), fs::read_to_string(program.with_extension("out")).unwrap());
+}
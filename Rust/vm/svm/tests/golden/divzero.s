@@ -0,0 +1,7 @@
+; 运行时错误也写入输出
+push 1
+print
+push 1
+push 0
+div
+print
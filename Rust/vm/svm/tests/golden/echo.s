@@ -0,0 +1,7 @@
+; 读入两个数相加，再原样输出一行文本
+readline
+readline
+add
+print
+readline
+print
@@ -0,0 +1,23 @@
+; 递归计算阶乘
+    push 10
+    call fact, 1
+    print
+    push 0
+    exit
+fact:
+    loadlocal 0
+    push recurse
+    push base
+    if
+    jmp
+recurse:
+    loadlocal 0
+    loadlocal 0
+    push 1
+    sub
+    call fact, 1
+    mul
+    return
+base:
+    push 1
+    return
@@ -0,0 +1,15 @@
+; 各种类型的值
+push "hello, world"
+print
+push 1
+push 2
+add
+print
+push 2.5
+push 0.5
+mul
+print
+push true
+print
+push nil
+print
@@ -0,0 +1,49 @@
+; 用substring逐个取出字符，倒序拼接；数组可以包含自身
+    push "hello, world"
+    push ""
+    loadlocal 0
+    arraylen
+loop:
+    loadlocal 2
+    push body
+    push done
+    if
+    jmp
+body:
+    loadlocal 2
+    push 1
+    sub
+    storelocal 2
+    loadlocal 1
+    loadlocal 0
+    loadlocal 2
+    push 1
+    substring
+    concat
+    storelocal 1
+    jmp loop
+done:
+    loadlocal 1
+    print
+    push 3
+    newarray
+    loadlocal 3
+    push 0
+    push 1
+    arrayset
+    loadlocal 3
+    push 1
+    push "two"
+    arrayset
+    loadlocal 3
+    push 2
+    loadlocal 3
+    arrayset
+    loadlocal 3
+    print
+    loadlocal 3
+    arraylen
+    print
+    loadlocal 3
+    push 3
+    arrayget
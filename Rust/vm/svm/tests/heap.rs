@@ -0,0 +1,178 @@
+mod common;
+
+use common::vm_with_io;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::value::Value;
+use svm::vm::MAX_ARRAY_LEN;
+
+// 循环100次，每次把"ab"拼接到slot 0的字符串后面，旧的字符串和"ab"都成为垃圾
+const CONCAT_LOOP: &str = "
+    push \"\"
+    push 0
+loop:
+    loadlocal 0
+    push \"ab\"
+    concat
+    storelocal 0
+    loadlocal 1
+    push 1
+    add
+    storelocal 1
+    loadlocal 1
+    push 100
+    sub
+    push loop
+    push done
+    if
+    jmp
+done:
+    loadlocal 0
+    arraylen
+    print
+";
+
+#[test]
+fn arrays_and_strings() {
+    let (mut vm, io) = vm_with_io("
+        push 2
+        newarray
+        loadlocal 0
+        push 1
+        push \"abc\"
+        push \"def\"
+        concat
+        arrayset
+        loadlocal 0
+        push 1
+        arrayget
+        push 2
+        push 3
+        substring
+        print
+        loadlocal 0
+        arraylen
+        print
+        loadlocal 0
+        print
+    ");
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(io.output(), "cde\n2\n[nil, \"abcdef\"]\n");
+}
+
+#[test]
+fn stress_mode_collects_on_every_allocation() {
+    let (mut vm, io) = vm_with_io(CONCAT_LOOP);
+    vm.set_gc_stress(true);
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(io.output(), "200\n");
+    let stats = vm.gc_stats();
+    assert_eq!(stats.allocated, 201);
+    assert_eq!(stats.collections, 201);
+    // 最后一次拼接时上一个字符串还在slot 0中，结束时和最终的字符串一起留在堆上
+    assert_eq!(stats.objects, 2);
+    assert_eq!(stats.freed, 199);
+    assert_eq!(stats.bytes, 198 + 200);
+}
+
+#[test]
+fn garbage_does_not_count_against_heap_limit() {
+    let (mut vm, io) = vm_with_io(CONCAT_LOOP);
+    vm.set_limits(svm::limits::Limits { max_heap: Some(1000), ..Default::default() });
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(io.output(), "200\n");
+    assert!(vm.gc_stats().collections > 0);
+    assert!(vm.gc_stats().peak_bytes <= 1000 + 200);
+}
+
+#[test]
+fn objects_reachable_from_arrays_and_frames_survive() {
+    // 内层数组只能经由外层数组访问，函数的参数和局部变量在调用期间也是根
+    let (mut vm, io) = vm_with_io("
+        push 1
+        newarray
+        loadlocal 0
+        push 0
+        push 2
+        newarray
+        arrayset
+        loadlocal 0
+        push 0
+        arrayget
+        push 1
+        push \"x\"
+        push \"y\"
+        concat
+        arrayset
+        loadlocal 0
+        call show, 1
+        print
+        push 0
+        exit
+    show:
+        push \"!\"
+        push \"?\"
+        concat
+        loadlocal 0
+        push 0
+        arrayget
+        push 1
+        arrayget
+        print
+        loadlocal 0
+        print
+        return
+    ");
+    vm.set_gc_stress(true);
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(io.output(), "xy\n[[nil, \"xy\"]]\n!?\n");
+}
+
+#[test]
+fn unreachable_cycles_are_collected() {
+    let (mut vm, _) = vm_with_io("
+        push 1
+        newarray
+        loadlocal 0
+        push 0
+        loadlocal 0
+        arrayset
+        push 0
+        storelocal 0
+        push \"x\"
+    ");
+    vm.set_gc_stress(true);
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.gc_stats().freed, 1);
+    assert_eq!(vm.heap().export(&vm.stack()[1]), Value::from("x"));
+}
+
+#[test]
+fn index_and_type_errors() {
+    let (mut vm, _) = vm_with_io("push 2\nnewarray\npush -1\narrayget");
+    assert_eq!(vm.run(), Err(VmError::IndexOutOfRange { pc: 5, opcode: OpCode::ArrayGet, index: -1, len: 2 }));
+
+    let (mut vm, _) = vm_with_io("push -1\nnewarray");
+    assert_eq!(vm.run(), Err(VmError::InvalidLength { pc: 2, opcode: OpCode::NewArray, length: -1 }));
+
+    let (mut vm, _) = vm_with_io("push \"abc\"\npush 2\npush 2\nsubstring");
+    assert_eq!(vm.run(), Err(VmError::IndexOutOfRange { pc: 6, opcode: OpCode::Substring, index: 4, len: 3 }));
+
+    let (mut vm, _) = vm_with_io("push \"abc\"\npush 0\narrayget");
+    assert_eq!(vm.run(), Err(VmError::TypeMismatch { pc: 4, opcode: OpCode::ArrayGet, expected: "array", found: "string" }));
+
+    let (mut vm, _) = vm_with_io("push 1\nnewarray\npush \"a\"\nconcat");
+    assert_eq!(vm.run(), Err(VmError::TypeMismatch { pc: 5, opcode: OpCode::Concat, expected: "string", found: "array" }));
+}
+
+#[test]
+fn huge_lengths_are_rejected() {
+    for len in [i64::MAX, 100_000_000_000, MAX_ARRAY_LEN + 1] {
+        let (mut vm, _) = vm_with_io(&format!("push {}\nnewarray", len));
+        assert!(matches!(vm.run(), Err(VmError::InvalidLength { opcode: OpCode::NewArray, length, .. }) if length == len));
+        assert_eq!(vm.heap_used(), 0);
+    }
+
+    let (mut vm, _) = vm_with_io("push \"abc\"\npush 1\npush 9223372036854775807\nsubstring");
+    assert_eq!(vm.run(), Err(VmError::IndexOutOfRange { pc: 15, opcode: OpCode::Substring, index: i64::MAX, len: 3 }));
+}
@@ -0,0 +1,291 @@
+// 同一程序分别全部解释执行和打开jit执行，比较输出、退出码、栈和错误：cargo test --features jit
+#![cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+
+use std::fs;
+use std::path::Path;
+use svm::assembler::assemble;
+use svm::compiler::compile;
+use svm::error::VmError;
+use svm::limits::{Limit, Limits};
+use svm::natives::register_builtins;
+use svm::optimizer::{optimize, Pass};
+use svm::program::Program;
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+fn run(program: &Program, input: &str, threshold: Option<u32>, limits: Limits) -> (String, Vm) {
+    let mut vm = Vm::new();
+    vm.import_program(program);
+    vm.set_jit_threshold(threshold);
+    vm.set_limits(limits);
+    register_builtins(&mut vm);
+    let io = BufferIo::new(input);
+    vm.set_io(io.clone());
+    let result = vm.run();
+    let stack: Vec<String> = vm.stack().iter().map(|v| vm.heap().repr(v)).collect();
+    let mut output = io.output();
+    output += &format!("exit: {:?}\nstack: [{}]\n", vm.exit_code(), stack.join(", "));
+    if let Err(e) = result {
+        output += &format!("error: {}\nframes: {}\n", e, vm.frames().len());
+    }
+    (output, vm)
+}
+
+// 返回打开jit运行的Vm，阈值为1，第一次调用时就编译
+fn same_with(program: &Program, input: &str, limits: Limits) -> Vm {
+    let (expected, _) = run(program, input, None, limits);
+    let (actual, vm) = run(program, input, Some(1), limits);
+    assert_eq!(actual, expected);
+    vm
+}
+
+fn same(source: &str) -> Vm {
+    same_with(&compile(source).unwrap(), "", Limits::default())
+}
+
+#[test]
+fn golden_programs_match() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.extension().is_some_and(|ext| ext == "s" || ext == "svs") {
+            continue;
+        }
+        let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+        let program = Program::load(path.to_str().unwrap()).unwrap();
+        same_with(&program, &input, Limits::default());
+        same_with(&optimize(&program, &Pass::ALL).unwrap(), &input, Limits::default());
+    }
+}
+
+#[test]
+fn hot_integer_functions_are_compiled() {
+    let source = "
+        fn fib(n) {
+            if n < 2 { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fn greet(n) { print(n); return n; }
+        print(fib(20));
+        greet(1);
+    ";
+    let vm = same(source);
+    assert_eq!(vm.jit_functions().len(), 1);
+
+    // 调用次数没有达到阈值时不编译
+    let (_, vm) = run(&compile(source).unwrap(), "", Some(1_000_000), Limits::default());
+    assert!(vm.jit_functions().is_empty());
+}
+
+#[test]
+fn assembly_with_computed_jumps_and_locals() {
+    let source = "
+        push 10
+        call fib, 1
+        print
+        push 3
+        push 4
+        call sum, 2
+        print
+        push 5
+        call g, 1
+        print
+        jmp end
+    fib:
+        loadlocal 0
+        loadlocal 0
+        push 1
+        sub
+        mul
+        push rec
+        push base
+        if
+        jmp
+    rec:
+        loadlocal 0
+        push 1
+        sub
+        call fib, 1
+        loadlocal 0
+        push 2
+        sub
+        call fib, 1
+        add
+        return
+    base:
+        loadlocal 0
+        return
+    sum:
+        push 0
+        loadlocal 1
+        push 1
+        gt
+        not
+        jmpifnonzero done
+    loop:
+        loadlocal 2
+        loadlocal 0
+        add
+        storelocal 2
+        loadlocal 1
+        push 1
+        sub
+        storelocal 1
+        loadlocal 1
+        jmpifnonzero loop
+    done:
+        loadlocal 2
+        return
+    back:
+        loadlocal 0
+        push 1
+        add
+        return
+    g:
+        push back
+        jmp
+    end:
+        push 0
+        exit
+    ";
+    let (output, _) = run(&assemble(source).unwrap(), "", None, Limits::default());
+    assert!(output.starts_with("55\n12\n6\n"), "{}", output);
+    let vm = same_with(&assemble(source).unwrap(), "", Limits::default());
+    assert_eq!(vm.jit_functions().len(), 3);
+}
+
+#[test]
+fn errors_in_native_code_are_reported_by_the_interpreter() {
+    let overflow = "
+        fn fact(n) { if n < 2 { return 1; } return n * fact(n - 1); }
+        print(fact(20));
+        print(fact(21));
+    ";
+    let vm = same(overflow);
+    assert_eq!(vm.jit_functions().len(), 1);
+    same("fn div(a, b) { return a / b; } print(div(7, 2)); print(div(1, 0));");
+    same("fn div(a, b) { return a / b; } print(div(-9223372036854775807 - 1, -1));");
+    same("fn sub(a, b) { return a - b; } print(sub(-9223372036854775807, 2));");
+    same("fn neg(a) { return -a; } print(neg(5)); print(neg(-9223372036854775807 - 1));");
+}
+
+#[test]
+fn call_depth_limit() {
+    let source = "fn down(n) { if n == 0 { return 0; } return down(n - 1) + 1; } print(down(50)); print(down(5000));";
+    let program = compile(source).unwrap();
+    same_with(&program, "", Limits::default());
+    let vm = same_with(&program, "", Limits { max_call_depth: Some(60), ..Limits::default() });
+    assert_eq!(vm.frames().len(), 60);
+    // 没有调用深度限制时，超过MAX_NATIVE_DEPTH的部分由解释器执行
+    same_with(&program, "", Limits::unlimited());
+}
+
+#[test]
+fn unsupported_arguments_and_opcodes_fall_back() {
+    same("fn twice(x) { return x + x; } print(twice(2)); print(twice(1.5)); print(twice(3));");
+    same("fn pick(a) { return a > 1 && a < 5; } print(pick(3));");
+    same("fn len2(s) { return len(s) * 2; } print(len2(\"abc\"));");
+    same("fn count(a) { let n = 0; while n < 3 { a[n] = n; n = n + 1; } return n; } print(count(array(3)));");
+}
+
+#[test]
+fn fuel_and_trace_use_the_interpreter() {
+    let program = compile("fn inc(n) { return n + 1; } let i = 0; while i < 100 { i = inc(i); } print(i);").unwrap();
+    let limits = Limits { fuel: Some(400), ..Limits::default() };
+    let (expected, interpreted) = run(&program, "", None, limits);
+    let (actual, mut jitted) = run(&program, "", Some(1), limits);
+    assert_eq!(actual, expected);
+    assert_eq!(jitted.fuel_used(), interpreted.fuel_used());
+    assert!(jitted.jit_functions().is_empty());
+    assert!(matches!(jitted.run(), Err(VmError::LimitExceeded { limit: Limit::Fuel(400), .. })));
+}
+
+#[test]
+fn compiled_code_is_reused_across_runs() {
+    let mut vm = Vm::new();
+    vm.import_program(&compile("fn sq(n) { return n * n; } let i = 0; while i < 10 { i = i + sq(i) + 1; } print(i);").unwrap());
+    vm.set_jit_threshold(Some(1));
+    let io = BufferIo::new("");
+    vm.set_io(io.clone());
+    for _ in 0..3 {
+        vm.run().unwrap();
+        assert_eq!(io.take_output(), "13\n");
+        assert_eq!(vm.jit_functions().len(), 1);
+    }
+    vm.import_program(&assemble("push 1\ncall f, 0\nexit\nf:\npush 1\nloadlocal 0\nadd\nreturn").unwrap());
+    assert!(vm.jit_functions().is_empty());
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.exit_code(), Some(2));
+    assert_eq!(vm.jit_functions().len(), 1);
+}
+
+// 简单的伪随机数，测试结果可以重现
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+
+    // 由变量vars和整数常量组成的算术表达式
+    fn expr(&mut self, vars: &[&str], depth: u32) -> String {
+        if depth == 0 || self.below(3) == 0 {
+            return match self.below(vars.len() as u64 + 1) as usize {
+                i if i < vars.len() => vars[i].to_string(),
+                _ => (self.below(2000) as i64 - 1000).to_string(),
+            };
+        }
+        let op = ["+", "-", "*", "/"][self.below(4) as usize];
+        format!("({} {} {})", self.expr(vars, depth - 1), op, self.expr(vars, depth - 1))
+    }
+
+    fn condition(&mut self, vars: &[&str]) -> String {
+        let cmp = ["<", "<=", ">", ">=", "==", "!="][self.below(6) as usize];
+        let test = format!("{} {} {}", self.expr(vars, 2), cmp, self.expr(vars, 2));
+        match self.below(3) {
+            0 => format!("!({})", test),
+            1 => format!("{} && {} != 0", test, self.expr(vars, 1)),
+            _ => test,
+        }
+    }
+}
+
+#[test]
+fn random_integer_functions() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut compiled = 0;
+    for _ in 0..30 {
+        let source = format!(
+            "
+            fn g(a, b) {{
+                let x = {};
+                if {} {{ return {}; }}
+                while x > 0 && x < 50 {{ x = x * 2 + 1; }}
+                return x + {};
+            }}
+            fn f(a, b) {{ let x = 0; return g(a, {}) - g(b, a); }}
+            let i = -6;
+            while i < 6 {{
+                let j = -3;
+                while j < 3 {{ print(f(i * {}, j)); j = j + 1; }}
+                i = i + 1;
+            }}
+            ",
+            rng.expr(&["a", "b"], 3),
+            rng.condition(&["a", "b", "x"]),
+            rng.expr(&["a", "b", "x"], 2),
+            rng.expr(&["a", "b", "x"], 2),
+            rng.expr(&["a", "b", "x"], 2),
+            rng.below(100_000),
+        );
+        let program = compile(&source).unwrap();
+        if !same_with(&program, "", Limits::default()).jit_functions().is_empty() {
+            compiled += 1;
+        }
+        same_with(&optimize(&program, &Pass::ALL).unwrap(), "", Limits::default());
+    }
+    assert!(compiled > 25, "{}", compiled);
+}
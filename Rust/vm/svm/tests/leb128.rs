@@ -0,0 +1,77 @@
+use svm::leb128::{read_signed, read_unsigned, signed_len, unsigned_len, write_signed, write_unsigned};
+
+fn signed(value: i64) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_signed(&mut out, value);
+    out
+}
+
+fn unsigned(value: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_unsigned(&mut out, value);
+    out
+}
+
+#[test]
+fn signed_boundaries_round_trip() {
+    let cases: [(i64, &[u8]); 8] = [
+        (0, &[0x00]),
+        (-1, &[0x7f]),
+        (63, &[0x3f]),
+        (64, &[0xc0, 0x00]),
+        (-64, &[0x40]),
+        (-65, &[0xbf, 0x7f]),
+        (i64::MAX, &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]),
+        (i64::MIN, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f]),
+    ];
+    for (value, bytes) in cases {
+        assert_eq!(signed(value), bytes, "{}", value);
+        assert_eq!(signed_len(value), bytes.len(), "{}", value);
+        assert_eq!(read_signed(bytes, 0), Some((value, bytes.len())), "{}", value);
+    }
+    // 从中间位置读取
+    assert_eq!(read_signed(&[0x01, 0xbf, 0x7f], 1), Some((-65, 2)));
+}
+
+#[test]
+fn unsigned_boundaries_round_trip() {
+    for value in [0, 1, 127, 128, 16383, 16384, u32::MAX as u64, u64::MAX - 1, u64::MAX] {
+        let bytes = unsigned(value);
+        assert_eq!(unsigned_len(value), bytes.len(), "{}", value);
+        assert_eq!(read_unsigned(&bytes, 0), Some((value, bytes.len())), "{}", value);
+    }
+    assert_eq!(unsigned(u64::MAX).len(), 10);
+}
+
+#[test]
+fn lengths_match_encoding() {
+    let mut value = 1i64;
+    for _ in 0..63 {
+        for v in [value, value - 1, -value, -value - 1] {
+            assert_eq!(signed_len(v), signed(v).len(), "{}", v);
+            assert_eq!(unsigned_len(v as u64), unsigned(v as u64).len(), "{}", v);
+        }
+        value = value.wrapping_mul(2);
+    }
+}
+
+#[test]
+fn overlong_and_truncated_input_is_rejected() {
+    // 11个字节一定超出64位
+    let eleven = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+    assert_eq!(read_signed(&eleven, 0), None);
+    assert_eq!(read_unsigned(&eleven, 0), None);
+    // 第10个字节超出64位的部分与符号位不一致
+    let mut overflow = vec![0xff; 9];
+    overflow.push(0x01);
+    assert_eq!(read_signed(&overflow, 0), None);
+    overflow[9] = 0x7e;
+    assert_eq!(read_signed(&overflow, 0), None);
+    overflow[9] = 0x02;
+    assert_eq!(read_unsigned(&overflow, 0), None);
+    // 数据不完整
+    assert_eq!(read_signed(&[0x80, 0x80], 0), None);
+    assert_eq!(read_unsigned(&[0xff], 0), None);
+    assert_eq!(read_signed(&[], 0), None);
+    assert_eq!(read_signed(&[0x00], 2), None);
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::vm_with;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::limits::{Limit, Limits};
+use svm::value::Value;
+use svm::vm::Vm;
+
+fn limited(source: &str, limits: Limits) -> Vm {
+    let mut vm = vm_with(source);
+    vm.set_limits(limits);
+    vm
+}
+
+#[test]
+fn fuel_stops_infinite_loop() {
+    let mut vm = limited("loop:\njmp loop", Limits { fuel: Some(100), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 0, opcode: OpCode::Push, limit: Limit::Fuel(100) }));
+    assert_eq!(vm.fuel_used(), 100);
+}
+
+#[test]
+fn fuel_used_is_reported_after_normal_run() {
+    let mut vm = limited("push 1\npush 2\nadd", Limits { fuel: Some(3), ..Limits::default() });
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.stack(), &[Value::Int(3)]);
+    assert_eq!(vm.fuel_used(), 3);
+}
+
+#[test]
+fn stack_limit() {
+    let mut vm = limited("push 1\npush 2\npush 3", Limits { max_stack: Some(2), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 4, opcode: OpCode::Push, limit: Limit::Stack(2) }));
+}
+
+#[test]
+fn call_depth_limit() {
+    let mut vm = limited("call f\nexit\nf:\ncall f", Limits { max_call_depth: Some(8), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 10, opcode: OpCode::Call, limit: Limit::CallDepth(8) }));
+    assert_eq!(vm.frames().len(), 8);
+}
+
+#[test]
+fn heap_limit_counts_live_objects() {
+    // 两份"abcd"都是同一个对象，原字符串还在栈上，拼接结果在分配之前就超出限制
+    let source = "push \"abcd\"\nloadlocal 0\nloadlocal 0\nconcat";
+    let mut vm = limited(source, Limits { max_heap: Some(8), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 8, opcode: OpCode::Concat, limit: Limit::Heap(8) }));
+    assert_eq!(vm.heap_used(), 4);
+}
+
+#[test]
+fn heap_limit_is_checked_before_allocating() {
+    let mut vm = limited("push 1000000\nnewarray", Limits { max_heap: Some(1000), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 4, opcode: OpCode::NewArray, limit: Limit::Heap(1000) }));
+    assert_eq!(vm.heap_used(), 0);
+
+    // 字符串每次加倍，超出限制时堆上只有不超过限制的部分
+    let source = "push \"ab\"\nloop:\nloadlocal 0\nloadlocal 0\nconcat\nstorelocal 0\njmp loop";
+    let mut vm = limited(source, Limits { max_heap: Some(1000), ..Limits::default() });
+    assert!(matches!(vm.run(), Err(VmError::LimitExceeded { opcode: OpCode::Concat, limit: Limit::Heap(1000), .. })));
+    assert!(vm.heap_used() <= 1000, "{}", vm.heap_used());
+}
+
+#[test]
+fn garbage_is_collected_before_the_heap_limit() {
+    // 每轮的数组覆盖上一轮的，存活的不超过两个
+    let source = "push 0\nloop:\npush 10\nnewarray\nstorelocal 0\npush \"abc\"\npush \"def\"\nconcat\nstorelocal 0\njmp loop";
+    let mut vm = limited(source, Limits { fuel: Some(10_000), max_heap: Some(500), ..Limits::default() });
+    assert!(matches!(vm.run(), Err(VmError::LimitExceeded { limit: Limit::Fuel(10_000), .. })));
+    assert!(vm.gc_stats().collections > 0);
+}
+
+#[test]
+fn fuel_counts_allocating_instructions() {
+    let source = "push 3\nnewarray\npush \"ab\"\npush \"cd\"\nconcat\npush 0\npush 1\nsubstring";
+    let mut vm = limited(source, Limits { fuel: Some(8), ..Limits::default() });
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.fuel_used(), 8);
+    vm.set_limits(Limits { fuel: Some(4), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 7, opcode: OpCode::Concat, limit: Limit::Fuel(4) }));
+    assert_eq!(vm.stack().len(), 3);
+}
+
+#[test]
+fn unlimited_allows_deep_recursion() {
+    // f(n)递归到n为0，深度超过默认的调用深度限制
+    let source = "
+        push 2000
+        call f, 1
+        exit
+    f:
+        loadlocal 0
+        push recurse
+        push done
+        if
+        jmp
+    recurse:
+        loadlocal 0
+        push 1
+        sub
+        call f, 1
+        return
+    done:
+        push 7
+        return
+    ";
+    let mut vm = limited(source, Limits::default());
+    assert!(matches!(vm.run(), Err(VmError::LimitExceeded { limit: Limit::CallDepth(_), .. })));
+    vm.set_limits(Limits::unlimited());
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.exit_code(), Some(7));
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::vm_with;
+use svm::assembler::assemble;
+use svm::disassembler::disassemble;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::natives::register_builtins;
+use svm::value::Value;
+
+#[test]
+fn calls_registered_function() {
+    let mut vm = vm_with("push 7\npush 5\ncallnative sub, 2\npush \"x\"\ncallnative \"repeat\", 1");
+    vm.register_native("sub", 2, |args| match args {
+        [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a - b)),
+        _ => Err("expected ints".to_string()),
+    });
+    vm.register_native("repeat", 1, |args| Ok(Value::Str(args[0].to_string().repeat(3))));
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.heap().export(&vm.stack()[1]), Value::from("xxx"));
+    assert_eq!(vm.stack()[0], Value::Int(2));
+}
+
+#[test]
+fn host_errors_become_vm_errors() {
+    let mut vm = vm_with("push -4\ncallnative sqrt, 1");
+    register_builtins(&mut vm);
+    assert_eq!(vm.run(), Err(VmError::Native {
+        pc: 4,
+        opcode: OpCode::CallNative,
+        name: "sqrt".to_string(),
+        message: "square root of negative number -4".to_string(),
+    }));
+}
+
+#[test]
+fn unknown_native_and_wrong_arity() {
+    let mut vm = vm_with("push 1\ncallnative nope, 1");
+    assert_eq!(vm.run(), Err(VmError::UnknownNative { pc: 4, opcode: OpCode::CallNative, name: "nope".to_string() }));
+
+    let mut vm = vm_with("push 1\npush 2\ncallnative sqrt, 2");
+    register_builtins(&mut vm);
+    assert!(matches!(vm.run(), Err(VmError::Native { pc: 6, message, .. }) if message == "expected 1 arguments, found 2"));
+}
+
+#[test]
+fn builtins() {
+    let mut vm = vm_with("push 16\ncallnative sqrt, 1\npush -3\ncallnative abs, 1\npush 2.5\ncallnative floor, 1");
+    register_builtins(&mut vm);
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.stack(), &[Value::Float(4.0), Value::Int(3), Value::Float(2.0)]);
+}
+
+#[test]
+fn disassembly_reassembles() {
+    let program = assemble("push 2\ncallnative sqrt, 1\nprint").unwrap();
+    let text = disassemble(&program);
+    assert!(text.contains("callnative \"sqrt\", 1"));
+    let source: String = text.lines().map(|line| format!("{}\n", &line[6..])).collect();
+    assert_eq!(assemble(&source).unwrap().codes, program.codes);
+}
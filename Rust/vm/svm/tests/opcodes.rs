@@ -0,0 +1,264 @@
+mod common;
+
+use common::{run, vm_with};
+use svm::assembler::assemble;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::limits::{Limit, DEFAULT_MAX_CALL_DEPTH};
+use svm::value::Value;
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+// 跳过静态校验，用来检查运行时的错误处理
+fn unverified(source: &str) -> Vm {
+    let mut vm = vm_with(source);
+    vm.set_verify(false);
+    vm
+}
+
+// 栈上的值，堆上的字符串换成Value::Str以便比较
+fn exported(vm: &Vm) -> Vec<Value> {
+    vm.stack().iter().map(|v| vm.heap().export(v)).collect()
+}
+
+#[test]
+fn if_selects_by_condition() {
+    assert_eq!(run("push 1\npush 7\npush 9\nif").stack(), &[Value::Int(7)]);
+    assert_eq!(run("push 0\npush 7\npush 9\nif").stack(), &[Value::Int(9)]);
+}
+
+#[test]
+fn if_selects_jump_target() {
+    let vm = run("
+        push 0
+        push yes
+        push no
+        if
+        jmp
+    yes:
+        push 1
+        exit
+    no:
+        push 2
+        exit
+    ");
+    assert_eq!(vm.exit_code(), Some(2));
+}
+
+#[test]
+fn read_line_pushes_number() {
+    let mut vm = vm_with("readline\nreadline\nadd");
+    vm.set_io(BufferIo::new("3\n 4 \n"));
+    vm.run().unwrap();
+    assert_eq!(vm.stack(), &[Value::Int(7)]);
+}
+
+#[test]
+fn read_line_pushes_float_and_string() {
+    let mut vm = vm_with("readline\nreadline");
+    vm.set_io(BufferIo::new("2.5\nabc\n"));
+    vm.run().unwrap();
+    assert_eq!(exported(&vm), &[Value::Float(2.5), Value::Str("abc".to_string())]);
+}
+
+#[test]
+fn arithmetic_is_type_checked() {
+    let mut vm = vm_with("readline\npush 1\nadd");
+    vm.set_io(BufferIo::new("abc\n"));
+    assert_eq!(vm.run(), Err(VmError::TypeMismatch { pc: 3, opcode: OpCode::Add, expected: "number", found: "string" }));
+
+    let mut vm = vm_with("readline\npush 1\nadd");
+    vm.set_io(BufferIo::new("1.5\n"));
+    assert_eq!(vm.run(), Err(VmError::TypeMismatch { pc: 3, opcode: OpCode::Add, expected: "float", found: "int" }));
+}
+
+#[test]
+fn push_wide_immediates_and_constants() {
+    let vm = run("push -5\npush 1000000000000\npush 2.5\npush \"a b; c\"\npush true\npush nil\npushconst 7");
+    assert_eq!(exported(&vm), &[
+        Value::Int(-5),
+        Value::Int(1_000_000_000_000),
+        Value::Float(2.5),
+        Value::Str("a b; c".to_string()),
+        Value::Bool(true),
+        Value::Nil,
+        Value::Int(7),
+    ]);
+}
+
+#[test]
+fn invalid_bytes_are_rejected() {
+    let mut vm = Vm::new();
+    vm.set_verify(false);
+    vm.import_codes(&[OpCode::Push as u8, 0x80]);
+    assert_eq!(vm.run(), Err(VmError::TruncatedOperand { pc: 0, opcode: OpCode::Push }));
+    vm.import_codes(&[200]);
+    assert_eq!(vm.run(), Err(VmError::InvalidOpcode { pc: 0, byte: 200 }));
+    vm.import_codes(&[OpCode::PushConst as u8, 0]);
+    assert_eq!(vm.run(), Err(VmError::ConstantOutOfRange { pc: 0, opcode: OpCode::PushConst, index: 0 }));
+}
+
+#[test]
+fn integers_do_not_wrap() {
+    assert_eq!(run("push 200\npush 100\nadd").stack(), &[Value::Int(300)]);
+    assert_eq!(run("push 1\npush 2\nsub").stack(), &[Value::Int(-1)]);
+    let mut vm = vm_with("push 1\npush 0\ndiv");
+    assert_eq!(vm.run(), Err(VmError::DivisionByZero { pc: 4, opcode: OpCode::Div }));
+}
+
+#[test]
+fn read_line_at_end_of_input() {
+    let mut vm = vm_with("readline");
+    vm.set_io(BufferIo::new(""));
+    assert!(matches!(vm.run(), Err(VmError::Io { pc: 0, opcode: OpCode::ReadLine, .. })));
+}
+
+#[test]
+fn call_and_return() {
+    let vm = run("
+        push 5
+        call double, 1
+        push 0
+        exit
+    double:
+        loadlocal 0
+        push 2
+        mul
+        return
+    ");
+    assert_eq!(vm.stack(), &[Value::Int(10)]);
+    assert_eq!(vm.exit_code(), Some(0));
+    assert!(vm.frames().is_empty());
+}
+
+#[test]
+fn recursive_call() {
+    // fact(n) = n == 0 ? 1 : n * fact(n - 1)
+    let vm = run("
+        push 5
+        call fact, 1
+        exit
+    fact:
+        loadlocal 0
+        push recurse
+        push base
+        if
+        jmp
+    base:
+        push 1
+        return
+    recurse:
+        loadlocal 0
+        loadlocal 0
+        push 1
+        sub
+        call fact, 1
+        mul
+        return
+    ");
+    assert_eq!(vm.exit_code(), Some(120));
+}
+
+#[test]
+fn store_local() {
+    let vm = run("
+        push 1
+        push 2
+        push 9
+        storelocal 0
+        loadlocal 0
+        loadlocal 1
+        add
+    ");
+    assert_eq!(vm.stack(), &[Value::Int(9), Value::Int(2), Value::Int(11)]);
+}
+
+#[test]
+fn local_out_of_range() {
+    let mut vm = unverified("push 1\nloadlocal 1");
+    assert_eq!(vm.run(), Err(VmError::LocalOutOfRange { pc: 4, opcode: OpCode::LoadLocal, index: 1 }));
+}
+
+#[test]
+fn callee_cannot_pop_caller_stack() {
+    let mut vm = unverified("push 1\ncall f\nf:\nadd");
+    assert_eq!(vm.run(), Err(VmError::StackUnderflow { pc: 7, opcode: OpCode::Add }));
+    // 调用者留下的0不是除数
+    let mut vm = unverified("push 0\ncall f\nf:\ndiv");
+    assert_eq!(vm.run(), Err(VmError::StackUnderflow { pc: 7, opcode: OpCode::Div }));
+}
+
+#[test]
+fn deep_recursion_overflows() {
+    let mut vm = vm_with("call f\nexit\nf:\ncall f");
+    assert!(matches!(vm.run(), Err(VmError::LimitExceeded { pc: 10, opcode: OpCode::Call, limit: Limit::CallDepth(DEFAULT_MAX_CALL_DEPTH) })));
+}
+
+#[test]
+fn return_without_call() {
+    let mut vm = unverified("return");
+    assert_eq!(vm.run(), Err(VmError::CallStackUnderflow { pc: 0, opcode: OpCode::Return }));
+}
+
+#[test]
+fn call_out_of_range() {
+    let mut vm = unverified("call 100");
+    assert_eq!(vm.run(), Err(VmError::JumpOutOfRange { pc: 5, opcode: OpCode::Call, target: 100 }));
+}
+
+#[test]
+fn exit_stops_execution() {
+    let vm = run("push 3\nexit\npush 1");
+    assert_eq!(vm.exit_code(), Some(3));
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+#[allow(deprecated)]
+fn init_is_a_no_op() {
+    let mut vm = Vm::new();
+    vm.init();
+    vm.import_program(&assemble("push 4\nexit").unwrap());
+    vm.init();
+    vm.run().unwrap();
+    assert_eq!(vm.exit_code(), Some(4));
+}
+
+#[test]
+fn run_twice_starts_over() {
+    let mut vm = vm_with("push 4\nexit");
+    vm.run().unwrap();
+    vm.run().unwrap();
+    assert_eq!(vm.exit_code(), Some(4));
+    assert_eq!(vm.pc(), 3);
+}
+
+fn assert_send<T: Send>() {}
+
+// 每个Vm有自己的栈和pc，多个线程同时运行互不影响
+#[test]
+fn vms_run_in_parallel_threads() {
+    assert_send::<Vm>();
+    let threads: Vec<_> = (0..4).map(|i| {
+        std::thread::spawn(move || {
+            let mut vm = vm_with(&format!("
+                push 0
+            loop:
+                loadlocal 0
+                push 1
+                add
+                storelocal 0
+                loadlocal 0
+                push {}
+                lt
+                jmpifnonzero loop
+            ", 20000 + i));
+            vm.run().unwrap();
+            vm
+        })
+    }).collect();
+    for (i, thread) in threads.into_iter().enumerate() {
+        let vm = thread.join().unwrap();
+        assert_eq!(vm.stack(), &[Value::Int(20000 + i as i64)]);
+    }
+}
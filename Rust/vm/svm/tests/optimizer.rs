@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::Path;
+use svm::assembler::assemble;
+use svm::compiler::compile;
+use svm::disassembler::disassemble;
+use svm::optimizer::{optimize, Pass};
+use svm::program::Program;
+use svm::vm::Vm;
+use svm::vmio::BufferIo;
+
+fn optimized(source: &str, passes: &[Pass]) -> Program {
+    optimize(&assemble(source).unwrap(), passes).unwrap()
+}
+
+// 运行程序，返回输出和错误信息，错误信息去掉出错的地址以便与优化前比较
+fn output(program: &Program, input: &str) -> String {
+    let mut vm = Vm::new();
+    vm.import_program(program);
+    let io = BufferIo::new(input);
+    vm.set_io(io.clone());
+    let result = vm.run();
+    let mut output = io.output();
+    if let Err(e) = result {
+        let message = e.to_string();
+        output += message.rsplit_once(" at ").map_or(message.as_str(), |(message, _)| message);
+    }
+    output
+}
+
+#[test]
+fn folds_constant_arithmetic() {
+    assert_eq!(optimized("push 1\npush 2\nadd\nprint", &[Pass::Fold]).codes, assemble("push 3\nprint").unwrap().codes);
+    assert_eq!(optimized("push 2\npush 3\nmul\npush 4\nadd\nprint", &[Pass::Fold]).codes, assemble("push 10\nprint").unwrap().codes);
+    let program = optimized("push 1.5\npush 2.0\nmul\npush 1\npush 2\nlt\nnot", &[Pass::Fold]);
+    assert_eq!(disassemble(&program), "0000  pushconst 3.0\n0002  pushconst false\n");
+}
+
+#[test]
+fn leaves_runtime_errors_to_runtime() {
+    for source in ["push 1\npush 0\ndiv", "push 1\npush 1.0\nadd", "push 9223372036854775807\npush 1\nadd", "push \"a\"\npush 1\nlt"] {
+        let program = assemble(source).unwrap();
+        assert_eq!(optimize(&program, &[Pass::Fold]).unwrap().codes, program.codes, "{}", source);
+    }
+}
+
+#[test]
+fn folds_only_within_basic_blocks() {
+    // push 2是跳转目标，不能与前面的push 1合并；去掉永不跳转的jmpifnonzero后才能折叠
+    let source = "
+        push 1
+    l:
+        push 2
+        add
+        push 0
+        jmpifnonzero l
+        print
+    ";
+    let program = assemble(source).unwrap();
+    assert_eq!(optimize(&program, &[Pass::Fold]).unwrap().codes, program.codes);
+    assert_eq!(optimized(source, &[Pass::PushPop, Pass::Fold]).codes, assemble("push 3\nprint").unwrap().codes);
+}
+
+#[test]
+fn removes_dead_code() {
+    let source = "
+        jmp end
+        push 99
+        print
+    end:
+        push 7
+        print
+        push 0
+        exit
+        push 1
+        print
+    ";
+    let expected = assemble("jmp end\nend:\npush 7\nprint\npush 0\nexit").unwrap();
+    assert_eq!(optimized(source, &[Pass::DeadCode]).codes, expected.codes);
+}
+
+#[test]
+fn constant_conditions_become_jumps() {
+    let source = "
+        push 0
+        jmpifzero else
+        push 1
+        print
+    else:
+        push 2
+        print
+    ";
+    let program = optimized(source, &[Pass::Fold, Pass::DeadCode]);
+    assert_eq!(program.codes, assemble("jmp else\nelse:\npush 2\nprint").unwrap().codes);
+    assert_eq!(output(&program, ""), "2\n");
+}
+
+#[test]
+fn threads_jumps() {
+    let source = "
+        jmp a
+    a:
+        jmp b
+    b:
+        push 1
+        jmpifnonzero a
+    ";
+    let program = optimized(source, &[Pass::Thread, Pass::DeadCode]);
+    assert_eq!(program.codes, assemble("jmp b\nb:\npush 1\njmpifnonzero b").unwrap().codes);
+    // 跳转链成环时保持不变
+    let program = assemble("l:\njmp l").unwrap();
+    assert_eq!(optimize(&program, &[Pass::Thread]).unwrap().codes, program.codes);
+}
+
+#[test]
+fn drops_cancelling_push_pop_pairs() {
+    assert_eq!(optimized("push 5\nloadlocal 0\nstorelocal 0\nprint", &[Pass::PushPop]).codes, assemble("push 5\nprint").unwrap().codes);
+    let source = "
+        push 1
+        jmpifzero end
+        push 2
+        print
+    end:
+        push 3
+        print
+    ";
+    assert_eq!(optimized(source, &[Pass::PushPop]).codes, assemble("push 2\nprint\npush 3\nprint").unwrap().codes);
+}
+
+#[test]
+fn fixes_up_relative_targets() {
+    let source = "
+        push 3
+        push 2
+        push 3
+        mul
+        print
+        loadlocal 0
+        push 1
+        sub
+        storelocal 0
+        loadlocal 0
+        jmpifnonzero -18
+    ";
+    let program = optimized(source, &[Pass::Fold]);
+    assert!(disassemble(&program).contains("jmpifnonzero -15"), "{}", disassemble(&program));
+    assert_eq!(output(&program, ""), "6\n6\n6\n");
+}
+
+#[test]
+fn keeps_labels_and_lines() {
+    let program = compile("fn f(x) {\n    return x * (2 + 3);\n}\nprint(f(4));").unwrap();
+    let optimized = optimize(&program, &Pass::ALL).unwrap();
+    assert!(optimized.codes.len() < program.codes.len());
+    let entry = optimized.debug.label_addr("f").unwrap();
+    assert_eq!(optimized.debug.line_of(entry), program.debug.line_of(program.debug.label_addr("f").unwrap()));
+    assert_eq!(output(&optimized, ""), "20\n");
+}
+
+#[test]
+fn golden_outputs_are_unchanged() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let mut passes: Vec<Vec<Pass>> = Pass::ALL.iter().map(|&pass| vec![pass]).collect();
+    passes.push(Pass::ALL.to_vec());
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.extension().is_some_and(|ext| ext == "s" || ext == "svs") {
+            continue;
+        }
+        let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+        let program = Program::load(path.to_str().unwrap()).unwrap();
+        let expected = output(&program, &input);
+        for passes in &passes {
+            let optimized = optimize(&program, passes).unwrap();
+            assert_eq!(output(&optimized, &input), expected, "{} with {:?}", path.display(), passes);
+        }
+    }
+}
+
+#[test]
+fn script_with_constant_conditions() {
+    let source = "
+        let n = 0;
+        if 1 < 2 && !false { n = 10 * 4 + 2; } else { print(\"unreachable\"); }
+        while n > 40 { print(n); n = n - 1; }
+    ";
+    let program = compile(source).unwrap();
+    let optimized = optimize(&program, &Pass::ALL).unwrap();
+    assert!(!disassemble(&optimized).contains("unreachable"));
+    assert_eq!(output(&optimized, ""), output(&program, ""));
+    assert_eq!(output(&optimized, ""), "42\n41\n");
+}
+
+#[test]
+fn parses_pass_lists() {
+    assert_eq!(Pass::parse_list("fold, thread"), Ok(vec![Pass::Fold, Pass::Thread]));
+    assert_eq!(Pass::parse_list("all"), Ok(Pass::ALL.to_vec()));
+    assert_eq!(Pass::parse_list("fold,inline"), Err("unknown optimization pass `inline`".to_string()));
+}
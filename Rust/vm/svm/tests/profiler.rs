@@ -0,0 +1,117 @@
+mod common;
+
+use common::vm_with;
+use svm::assembler::assemble;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::limits::Limits;
+use svm::profiler::Profiler;
+use svm::program::Program;
+use svm::value::Value;
+use svm::vm::Vm;
+
+// 递归计算3!，exit之后的两行永远执行不到
+const PROGRAM: &str = "
+    push 3
+    call fact, 1
+    push 0
+    exit
+    push 99
+    print
+fact:
+    loadlocal 0
+    push recurse
+    push base
+    if
+    jmp
+recurse:
+    loadlocal 0
+    loadlocal 0
+    push 1
+    sub
+    call fact, 1
+    mul
+    return
+base:
+    push 1
+    return
+";
+
+fn profile() -> (Vm, Profiler) {
+    let mut vm = vm_with(PROGRAM);
+    let mut profiler = Profiler::new();
+    profiler.run(&mut vm).unwrap();
+    (vm, profiler)
+}
+
+#[test]
+fn counts_per_address_and_opcode() {
+    let (vm, profiler) = profile();
+    assert_eq!(vm.stack(), &[Value::Int(6)]);
+    let fact = vm.debug_info().label_addr("fact").unwrap();
+    assert_eq!(profiler.count(0), 1);
+    assert_eq!(profiler.count(fact), 4);
+    assert_eq!(profiler.opcode_count(OpCode::Call), 4);
+    assert_eq!(profiler.opcode_count(OpCode::Return), 4);
+    assert_eq!(profiler.opcode_count(OpCode::Mul), 3);
+    assert_eq!(profiler.opcode_count(OpCode::Print), 0);
+}
+
+#[test]
+fn times_each_function() {
+    let (vm, profiler) = profile();
+    let fact = vm.debug_info().label_addr("fact").unwrap();
+    assert_eq!(profiler.function(None).unwrap().calls, 1);
+    let stats = profiler.function(Some(fact)).unwrap();
+    assert_eq!(stats.calls, 4);
+    assert!(stats.time <= profiler.function(None).unwrap().time);
+}
+
+#[test]
+fn report_lists_hotspots() {
+    let (vm, profiler) = profile();
+    let report = profiler.report(vm.program());
+    assert!(report.contains("opcode"));
+    assert!(report.contains("fact"));
+    assert!(report.contains("main"));
+}
+
+#[test]
+fn lcov_marks_unreached_lines() {
+    let (vm, profiler) = profile();
+    let lcov = profiler.lcov(vm.program());
+    assert!(lcov.starts_with("TN:\nSF:\n"));
+    assert!(lcov.contains("DA:2,1\n"));
+    assert!(lcov.contains("DA:6,0\nDA:7,0\n"));
+    assert!(lcov.contains("DA:9,4\n"));
+    assert!(lcov.contains("LF:20\nLH:18\n"));
+    assert!(lcov.ends_with("end_of_record\n"));
+}
+
+#[test]
+fn lcov_without_debug_info_has_no_lines() {
+    let program = assemble(PROGRAM).unwrap();
+    let mut vm = Vm::new();
+    vm.import_program(&Program::new(program.codes, program.constants));
+    let mut profiler = Profiler::new();
+    profiler.run(&mut vm).unwrap();
+    let lcov = profiler.lcov(vm.program());
+    assert!(!lcov.contains("DA:"));
+    assert!(lcov.contains("LF:0\nLH:0\n"));
+}
+
+#[test]
+fn instruction_out_of_fuel_is_not_counted() {
+    // 只执行了第一条push，call前面的push因为耗尽fuel没有执行
+    let mut vm = vm_with(PROGRAM);
+    vm.set_limits(Limits { fuel: Some(1), ..Limits::default() });
+    let mut profiler = Profiler::new();
+    assert!(matches!(profiler.run(&mut vm), Err(VmError::LimitExceeded { pc: 2, .. })));
+    assert_eq!(profiler.count(0), 1);
+    assert_eq!(profiler.count(2), 0);
+    assert_eq!(profiler.opcode_count(OpCode::Push), 1);
+    let lcov = profiler.lcov(vm.program());
+    assert!(lcov.contains("DA:2,1\nDA:3,0\n"));
+    assert!(lcov.contains("LH:1\n"));
+    assert!(!profiler.report(vm.program()).contains("0002"));
+}
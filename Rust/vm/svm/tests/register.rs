@@ -0,0 +1,250 @@
+mod common;
+
+use common::vm_with;
+use std::fs;
+use std::path::Path;
+use svm::assembler::assemble;
+use svm::compiler::compile;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::limits::{Limit, Limits};
+use svm::natives::register_builtins;
+use svm::optimizer::{optimize, Pass};
+use svm::program::Program;
+use svm::register::{disassemble, translate, RegInstruction, RegOp, Src};
+use svm::vm::{Backend, Vm};
+use svm::vmio::BufferIo;
+
+// 运行程序，返回输出、错误、退出码和正常结束时的栈，用于比较两个后端。出错时两个后端栈上的内容不同
+fn run(program: &Program, input: &str, backend: Backend) -> (String, Vm) {
+    let mut vm = Vm::new();
+    vm.import_program(program);
+    vm.set_backend(backend);
+    // 比较的是解释执行的指令条数
+    #[cfg(feature = "jit")]
+    vm.set_jit_threshold(None);
+    register_builtins(&mut vm);
+    let io = BufferIo::new(input);
+    vm.set_io(io.clone());
+    let result = vm.run();
+    let mut output = io.output();
+    match result {
+        Ok(()) => {
+            let stack: Vec<String> = vm.stack().iter().map(|v| vm.heap().repr(v)).collect();
+            output += &format!("exit: {:?}\nstack: [{}]\n", vm.exit_code(), stack.join(", "));
+        }
+        Err(e) => output += &format!("error: {}\n", e),
+    }
+    (output, vm)
+}
+
+fn assert_same(program: &Program, input: &str) -> (Vm, Vm) {
+    let (expected, stack) = run(program, input, Backend::Stack);
+    let (actual, register) = run(program, input, Backend::Register);
+    assert_eq!(actual, expected, "\n{}", disassemble(&translate(program).unwrap()));
+    (stack, register)
+}
+
+fn same(source: &str) -> (Vm, Vm) {
+    assert_same(&assemble(source).unwrap(), "")
+}
+
+#[test]
+fn golden_programs_match() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !path.extension().is_some_and(|ext| ext == "s" || ext == "svs") {
+            continue;
+        }
+        let input = fs::read_to_string(path.with_extension("in")).unwrap_or_default();
+        let program = Program::load(path.to_str().unwrap()).unwrap();
+        assert_same(&program, &input);
+        assert_same(&optimize(&program, &Pass::ALL).unwrap(), &input);
+    }
+}
+
+#[test]
+fn locals_become_registers() {
+    let program = translate(&assemble("push 0\npush 5\nl:\nloadlocal 1\npush 1\nadd\nstorelocal 1\njmp l").unwrap()).unwrap();
+    let text = disassemble(&program);
+    assert!(text.contains("add          r1, r1, 1\n"), "{}", text);
+    assert_eq!(program.instructions().len(), 4, "{}", text);
+}
+
+#[test]
+fn fewer_instructions_for_the_same_result() {
+    let source = "
+        fn fib(n) {
+            if n < 2 { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        let i = 0;
+        while i < 15 { i = i + 1; }
+        print(fib(i));
+    ";
+    let (stack, register) = assert_same(&compile(source).unwrap(), "");
+    assert!(register.fuel_used() * 2 < stack.fuel_used(), "{} vs {}", register.fuel_used(), stack.fuel_used());
+}
+
+#[test]
+fn errors_report_the_stack_instruction() {
+    let (_, mut vm) = same("push 1\npush 2\nprint\npush 0\ndiv");
+    assert_eq!(vm.run(), Err(VmError::DivisionByZero { pc: 7, opcode: OpCode::Div }));
+    same("push 1\npush \"a\"\nlt");
+    same("push 2\nnewarray\npush 2\narrayget");
+    same("push \"abc\"\npush 1\npush 5\nsubstring");
+    same("push 1\npush 2\npush 3\npush 4\nadd\nadd\nadd\npush 9223372036854775807\nadd");
+    same("push 2.5\nneg\nprint\npush -9223372036854775807\npush 1\nsub\nneg");
+    same("push \"a\"\nneg");
+}
+
+#[test]
+fn exit_keeps_the_stack() {
+    let (_, vm) = same("push 7\npush 8\npush 1\npush f\ncall\nprint\npush 0\nexit\nf:\npush 9\nloadlocal 0\npush 2\nadd\nexit");
+    assert_eq!(vm.exit_code(), Some(10));
+    same("push 1\npush 2\npush 3\nstorelocal 0\nloadlocal 0");
+    same("push 1\npush 2\ncall f, 0\nf:\npush 5\nreturn");
+}
+
+#[test]
+fn dynamic_targets_and_locals() {
+    // 目标经过局部变量的jmp/call，以及下标不是常量的loadlocal/storelocal
+    let source = "
+        push f
+        push 1
+        jmpifnonzero start
+    start:
+        push 0
+        loadlocal 0
+        call
+        print
+        push 10
+        push 20
+        push 1
+        push 1
+        add
+        loadlocal
+        push 2
+        push 1
+        sub
+        storelocal
+        loadlocal 1
+        print
+        push 0
+        push a
+        push b
+        if
+        storelocal 2
+        loadlocal 2
+        jmp
+    a:
+        push 99
+        print
+    b:
+        push 42
+        print
+        push 0
+        exit
+    f:
+        push 5
+        return
+    ";
+    same(source);
+    let program = translate(&assemble(source).unwrap()).unwrap();
+    let text = disassemble(&program);
+    for mnemonic in ["callindirect", "jumpindirect", "loadlocal", "storelocal"] {
+        assert!(text.contains(mnemonic), "{}", text);
+    }
+}
+
+#[test]
+fn local_index_out_of_range() {
+    same("push 1\npush 2\npush 5\nloadlocal");
+    same("push 1\npush 2\npush 1\nstorelocal");
+}
+
+#[test]
+fn scripts_with_strings_arrays_and_natives() {
+    let source = "
+        fn fill(a, n) {
+            let i = 0;
+            while i < n { a[i] = i * i; i = i + 1; }
+            return a;
+        }
+        let a = fill(array(5), 5);
+        print(a[4] + len(a));
+        let s = concat(\"hello, \", readline());
+        print(substring(s, 0, len(s) - 1));
+        print(abs(-3) == 3 && s != \"x\");
+        print(sqrt(2.0) > 1.4 || nil);
+        let t = a[9];
+    ";
+    same_script(source, "world!\n");
+}
+
+fn same_script(source: &str, input: &str) {
+    let program = compile(source).unwrap();
+    assert_same(&program, input);
+    assert_same(&optimize(&program, &Pass::ALL).unwrap(), input);
+}
+
+#[test]
+fn recursion_limit() {
+    let mut vm = vm_with("call f, 0\nexit\nf:\ncall f, 0");
+    vm.set_backend(Backend::Register);
+    vm.set_limits(Limits { max_call_depth: Some(100), ..Limits::default() });
+    assert_eq!(vm.run(), Err(VmError::LimitExceeded { pc: 10, opcode: OpCode::Call, limit: Limit::CallDepth(100) }));
+    assert_eq!(vm.frames().len(), 100);
+}
+
+#[test]
+fn unverifiable_programs_are_rejected() {
+    let mut vm = vm_with("add");
+    vm.set_backend(Backend::Register);
+    vm.set_verify(false);
+    assert!(matches!(vm.run(), Err(VmError::Verify(_))));
+}
+
+#[test]
+fn instructions_round_trip() {
+    let ops = [
+        RegOp::Move { dst: 1, src: Src::Const(300) },
+        RegOp::Binary { opcode: OpCode::Ge, dst: 0, lhs: Src::Reg(2), rhs: Src::Const(1) },
+        RegOp::JumpIf { when: false, cond: Src::Reg(64), target: 1000 },
+        RegOp::CallNative { base: 3, argc: 2, name: 0 },
+        RegOp::Substring { dst: 4, string: Src::Reg(1), start: Src::Const(0), len: Src::Reg(3) },
+        RegOp::Halt { depth: 0 },
+    ];
+    let mut codes = Vec::new();
+    for op in ops {
+        RegInstruction::new(op).encode(&mut codes);
+    }
+    let mut pc = 0;
+    for op in ops {
+        let instruction = RegInstruction::decode(&codes, pc).unwrap();
+        assert_eq!(instruction, RegInstruction::new(op));
+        pc += instruction.len;
+    }
+    assert_eq!(pc, codes.len());
+    assert_eq!(RegInstruction::decode(&[200], 0), None);
+    assert_eq!(RegInstruction::decode(&codes[..2], 0), None);
+}
+
+#[test]
+fn stress_gc_on_register_backend() {
+    let source = "
+        let words = array(20);
+        let i = 0;
+        while i < 20 { words[i] = concat(\"w\", substring(\"0123456789\", i / 2, 1)); i = i + 1; }
+        print(words[19]);
+    ";
+    let mut vm = Vm::new();
+    vm.import_program(&compile(source).unwrap());
+    vm.set_backend(Backend::Register);
+    vm.set_gc_stress(true);
+    let io = BufferIo::new("");
+    vm.set_io(io.clone());
+    vm.run().unwrap();
+    assert_eq!(io.output(), "w9\n");
+}
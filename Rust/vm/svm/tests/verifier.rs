@@ -0,0 +1,115 @@
+mod common;
+
+use common::vm_with;
+use svm::assembler::assemble;
+use svm::error::VmError;
+use svm::instruction::OpCode;
+use svm::program::Program;
+use svm::verifier::{verify, VerifyError, VerifyErrorKind};
+
+fn check(source: &str) -> Result<(), VerifyError> {
+    verify(&assemble(source).unwrap()).map(|_| ())
+}
+
+fn error_kind(source: &str) -> VerifyErrorKind {
+    check(source).unwrap_err().kind
+}
+
+#[test]
+fn accepts_branches_calls_and_locals() {
+    check("
+        push 5
+        call fact, 1
+        exit
+    fact:
+        loadlocal 0
+        push recurse
+        push base
+        if
+        jmp
+    base:
+        push 1
+        return
+    recurse:
+        loadlocal 0
+        loadlocal 0
+        push 1
+        sub
+        call fact, 1
+        mul
+        return
+    ").unwrap();
+}
+
+#[test]
+fn rejects_invalid_bytes() {
+    let err = verify(&Program::new(vec![OpCode::Push as u8, 1, 200], Vec::new())).unwrap_err();
+    assert_eq!(err, VerifyError { pc: 2, opcode: None, kind: VerifyErrorKind::InvalidOpcode(200) });
+    let err = verify(&Program::new(vec![OpCode::PushConst as u8, 0], Vec::new())).unwrap_err();
+    assert_eq!(err.kind, VerifyErrorKind::ConstantOutOfRange(0));
+}
+
+#[test]
+fn rejects_underflow_on_any_path() {
+    assert_eq!(error_kind("push 1\nadd"), VerifyErrorKind::StackUnderflow { needed: 2, depth: 1 });
+    // 只有readline返回0时才会走到出错的分支
+    assert_eq!(
+        error_kind("readline\npush ok\npush bad\nif\njmp\nok:\nexit\nbad:\nprint\nprint"),
+        VerifyErrorKind::StackUnderflow { needed: 1, depth: 0 },
+    );
+    // 函数不能使用调用者栈上的数据
+    assert_eq!(error_kind("push 1\ncall f\nexit\nf:\nadd"), VerifyErrorKind::StackUnderflow { needed: 2, depth: 0 });
+}
+
+#[test]
+fn rejects_inconsistent_depth_at_merge() {
+    let err = check("readline\npush a\npush b\nif\njmp\na:\npush 1\nb:\npush 0\nexit").unwrap_err();
+    assert_eq!(err.kind, VerifyErrorKind::DepthMismatch { expected: 0, found: 1 });
+}
+
+#[test]
+fn rejects_bad_jump_targets() {
+    assert_eq!(error_kind("readline\njmp"), VerifyErrorKind::UnknownTarget);
+    assert_eq!(error_kind("jmp 100"), VerifyErrorKind::TargetOutOfRange(100));
+    assert_eq!(error_kind("push 1000\njmp 1"), VerifyErrorKind::TargetNotOnBoundary(1));
+}
+
+#[test]
+fn rejects_bad_calls_and_locals() {
+    assert_eq!(error_kind("readline\npush f\ncall\nf:\npush 0\nreturn"), VerifyErrorKind::UnknownArgCount);
+    assert_eq!(
+        error_kind("push 1\ncall f, 1\npush 1\npush 2\ncall f, 2\nexit\nf:\npush 0\nreturn"),
+        VerifyErrorKind::ArgCountMismatch { expected: 1, found: 2 },
+    );
+    assert_eq!(error_kind("push 1\nloadlocal 1"), VerifyErrorKind::LocalOutOfRange(1));
+    assert_eq!(error_kind("push 1\nreturn"), VerifyErrorKind::ReturnOutsideFunction);
+}
+
+#[test]
+fn rejects_main_code_running_into_a_function() {
+    // 调用返回后主程序接着执行到f，f中的return在顶层执行
+    let err = check("push 1\ncall f, 1\nf:\nloadlocal 0\nreturn").unwrap_err();
+    assert_eq!(err.kind, VerifyErrorKind::MixedFunctionCode);
+    assert_eq!(err.to_string(), "code is reachable both from main code and from a function at 0006 (call)");
+    // 主程序跳进函数体
+    assert_eq!(error_kind("push 1\ncall f, 1\njmp body\nf:\nloadlocal 0\nstorelocal 0\nbody:\npush 2\nreturn"), VerifyErrorKind::MixedFunctionCode);
+    assert!(check("push 1\ncall f, 1\nexit\nf:\nloadlocal 0\nreturn").is_ok());
+}
+
+#[test]
+fn run_rejects_unverified_program() {
+    let mut vm = vm_with("push 1\nprint\nadd");
+    let err = vm.run().unwrap_err();
+    assert!(matches!(err, VmError::Verify(VerifyError { pc: 3, opcode: Some(OpCode::Add), .. })));
+    // 校验失败时不执行任何指令
+    assert_eq!(vm.pc(), 0);
+}
+
+#[test]
+fn rejects_bad_native_calls() {
+    let program = Program::new(vec![OpCode::ReadLine as u8, OpCode::CallNative as u8, 0], vec!["sqrt".into()]);
+    assert_eq!(verify(&program).unwrap_err().kind, VerifyErrorKind::UnknownArgCount);
+    assert_eq!(error_kind("push 1\ncallnative sqrt, 2"), VerifyErrorKind::StackUnderflow { needed: 3, depth: 2 });
+    let program = Program::new(vec![OpCode::Push as u8, 0, OpCode::CallNative as u8, 0], vec![1.5.into()]);
+    assert_eq!(verify(&program).unwrap_err().kind, VerifyErrorKind::NativeNameNotString(0));
+}